@@ -0,0 +1,164 @@
+//! BVH queries against the naive loop over every item.
+//!
+//! `caddis` is a binary crate, so the modules under test are compiled into
+//! the benchmark directly.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+#[allow(warnings, clippy::all)]
+#[path = "../src/bvh.rs"]
+mod bvh;
+#[allow(warnings, clippy::all)]
+#[path = "../src/geometry.rs"]
+mod geometry;
+#[allow(warnings, clippy::all)]
+#[path = "../src/scene.rs"]
+mod scene;
+#[allow(warnings, clippy::all)]
+#[path = "../src/sdf.rs"]
+mod sdf;
+
+use bvh::{Bounded, Bvh};
+use geometry::{Sphere, Vector};
+use sdf::{sphere_trace, SignedDistance};
+
+type Scalar = f64;
+
+/// `n` spheres scattered deterministically through a 100 m cube.
+fn spheres(n: usize, seed: u64) -> Vec<Sphere> {
+    let mut seed = seed;
+    let mut rand = || {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 11) as Scalar / (1u64 << 53) as Scalar
+    };
+    (0..n)
+        .map(|_| {
+            let c = Vector::from_slice(&[100.0 * rand(), 100.0 * rand(), 100.0 * rand()]);
+            Sphere::from_radius(&c, 0.5 + rand())
+        })
+        .collect()
+}
+
+fn points(n: usize) -> Vec<Vector> {
+    spheres(n, 54321).iter().map(|s| s.center()).collect()
+}
+
+fn contains(c: &mut Criterion) {
+    let mut group = c.benchmark_group("contains");
+    let queries = points(100);
+    for &n in [100, 1_000, 10_000].iter() {
+        let bvh = Bvh::new(spheres(n, 12345));
+        group.bench_with_input(BenchmarkId::new("bvh", n), &bvh, |b, bvh| {
+            b.iter(|| {
+                for p in &queries {
+                    black_box(bvh.contains(p));
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("naive", n), &bvh, |b, bvh| {
+            b.iter(|| {
+                for p in &queries {
+                    black_box(
+                        (0..bvh.len())
+                            .filter(|&j| SignedDistance::contains(&bvh.items()[j], p))
+                            .collect::<Vec<_>>(),
+                    );
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn ray_cast(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ray_cast");
+    let origins = points(100);
+    let direction = Vector::from_slice(&[1.0, 0.3, -0.2]);
+    for &n in [100, 1_000, 10_000].iter() {
+        let bvh = Bvh::new(spheres(n, 12345));
+        group.bench_with_input(BenchmarkId::new("bvh", n), &bvh, |b, bvh| {
+            b.iter(|| {
+                for o in &origins {
+                    black_box(bvh.ray_cast(o, &direction, 1e3));
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("naive", n), &bvh, |b, bvh| {
+            b.iter(|| {
+                for o in &origins {
+                    black_box(
+                        (0..bvh.len())
+                            .filter_map(|j| {
+                                sphere_trace(&bvh.items()[j], o, &direction, 1e3).map(|t| (j, t))
+                            })
+                            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap()),
+                    );
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn nearest(c: &mut Criterion) {
+    let mut group = c.benchmark_group("nearest");
+    let queries = points(100);
+    for &n in [100, 1_000, 10_000].iter() {
+        let bvh = Bvh::new(spheres(n, 12345));
+        group.bench_with_input(BenchmarkId::new("bvh", n), &bvh, |b, bvh| {
+            b.iter(|| {
+                for p in &queries {
+                    black_box(bvh.nearest(p));
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("naive", n), &bvh, |b, bvh| {
+            b.iter(|| {
+                for p in &queries {
+                    black_box(
+                        bvh.items()
+                            .iter()
+                            .enumerate()
+                            .map(|(j, s)| (j, s.distance(p)))
+                            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap()),
+                    );
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn overlaps(c: &mut Criterion) {
+    let mut group = c.benchmark_group("overlapping_pairs");
+    for &n in [100, 1_000, 10_000].iter() {
+        let bvh = Bvh::new(spheres(n, 12345));
+        group.bench_with_input(BenchmarkId::new("bvh", n), &bvh, |b, bvh| {
+            b.iter(|| black_box(bvh.overlapping_pairs()))
+        });
+        // The quadratic loop is skipped where it would take minutes.
+        if n > 1_000 {
+            continue;
+        }
+        group.bench_with_input(BenchmarkId::new("naive", n), &bvh, |b, bvh| {
+            b.iter(|| {
+                let items = bvh.items();
+                let mut pairs = Vec::new();
+                for i in 0..items.len() {
+                    for j in i + 1..items.len() {
+                        if items[i].aabb().overlaps(&items[j].aabb()) {
+                            pairs.push((i, j));
+                        }
+                    }
+                }
+                black_box(pairs)
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, contains, ray_cast, nearest, overlaps);
+criterion_main!(benches);
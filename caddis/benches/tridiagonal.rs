@@ -0,0 +1,73 @@
+//! Block Thomas elimination of Henyey-shaped systems, a 2 x 2 centre block
+//! and 4 x 4 blocks for every further shell, against dense LU.
+//!
+//! A solve should stay linear in the number of shells and take milliseconds
+//! for thousands of them.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+#[allow(warnings, clippy::all)]
+#[path = "../src/tridiagonal.rs"]
+mod tridiagonal;
+
+use tridiagonal::BlockTridiagonal;
+
+type Scalar = f64;
+
+/// Diagonally weighted pseudo-random system for `shells` shells and its
+/// right-hand side.
+fn system(shells: usize) -> (BlockTridiagonal, Vec<Scalar>) {
+    let mut sizes = vec![4; shells];
+    sizes[0] = 2;
+    let mut m = BlockTridiagonal::new(sizes);
+    let n = m.len();
+    let mut seed = 12345u64;
+    let mut random = || {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+        (seed >> 11) as Scalar / (1u64 << 53) as Scalar - 0.5
+    };
+    for row in 0..n {
+        // The band reaches at most one block, of at most four columns, to
+        // either side.
+        for col in row.saturating_sub(7)..(row + 8).min(n) {
+            let diagonal = if row == col { 3.0 } else { 0.0 };
+            if m.get(row, col) == 0.0
+                && block_of(row) + 1 >= block_of(col)
+                && block_of(col) + 1 >= block_of(row)
+            {
+                m.set(row, col, random() + diagonal);
+            }
+        }
+    }
+    let b = (0..n).map(|i| i as Scalar - 4.0).collect();
+    (m, b)
+}
+
+/// Block of row or column `i` for a 2 x 2 first block and 4 x 4 blocks after.
+fn block_of(i: usize) -> usize {
+    if i < 2 {
+        0
+    } else {
+        (i - 2) / 4 + 1
+    }
+}
+
+fn solve(c: &mut Criterion) {
+    let mut group = c.benchmark_group("solve");
+    for &shells in &[100, 1000, 5000] {
+        let (m, b) = system(shells);
+        group.bench_with_input(BenchmarkId::new("block", shells), &shells, |bench, _| {
+            bench.iter(|| m.solve(black_box(&b)).unwrap())
+        });
+        // Dense LU is cubic; 1000 shells already take seconds.
+        if shells <= 100 {
+            group.bench_with_input(BenchmarkId::new("dense", shells), &shells, |bench, _| {
+                bench.iter(|| m.solve_dense(black_box(&b)).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, solve);
+criterion_main!(benches);
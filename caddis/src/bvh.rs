@@ -0,0 +1,590 @@
+//! Axis-aligned bounding boxes and a bounding volume hierarchy for spatial
+//! queries over many primitives.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::geometry::{Cuboid, Cylinder, NormedSpace, Sphere, Vector};
+use crate::scene::{Node, Primitive, Scene};
+use crate::sdf::{sphere_trace, Difference, Intersection, SignedDistance, Union};
+
+type Scalar = f64;
+
+const LEAF_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: [Scalar; 3],
+    pub max: [Scalar; 3],
+}
+
+impl Aabb {
+    pub fn new(min: [Scalar; 3], max: [Scalar; 3]) -> Self {
+        Aabb { min, max }
+    }
+
+    /// The empty box, identity for `union`.
+    pub fn empty() -> Self {
+        Aabb {
+            min: [Scalar::INFINITY; 3],
+            max: [Scalar::NEG_INFINITY; 3],
+        }
+    }
+
+    pub fn from_center(center: &Vector, half_extents: [Scalar; 3]) -> Self {
+        let mut b = Aabb::empty();
+        for i in 0..3 {
+            b.min[i] = center[i] - half_extents[i];
+            b.max[i] = center[i] + half_extents[i];
+        }
+        b
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..3).any(|i| self.min[i] > self.max[i])
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        let mut b = *self;
+        for i in 0..3 {
+            b.min[i] = b.min[i].min(other.min[i]);
+            b.max[i] = b.max[i].max(other.max[i]);
+        }
+        b
+    }
+
+    pub fn intersection(&self, other: &Self) -> Self {
+        let mut b = *self;
+        for i in 0..3 {
+            b.min[i] = b.min[i].max(other.min[i]);
+            b.max[i] = b.max[i].min(other.max[i]);
+        }
+        b
+    }
+
+    pub fn grow(&mut self, point: &[Scalar; 3]) {
+        for (i, x) in point.iter().enumerate() {
+            self.min[i] = self.min[i].min(*x);
+            self.max[i] = self.max[i].max(*x);
+        }
+    }
+
+    pub fn center(&self) -> [Scalar; 3] {
+        let mut c = [0.0; 3];
+        for (i, ci) in c.iter_mut().enumerate() {
+            *ci = 0.5 * (self.min[i] + self.max[i]);
+        }
+        c
+    }
+
+    pub fn corners(&self) -> [[Scalar; 3]; 8] {
+        let mut c = [[0.0; 3]; 8];
+        for (k, ck) in c.iter_mut().enumerate() {
+            for (i, ci) in ck.iter_mut().enumerate() {
+                *ci = if k & (1 << i) == 0 {
+                    self.min[i]
+                } else {
+                    self.max[i]
+                };
+            }
+        }
+        c
+    }
+
+    pub fn contains(&self, point: &Vector) -> bool {
+        (0..3).all(|i| self.min[i] <= point[i] && point[i] <= self.max[i])
+    }
+
+    pub fn overlaps(&self, other: &Self) -> bool {
+        (0..3).all(|i| self.min[i] <= other.max[i] && other.min[i] <= self.max[i])
+    }
+
+    /// Euclidean distance from `point` to the box, zero inside.
+    pub fn distance(&self, point: &Vector) -> Scalar {
+        (0..3)
+            .map(|i| {
+                (self.min[i] - point[i])
+                    .max(point[i] - self.max[i])
+                    .max(0.0)
+                    .powi(2)
+            })
+            .sum::<Scalar>()
+            .sqrt()
+    }
+
+    /// Parameter interval `[t0, t1]` over which the ray `origin + t direction`
+    /// lies inside the box, clipped to `[0, t_max]`.
+    pub fn ray_interval(
+        &self,
+        origin: &Vector,
+        direction: &Vector,
+        t_max: Scalar,
+    ) -> Option<(Scalar, Scalar)> {
+        let mut t0: Scalar = 0.0;
+        let mut t1 = t_max;
+        for i in 0..3 {
+            let inv = direction[i].recip();
+            let mut near = (self.min[i] - origin[i]) * inv;
+            let mut far = (self.max[i] - origin[i]) * inv;
+            if near > far {
+                std::mem::swap(&mut near, &mut far);
+            }
+            // NaN arises for a ray lying in a slab face; treat it as inside.
+            if !near.is_nan() {
+                t0 = t0.max(near);
+            }
+            if !far.is_nan() {
+                t1 = t1.min(far);
+            }
+            if t0 > t1 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+}
+
+pub trait Bounded {
+    fn aabb(&self) -> Aabb;
+}
+
+impl Bounded for Sphere {
+    fn aabb(&self) -> Aabb {
+        Aabb::from_center(&self.center(), [self.radius(); 3])
+    }
+}
+
+impl Bounded for Cylinder {
+    fn aabb(&self) -> Aabb {
+        let mut e = [0.0; 3];
+        for (i, ei) in e.iter_mut().enumerate() {
+            let a = self.axis[i];
+            *ei = self.radius * (1.0 - a * a).max(0.0).sqrt() + 0.5 * self.length * a.abs();
+        }
+        Aabb::from_center(&self.center, e)
+    }
+}
+
+impl Bounded for Cuboid {
+    fn aabb(&self) -> Aabb {
+        let h = &self.half_extents;
+        Aabb::from_center(&self.center, [h[0], h[1], h[2]])
+    }
+}
+
+impl Bounded for Primitive {
+    fn aabb(&self) -> Aabb {
+        match self {
+            Primitive::Sphere(s) => s.aabb(),
+            Primitive::Cylinder(c) => c.aabb(),
+            Primitive::Cuboid(b) => b.aabb(),
+        }
+    }
+}
+
+impl Bounded for Node {
+    fn aabb(&self) -> Aabb {
+        let mut b = Aabb::empty();
+        for c in self.primitive.aabb().corners().iter() {
+            let p = self.transform.apply(&Vector::from_slice(c));
+            b.grow(&[p[0], p[1], p[2]]);
+        }
+        b
+    }
+}
+
+impl Bounded for Scene {
+    fn aabb(&self) -> Aabb {
+        self.nodes
+            .iter()
+            .fold(Aabb::empty(), |b, n| b.union(&n.aabb()))
+    }
+}
+
+impl<A: Bounded, B: Bounded> Bounded for Union<A, B> {
+    fn aabb(&self) -> Aabb {
+        self.0.aabb().union(&self.1.aabb())
+    }
+}
+
+impl<A: Bounded, B: Bounded> Bounded for Intersection<A, B> {
+    fn aabb(&self) -> Aabb {
+        self.0.aabb().intersection(&self.1.aabb())
+    }
+}
+
+impl<A: Bounded, B> Bounded for Difference<A, B> {
+    fn aabb(&self) -> Aabb {
+        self.0.aabb()
+    }
+}
+
+enum BvhNode {
+    Leaf {
+        aabb: Aabb,
+        start: usize,
+        end: usize,
+    },
+    Branch {
+        aabb: Aabb,
+        left: usize,
+        right: usize,
+    },
+}
+
+impl BvhNode {
+    fn aabb(&self) -> &Aabb {
+        match self {
+            BvhNode::Leaf { aabb, .. } | BvhNode::Branch { aabb, .. } => aabb,
+        }
+    }
+}
+
+/// Bounding volume hierarchy over a set of items, split at the median
+/// centroid along the longest axis. Query results refer to items by their
+/// index in the vector passed to `Bvh::new`.
+pub struct Bvh<T> {
+    items: Vec<T>,
+    boxes: Vec<Aabb>,
+    order: Vec<usize>,
+    nodes: Vec<BvhNode>,
+}
+
+impl<T: Bounded + SignedDistance> Bvh<T> {
+    pub fn new(items: Vec<T>) -> Self {
+        let boxes: Vec<Aabb> = items.iter().map(|i| i.aabb()).collect();
+        let order = (0..items.len()).collect();
+        let mut bvh = Bvh {
+            items,
+            boxes,
+            order,
+            nodes: Vec::new(),
+        };
+        if !bvh.items.is_empty() {
+            bvh.build(0, bvh.items.len());
+        }
+        bvh
+    }
+
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    fn build(&mut self, start: usize, end: usize) -> usize {
+        let aabb = self.order[start..end]
+            .iter()
+            .fold(Aabb::empty(), |b, &i| b.union(&self.boxes[i]));
+        let index = self.nodes.len();
+        if end - start <= LEAF_SIZE {
+            self.nodes.push(BvhNode::Leaf { aabb, start, end });
+            return index;
+        }
+        let mut centroids = Aabb::empty();
+        for &i in &self.order[start..end] {
+            centroids.grow(&self.boxes[i].center());
+        }
+        let axis = (0..3)
+            .max_by(|&a, &b| {
+                let da = centroids.max[a] - centroids.min[a];
+                let db = centroids.max[b] - centroids.min[b];
+                da.partial_cmp(&db).unwrap_or(Ordering::Equal)
+            })
+            .unwrap();
+        let boxes = &self.boxes;
+        let mid = (start + end) / 2;
+        self.order[start..end].select_nth_unstable_by(mid - start, |&a, &b| {
+            let ca = boxes[a].center()[axis];
+            let cb = boxes[b].center()[axis];
+            ca.partial_cmp(&cb).unwrap_or(Ordering::Equal)
+        });
+        // Reserve this node's slot before the children are pushed.
+        self.nodes.push(BvhNode::Leaf { aabb, start, end });
+        let left = self.build(start, mid);
+        let right = self.build(mid, end);
+        self.nodes[index] = BvhNode::Branch { aabb, left, right };
+        index
+    }
+
+    /// Visits every leaf item whose box passes `test`, pruning subtrees
+    /// whose boxes fail it.
+    fn visit<P: Fn(&Aabb) -> bool, F: FnMut(usize)>(&self, test: P, mut f: F) {
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(n) = stack.pop() {
+            if !test(self.nodes[n].aabb()) {
+                continue;
+            }
+            match self.nodes[n] {
+                BvhNode::Leaf { start, end, .. } => {
+                    for &i in &self.order[start..end] {
+                        if test(&self.boxes[i]) {
+                            f(i);
+                        }
+                    }
+                }
+                BvhNode::Branch { left, right, .. } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+    }
+
+    /// Indices of the items containing `point`.
+    pub fn contains(&self, point: &Vector) -> Vec<usize> {
+        let mut hits = Vec::new();
+        self.visit(
+            |b| b.contains(point),
+            |i| {
+                if self.items[i].contains(point) {
+                    hits.push(i);
+                }
+            },
+        );
+        hits.sort_unstable();
+        hits
+    }
+
+    /// Indices of the items whose bounding boxes overlap `aabb`.
+    pub fn overlaps(&self, aabb: &Aabb) -> Vec<usize> {
+        let mut hits = Vec::new();
+        self.visit(|b| b.overlaps(aabb), |i| hits.push(i));
+        hits.sort_unstable();
+        hits
+    }
+
+    /// All pairs `(i, j)`, `i < j`, of items with overlapping bounding boxes.
+    pub fn overlapping_pairs(&self) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        for i in 0..self.items.len() {
+            for j in self.overlaps(&self.boxes[i]) {
+                if i < j {
+                    pairs.push((i, j));
+                }
+            }
+        }
+        pairs
+    }
+
+    /// First item hit by the ray `origin + t direction`, `0 <= t <= t_max`,
+    /// and the distance along the (normalised) direction to the hit.
+    pub fn ray_cast(
+        &self,
+        origin: &Vector,
+        direction: &Vector,
+        t_max: Scalar,
+    ) -> Option<(usize, Scalar)> {
+        let direction = direction.normalize();
+        let mut best: Option<(usize, Scalar)> = None;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(n) = stack.pop() {
+            let limit = best.map_or(t_max, |(_, t)| t);
+            if self.nodes[n]
+                .aabb()
+                .ray_interval(origin, &direction, limit)
+                .is_none()
+            {
+                continue;
+            }
+            match self.nodes[n] {
+                BvhNode::Leaf { start, end, .. } => {
+                    for &i in &self.order[start..end] {
+                        let limit = best.map_or(t_max, |(_, t)| t);
+                        if let Some((t0, t1)) =
+                            self.boxes[i].ray_interval(origin, &direction, limit)
+                        {
+                            let entry = origin.clone() + direction.clone() * t0;
+                            if let Some(t) =
+                                sphere_trace(&self.items[i], &entry, &direction, t1 - t0)
+                            {
+                                if best.is_none_or(|(_, b)| t0 + t < b) {
+                                    best = Some((i, t0 + t));
+                                }
+                            }
+                        }
+                    }
+                }
+                BvhNode::Branch { left, right, .. } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+        best
+    }
+
+    /// Item closest to `point` and its signed distance, searching boxes in
+    /// order of their distance from the point.
+    pub fn nearest(&self, point: &Vector) -> Option<(usize, Scalar)> {
+        let mut best: Option<(usize, Scalar)> = None;
+        let mut heap = BinaryHeap::new();
+        if !self.nodes.is_empty() {
+            heap.push(Candidate(self.nodes[0].aabb().distance(point), 0));
+        }
+        while let Some(Candidate(d, n)) = heap.pop() {
+            // Box distances are never negative, so inside an item they can
+            // only rule out boxes away from the point.
+            if best.is_some_and(|(_, b)| d > b.max(0.0)) {
+                break;
+            }
+            match self.nodes[n] {
+                BvhNode::Leaf { start, end, .. } => {
+                    for &i in &self.order[start..end] {
+                        if best.is_some_and(|(_, b)| self.boxes[i].distance(point) > b.max(0.0)) {
+                            continue;
+                        }
+                        let di = self.items[i].distance(point);
+                        if best.is_none_or(|(_, b)| di < b) {
+                            best = Some((i, di));
+                        }
+                    }
+                }
+                BvhNode::Branch { left, right, .. } => {
+                    heap.push(Candidate(self.nodes[left].aabb().distance(point), left));
+                    heap.push(Candidate(self.nodes[right].aabb().distance(point), right));
+                }
+            }
+        }
+        best
+    }
+}
+
+/// Node queued for nearest-neighbour search, ordered closest first.
+struct Candidate(Scalar, usize);
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.partial_cmp(&self.0).unwrap_or(Ordering::Equal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spheres(n: usize) -> Vec<Sphere> {
+        // Deterministic scatter on a jittered grid.
+        let mut seed = 12345u64;
+        let mut rand = || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 11) as Scalar / (1u64 << 53) as Scalar
+        };
+        (0..n)
+            .map(|_| {
+                let c = Vector::from_slice(&[100.0 * rand(), 100.0 * rand(), 100.0 * rand()]);
+                Sphere::from_radius(&c, 0.5 + rand())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_aabb() {
+        let c = Cylinder::new(
+            &Vector::from_slice(&[0.0, 0.0, 0.0]),
+            &Vector::from_slice(&[0.0, 0.0, 1.0]),
+            1.0,
+            4.0,
+        );
+        assert_eq!(c.aabb(), Aabb::new([-1.0, -1.0, -2.0], [1.0, 1.0, 2.0]));
+        let b = c.aabb();
+        let o = Vector::from_slice(&[-5.0, 0.0, 0.0]);
+        let d = Vector::from_slice(&[1.0, 0.0, 0.0]);
+        assert_eq!(b.ray_interval(&o, &d, 100.0), Some((4.0, 6.0)));
+    }
+
+    #[test]
+    fn test_queries_match_naive() {
+        let bvh = Bvh::new(spheres(200));
+        let p = Vector::from_slice(&[50.0, 50.0, 50.0]);
+
+        let (i, d) = bvh.nearest(&p).unwrap();
+        let naive = bvh
+            .items()
+            .iter()
+            .map(|s| s.distance(&p))
+            .fold(Scalar::INFINITY, Scalar::min);
+        assert_eq!(d, naive);
+        assert_eq!(bvh.items()[i].distance(&p), d);
+
+        let q = bvh.items()[7].center();
+        let naive: Vec<usize> = (0..bvh.len())
+            .filter(|&j| SignedDistance::contains(&bvh.items()[j], &q))
+            .collect();
+        assert_eq!(bvh.contains(&q), naive);
+
+        let dir = Vector::from_slice(&[1.0, 0.3, -0.2]);
+        let hit = bvh.ray_cast(&Vector::from_slice(&[0.0, 50.0, 50.0]), &dir, 1e3);
+        let naive = (0..bvh.len())
+            .filter_map(|j| {
+                sphere_trace(
+                    &bvh.items()[j],
+                    &Vector::from_slice(&[0.0, 50.0, 50.0]),
+                    &dir,
+                    1e3,
+                )
+                .map(|t| (j, t))
+            })
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        assert_eq!(hit.map(|h| h.0), naive.map(|h| h.0));
+    }
+
+    #[test]
+    fn test_nearest_inside() {
+        // A point inside several solids is nearest to the one it lies
+        // deepest in, whichever box is searched first.
+        let mut items = spheres(200);
+        let p = Vector::from_slice(&[50.0, 50.0, 50.0]);
+        items.push(Sphere::from_radius(&p, 1.0));
+        items.push(Sphere::from_radius(
+            &Vector::from_slice(&[60.0, 50.0, 50.0]),
+            15.0,
+        ));
+        items.push(Sphere::from_radius(
+            &Vector::from_slice(&[48.0, 50.0, 50.0]),
+            4.0,
+        ));
+        let bvh = Bvh::new(items);
+        let mut points = vec![p];
+        points.extend(bvh.items().iter().map(|s| s.center()));
+        for p in &points {
+            let naive = bvh
+                .items()
+                .iter()
+                .map(|s| s.distance(p))
+                .fold(Scalar::INFINITY, Scalar::min);
+            let (i, d) = bvh.nearest(p).unwrap();
+            assert_eq!(d, naive);
+            assert_eq!(bvh.items()[i].distance(p), d);
+        }
+        assert_eq!(bvh.nearest(&points[0]).unwrap().0, 201);
+    }
+}
@@ -0,0 +1,370 @@
+//! Point-free combinators for differentiable functions, after Elliott's
+//! "The simple essence of automatic differentiation".
+//!
+//! Formulas are written once, generic over a category `C`, from
+//! composition, the cartesian combinators
+//!
+//! ```text
+//! exl (a, b) = a        exr (a, b) = b
+//! (f △ g) a = (f a, g a)        (f × g) (a, b) = (f a, g b)
+//! ```
+//!
+//! and numeric primitives such as `mul_c (a, b) = a b`, so that
+//! `r^2 = mul_c ∘ (exl △ exl)` on (r, rho). In `Fun` they evaluate; in `D`
+//! they also return their derivative, a linear map from input to output
+//! increments, built by the chain rule
+//!
+//! ```text
+//! D (g ∘ f) a = D g (f a) ∘ D f a
+//! ```
+
+use std::rc::Rc;
+
+type Scalar = f64;
+
+/// Types of the objects of the categories: scalars and tuples of them.
+pub trait Object: Clone + 'static {}
+
+impl<T: Clone + 'static> Object for T {}
+
+pub trait Category {
+    /// Arrows from `A` to `B`.
+    type Hom<A: Object, B: Object>: Clone;
+
+    fn id<A: Object>() -> Self::Hom<A, A>;
+
+    /// g ∘ f
+    fn compose<A: Object, B: Object, C: Object>(
+        g: Self::Hom<B, C>,
+        f: Self::Hom<A, B>,
+    ) -> Self::Hom<A, C>;
+}
+
+pub trait Monoidal: Category {
+    /// f × g, acting on the two sides of a pair.
+    fn cross<A: Object, B: Object, C: Object, D: Object>(
+        f: Self::Hom<A, C>,
+        g: Self::Hom<B, D>,
+    ) -> Self::Hom<(A, B), (C, D)>;
+}
+
+pub trait Cartesian: Monoidal {
+    fn exl<A: Object, B: Object>() -> Self::Hom<(A, B), A>;
+
+    fn exr<A: Object, B: Object>() -> Self::Hom<(A, B), B>;
+
+    /// f △ g, both applied to the same argument.
+    fn fork<A: Object, B: Object, C: Object>(
+        f: Self::Hom<A, B>,
+        g: Self::Hom<A, C>,
+    ) -> Self::Hom<A, (B, C)>;
+
+    fn dup<A: Object>() -> Self::Hom<A, (A, A)> {
+        Self::fork(Self::id(), Self::id())
+    }
+}
+
+/// Scalar primitives.
+pub trait Numeric: Category {
+    fn add_c() -> Self::Hom<(Scalar, Scalar), Scalar>;
+
+    fn mul_c() -> Self::Hom<(Scalar, Scalar), Scalar>;
+
+    fn neg_c() -> Self::Hom<Scalar, Scalar>;
+
+    fn recip_c() -> Self::Hom<Scalar, Scalar>;
+
+    fn exp_c() -> Self::Hom<Scalar, Scalar>;
+
+    fn ln_c() -> Self::Hom<Scalar, Scalar>;
+
+    fn sqrt_c() -> Self::Hom<Scalar, Scalar>;
+
+    /// Multiplication by the constant `c`.
+    fn scale_c(c: Scalar) -> Self::Hom<Scalar, Scalar>;
+
+    /// The constant `c`, whatever the argument.
+    fn const_c<A: Object>(c: Scalar) -> Self::Hom<A, Scalar>;
+}
+
+/// Plain functions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fun;
+
+/// Function from `A` to `B`.
+pub type Function<A, B> = Rc<dyn Fn(A) -> B>;
+
+impl Category for Fun {
+    type Hom<A: Object, B: Object> = Function<A, B>;
+
+    fn id<A: Object>() -> Function<A, A> {
+        Rc::new(|a| a)
+    }
+
+    fn compose<A: Object, B: Object, C: Object>(
+        g: Function<B, C>,
+        f: Function<A, B>,
+    ) -> Function<A, C> {
+        Rc::new(move |a| g(f(a)))
+    }
+}
+
+impl Monoidal for Fun {
+    fn cross<A: Object, B: Object, C: Object, D: Object>(
+        f: Function<A, C>,
+        g: Function<B, D>,
+    ) -> Function<(A, B), (C, D)> {
+        Rc::new(move |(a, b)| (f(a), g(b)))
+    }
+}
+
+impl Cartesian for Fun {
+    fn exl<A: Object, B: Object>() -> Function<(A, B), A> {
+        Rc::new(|(a, _)| a)
+    }
+
+    fn exr<A: Object, B: Object>() -> Function<(A, B), B> {
+        Rc::new(|(_, b)| b)
+    }
+
+    fn fork<A: Object, B: Object, C: Object>(
+        f: Function<A, B>,
+        g: Function<A, C>,
+    ) -> Function<A, (B, C)> {
+        Rc::new(move |a: A| (f(a.clone()), g(a)))
+    }
+}
+
+impl Numeric for Fun {
+    fn add_c() -> Function<(Scalar, Scalar), Scalar> {
+        Rc::new(|(a, b)| a + b)
+    }
+
+    fn mul_c() -> Function<(Scalar, Scalar), Scalar> {
+        Rc::new(|(a, b)| a * b)
+    }
+
+    fn neg_c() -> Function<Scalar, Scalar> {
+        Rc::new(|a: Scalar| -a)
+    }
+
+    fn recip_c() -> Function<Scalar, Scalar> {
+        Rc::new(Scalar::recip)
+    }
+
+    fn exp_c() -> Function<Scalar, Scalar> {
+        Rc::new(Scalar::exp)
+    }
+
+    fn ln_c() -> Function<Scalar, Scalar> {
+        Rc::new(Scalar::ln)
+    }
+
+    fn sqrt_c() -> Function<Scalar, Scalar> {
+        Rc::new(Scalar::sqrt)
+    }
+
+    fn scale_c(c: Scalar) -> Function<Scalar, Scalar> {
+        Rc::new(move |a| c * a)
+    }
+
+    fn const_c<A: Object>(c: Scalar) -> Function<A, Scalar> {
+        Rc::new(move |_| c)
+    }
+}
+
+/// Differentiable functions, returning their value and derivative.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct D;
+
+/// Differentiable function from `A` to `B`: its value at a point and the
+/// linear map of increments there.
+pub type Differentiable<A, B> = Rc<dyn Fn(A) -> (B, Function<A, B>)>;
+
+impl D {
+    /// Arrow of the linear map `f`, which is its own derivative.
+    pub fn linear<A: Object, B: Object>(f: Function<A, B>) -> Differentiable<A, B> {
+        Rc::new(move |a| (f(a), f.clone()))
+    }
+
+    /// Arrow of a scalar function of value `f` and derivative `df`.
+    fn scalar<F>(f: F) -> Differentiable<Scalar, Scalar>
+    where
+        F: Fn(Scalar) -> (Scalar, Scalar) + 'static,
+    {
+        Rc::new(move |a| {
+            let (b, db) = f(a);
+            (b, Fun::scale_c(db))
+        })
+    }
+
+    /// Gradient of the scalar `f` of two variables at `a`.
+    pub fn gradient(
+        f: &Differentiable<(Scalar, Scalar), Scalar>,
+        a: (Scalar, Scalar),
+    ) -> [Scalar; 2] {
+        let (_, df) = f(a);
+        [df((1.0, 0.0)), df((0.0, 1.0))]
+    }
+}
+
+impl Category for D {
+    type Hom<A: Object, B: Object> = Differentiable<A, B>;
+
+    fn id<A: Object>() -> Differentiable<A, A> {
+        D::linear(Fun::id())
+    }
+
+    fn compose<A: Object, B: Object, C: Object>(
+        g: Differentiable<B, C>,
+        f: Differentiable<A, B>,
+    ) -> Differentiable<A, C> {
+        Rc::new(move |a| {
+            let (b, df) = f(a);
+            let (c, dg) = g(b);
+            (c, Fun::compose(dg, df))
+        })
+    }
+}
+
+impl Monoidal for D {
+    fn cross<A: Object, B: Object, C: Object, E: Object>(
+        f: Differentiable<A, C>,
+        g: Differentiable<B, E>,
+    ) -> Differentiable<(A, B), (C, E)> {
+        Rc::new(move |(a, b)| {
+            let (c, df) = f(a);
+            let (e, dg) = g(b);
+            ((c, e), Fun::cross(df, dg))
+        })
+    }
+}
+
+impl Cartesian for D {
+    fn exl<A: Object, B: Object>() -> Differentiable<(A, B), A> {
+        D::linear(Fun::exl())
+    }
+
+    fn exr<A: Object, B: Object>() -> Differentiable<(A, B), B> {
+        D::linear(Fun::exr())
+    }
+
+    fn fork<A: Object, B: Object, C: Object>(
+        f: Differentiable<A, B>,
+        g: Differentiable<A, C>,
+    ) -> Differentiable<A, (B, C)> {
+        Rc::new(move |a: A| {
+            let (b, df) = f(a.clone());
+            let (c, dg) = g(a);
+            ((b, c), Fun::fork(df, dg))
+        })
+    }
+}
+
+impl Numeric for D {
+    fn add_c() -> Differentiable<(Scalar, Scalar), Scalar> {
+        D::linear(Fun::add_c())
+    }
+
+    fn mul_c() -> Differentiable<(Scalar, Scalar), Scalar> {
+        Rc::new(|(a, b)| {
+            let dmul: Function<(Scalar, Scalar), Scalar> = Rc::new(move |(da, db)| b * da + a * db);
+            (a * b, dmul)
+        })
+    }
+
+    fn neg_c() -> Differentiable<Scalar, Scalar> {
+        D::linear(Fun::neg_c())
+    }
+
+    fn recip_c() -> Differentiable<Scalar, Scalar> {
+        D::scalar(|a| (a.recip(), -a.powi(-2)))
+    }
+
+    fn exp_c() -> Differentiable<Scalar, Scalar> {
+        D::scalar(|a| (a.exp(), a.exp()))
+    }
+
+    fn ln_c() -> Differentiable<Scalar, Scalar> {
+        D::scalar(|a| (a.ln(), a.recip()))
+    }
+
+    fn sqrt_c() -> Differentiable<Scalar, Scalar> {
+        D::scalar(|a| (a.sqrt(), 0.5 / a.sqrt()))
+    }
+
+    fn scale_c(c: Scalar) -> Differentiable<Scalar, Scalar> {
+        D::linear(Fun::scale_c(c))
+    }
+
+    fn const_c<A: Object>(c: Scalar) -> Differentiable<A, Scalar> {
+        Rc::new(move |_| (c, Fun::const_c(0.0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::MOLAR_GAS_CONSTANT;
+    use std::f64::consts::PI;
+
+    /// r^2 on (r, rho), as in doc/stars.tex
+    fn r_squared<C: Cartesian + Numeric>() -> C::Hom<(Scalar, Scalar), Scalar> {
+        C::compose(C::mul_c(), C::fork(C::exl(), C::exl()))
+    }
+
+    /// dr/dm = 1/(4 pi r^2 rho) on (r, rho)
+    fn dr_dm<C: Cartesian + Numeric>() -> C::Hom<(Scalar, Scalar), Scalar> {
+        let r2_rho = C::compose(C::mul_c(), C::fork(r_squared::<C>(), C::exr()));
+        C::compose(C::recip_c(), C::compose(C::scale_c(4.0 * PI), r2_rho))
+    }
+
+    /// rho = mu P/(R T) on (P, T), for molar mass `mu`
+    fn density<C: Cartesian + Numeric>(mu: Scalar) -> C::Hom<(Scalar, Scalar), Scalar> {
+        let p_over_t = C::compose(C::mul_c(), C::cross(C::id(), C::recip_c()));
+        C::compose(C::scale_c(mu / MOLAR_GAS_CONSTANT), p_over_t)
+    }
+
+    #[test]
+    fn test_dr_dm() {
+        let (r, rho) = (7e8, 1.4e3);
+        let value = 1.0 / (4.0 * PI * r * r * rho);
+        assert_eq!(dr_dm::<Fun>()((r, rho)), value);
+        let f = dr_dm::<D>();
+        assert_eq!(f((r, rho)).0, value);
+        let [dr, drho] = D::gradient(&f, (r, rho));
+        assert!((dr / (-2.0 * value / r) - 1.0).abs() < 1e-14);
+        assert!((drho / (-value / rho) - 1.0).abs() < 1e-14);
+    }
+
+    #[test]
+    fn test_composition() {
+        // dr/dm on (r, (P, T)), through the ideal gas density
+        let mu = 0.6e-3;
+        let f = D::compose(dr_dm::<D>(), D::cross(D::id(), density::<D>(mu)));
+        let (r, p, t) = (7e8, 2e14, 1.5e7);
+        let rho = mu * p / (MOLAR_GAS_CONSTANT * t);
+        let (value, df) = f((r, (p, t)));
+        assert_eq!(
+            value,
+            Fun::compose(dr_dm::<Fun>(), Fun::cross(Fun::id(), density::<Fun>(mu)))((r, (p, t)))
+        );
+        assert!((value * 4.0 * PI * r * r * rho - 1.0).abs() < 1e-14);
+        // dr/dm is proportional to T/P
+        assert!((df((0.0, (p, 0.0))) / -value - 1.0).abs() < 1e-14);
+        assert!((df((0.0, (0.0, t))) / value - 1.0).abs() < 1e-14);
+        // Derivatives of the primitives
+        let g = D::compose(
+            D::add_c(),
+            D::fork(
+                D::exp_c(),
+                D::compose(D::neg_c(), D::compose(D::ln_c(), D::sqrt_c())),
+            ),
+        );
+        let (value, dg) = g(4.0);
+        assert_eq!(value, 4f64.exp() - 2f64.ln());
+        assert!((dg(1.0) - (4f64.exp() - 0.125)).abs() < 1e-12);
+        assert_eq!(D::dup::<Scalar>()(2.0).1(3.0), (3.0, 3.0));
+        assert_eq!(D::const_c::<Scalar>(2.0)(5.0).1(1.0), 0.0);
+    }
+}
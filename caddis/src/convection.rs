@@ -0,0 +1,188 @@
+//! Convective stability and mixing-length theory.
+//!
+//! The temperature gradient nabla = d ln T / d ln P of a convective layer
+//! follows from the cubic of Kippenhahn & Weigert (eq. 7.18),
+//!
+//! ```text
+//! (xi - U)^3 + 8 U/9 (xi^2 - U^2 - W) = 0,    nabla = xi^2 - U^2 + nabla_ad
+//! ```
+//!
+//! with W = nabla_rad - nabla_ad and the efficiency parameter
+//!
+//! ```text
+//! U = 3 a c T^3/(c_P rho^2 kappa l_m^2) (8 H_P/(g delta))^(1/2),    l_m = alpha H_P
+//! ```
+//!
+//! U -> 0 gives the adiabatic gradient of efficient convection and U -> oo
+//! the radiative gradient.
+
+use crate::units::{RADIATION_DENSITY_CONSTANT, SPEED_OF_LIGHT};
+
+type Scalar = f64;
+
+const TOLERANCE: Scalar = 1e-14;
+const MAX_ITERATIONS: usize = 100;
+
+/// Criterion for the onset of convection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Criterion {
+    /// nabla_rad > nabla_ad
+    Schwarzschild,
+    /// nabla_rad > nabla_ad + phi/delta nabla_mu, stabilised by a mean
+    /// molecular weight increasing inwards
+    Ledoux,
+}
+
+/// Mixing-length convection with mixing length `alpha` pressure scale
+/// heights.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Convection {
+    pub alpha: Scalar,
+    pub criterion: Criterion,
+}
+
+impl Convection {
+    pub fn new(alpha: Scalar) -> Self {
+        Convection {
+            alpha,
+            criterion: Criterion::Schwarzschild,
+        }
+    }
+
+    pub fn with_criterion(self, criterion: Criterion) -> Self {
+        Convection { criterion, ..self }
+    }
+
+    /// Whether a layer is convectively unstable. `nabla_mu` is
+    /// d ln mu / d ln P and `phi_over_delta` weighs it against the thermal
+    /// stratification, 1 for an ideal gas; a homogeneous layer ignores it,
+    /// which may then be infinite.
+    pub fn is_unstable(
+        &self,
+        nabla_rad: Scalar,
+        nabla_ad: Scalar,
+        nabla_mu: Scalar,
+        phi_over_delta: Scalar,
+    ) -> bool {
+        match self.criterion {
+            Criterion::Schwarzschild => nabla_rad > nabla_ad,
+            Criterion::Ledoux if nabla_mu != 0.0 => {
+                nabla_rad > nabla_ad + phi_over_delta * nabla_mu
+            }
+            _ => nabla_rad > nabla_ad,
+        }
+    }
+
+    /// Efficiency parameter U, which scales as T^3 rho^-2 kappa^-1
+    /// H_P^(-3/2) g^(-1/2) at fixed c_P and delta.
+    #[allow(clippy::too_many_arguments)]
+    pub fn efficiency(
+        &self,
+        temperature: Scalar,
+        rho: Scalar,
+        kappa: Scalar,
+        specific_heat: Scalar,
+        delta: Scalar,
+        scale_height: Scalar,
+        gravity: Scalar,
+    ) -> Scalar {
+        let a = RADIATION_DENSITY_CONSTANT;
+        let c = SPEED_OF_LIGHT;
+        let mixing_length = self.alpha * scale_height;
+        3.0 * a * c * temperature.powi(3)
+            / (specific_heat * rho * rho * kappa * mixing_length * mixing_length)
+            * (8.0 * scale_height / (gravity * delta)).sqrt()
+    }
+
+    /// Temperature gradient of a convective layer, and its partial
+    /// derivatives with respect to nabla_rad, nabla_ad and U,
+    /// `[nabla, dnabla/dnabla_rad, dnabla/dnabla_ad, dnabla/dU]`.
+    /// Stable layers, nabla_rad <= nabla_ad, are radiative.
+    pub fn gradient(&self, nabla_rad: Scalar, nabla_ad: Scalar, u: Scalar) -> [Scalar; 4] {
+        let w = nabla_rad - nabla_ad;
+        if w <= 0.0 {
+            return [nabla_rad, 1.0, 0.0, 0.0];
+        }
+        // In terms of x = xi - U, which avoids cancellation when U is large,
+        // the cubic is x^3 + 8 U/9 (x^2 + 2 U x - W) = 0 and
+        // nabla - nabla_ad = x^2 + 2 U x.
+        let f = |x: Scalar| x.powi(3) + 8.0 * u / 9.0 * (x * x + 2.0 * u * x - w);
+        let f_x = |x: Scalar| 3.0 * x * x + 16.0 * u / 9.0 * (x + u);
+        // The root is bracketed by 0, where f < 0, and the radiative limit,
+        // where f > 0. Newton steps leaving the bracket are replaced by
+        // bisection.
+        let (mut low, mut high) = (0.0, w / ((u * u + w).sqrt() + u));
+        let mut x = high;
+        for _ in 0..MAX_ITERATIONS {
+            let value = f(x);
+            if value > 0.0 {
+                high = x;
+            } else {
+                low = x;
+            }
+            let mut next = x - value / f_x(x);
+            if !(next > low && next < high) {
+                next = 0.5 * (low + high);
+            }
+            let step = (next - x).abs();
+            x = next;
+            if step <= TOLERANCE * x {
+                break;
+            }
+        }
+        let denominator = f_x(x);
+        let x_w = 8.0 * u / (9.0 * denominator);
+        let x_u = -8.0 / 9.0 * (x * x + 4.0 * u * x - w) / denominator;
+        let nabla_w = 2.0 * (x + u) * x_w;
+        [
+            x * x + 2.0 * u * x + nabla_ad,
+            nabla_w,
+            1.0 - nabla_w,
+            2.0 * (x + u) * x_u + 2.0 * x,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits() {
+        let mlt = Convection::new(1.8);
+        let (nabla_rad, nabla_ad) = (2.0, 0.4);
+        // Efficient convection is nearly adiabatic, inefficient radiative.
+        assert!((mlt.gradient(nabla_rad, nabla_ad, 1e-6)[0] - nabla_ad).abs() < 1e-3);
+        assert!((mlt.gradient(nabla_rad, nabla_ad, 1e6)[0] - nabla_rad).abs() < 1e-6);
+        let nabla = mlt.gradient(nabla_rad, nabla_ad, 0.1)[0];
+        assert!(nabla > nabla_ad && nabla < nabla_rad);
+        assert_eq!(mlt.gradient(0.3, nabla_ad, 0.1), [0.3, 1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_gradient_derivatives() {
+        let mlt = Convection::new(1.8);
+        for &(nabla_rad, nabla_ad, u) in &[(2.0, 0.4, 0.1), (0.5, 0.4, 0.01), (10.0, 0.25, 3.0)] {
+            let [_, d_rad, d_ad, d_u] = mlt.gradient(nabla_rad, nabla_ad, u);
+            let h = 1e-6;
+            let fd = |a: [Scalar; 3], b: [Scalar; 3]| {
+                (mlt.gradient(a[0], a[1], a[2])[0] - mlt.gradient(b[0], b[1], b[2])[0]) / (2.0 * h)
+            };
+            let fd_rad = fd([nabla_rad + h, nabla_ad, u], [nabla_rad - h, nabla_ad, u]);
+            let fd_ad = fd([nabla_rad, nabla_ad + h, u], [nabla_rad, nabla_ad - h, u]);
+            let fd_u = fd([nabla_rad, nabla_ad, u + h], [nabla_rad, nabla_ad, u - h]);
+            assert!((fd_rad - d_rad).abs() < 1e-7);
+            assert!((fd_ad - d_ad).abs() < 1e-7);
+            assert!((fd_u - d_u).abs() < 1e-7);
+        }
+    }
+
+    #[test]
+    fn test_criteria() {
+        let schwarzschild = Convection::new(1.8);
+        let ledoux = schwarzschild.with_criterion(Criterion::Ledoux);
+        assert!(schwarzschild.is_unstable(0.45, 0.4, 0.1, 1.0));
+        assert!(!ledoux.is_unstable(0.45, 0.4, 0.1, 1.0));
+        assert!(ledoux.is_unstable(0.55, 0.4, 0.1, 1.0));
+    }
+}
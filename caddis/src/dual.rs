@@ -0,0 +1,665 @@
+//! Forward-mode automatic differentiation with dual numbers.
+//!
+//! A dual number x + x' e, with e^2 = 0, carries a value and its gradient
+//! with respect to the independent variables, and every operation applies
+//! the chain rule,
+//!
+//! ```text
+//! f(x + x' e) = f(x) + f'(x) x' e
+//! ```
+//!
+//! The gradient lives in a `Tangent` space: `f64` for a single direction,
+//! `[f64; N]` for N independent variables at once, as in the Jacobian blocks
+//! of the Henyey matrix, or `geometry::Vector` for gradients in space. Duals
+//! over `Copy` tangents implement `num_traits::Float`, so code generic over
+//! `Float` differentiates itself.
+
+use std::cmp::Ordering;
+use std::num::FpCategory;
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Rem, Sub, SubAssign};
+
+use num_traits::{Float, Num, NumCast, One, ToPrimitive, Zero};
+
+use crate::geometry::Vector;
+
+type Scalar = f64;
+
+/// Space of the derivative part of a dual number.
+pub trait Tangent: Clone + PartialEq {
+    fn zero() -> Self;
+    /// a self + b other
+    fn combine(&self, a: Scalar, other: &Self, b: Scalar) -> Self;
+    /// a self, with zero components staying zero for an infinite a
+    fn scale(&self, a: Scalar) -> Self;
+}
+
+/// a x, or 0 for x = 0 whatever a is
+fn scale_component(x: Scalar, a: Scalar) -> Scalar {
+    if x == 0.0 {
+        0.0
+    } else {
+        a * x
+    }
+}
+
+impl Tangent for Scalar {
+    fn zero() -> Self {
+        0.0
+    }
+
+    fn combine(&self, a: Scalar, other: &Self, b: Scalar) -> Self {
+        a * self + b * other
+    }
+
+    fn scale(&self, a: Scalar) -> Self {
+        scale_component(*self, a)
+    }
+}
+
+impl<const N: usize> Tangent for [Scalar; N] {
+    fn zero() -> Self {
+        [0.0; N]
+    }
+
+    fn combine(&self, a: Scalar, other: &Self, b: Scalar) -> Self {
+        let mut c = [0.0; N];
+        for i in 0..N {
+            c[i] = a * self[i] + b * other[i];
+        }
+        c
+    }
+
+    fn scale(&self, a: Scalar) -> Self {
+        let mut c = [0.0; N];
+        for i in 0..N {
+            c[i] = scale_component(self[i], a);
+        }
+        c
+    }
+}
+
+impl Tangent for Vector {
+    fn zero() -> Self {
+        <Vector as Zero>::zero()
+    }
+
+    fn combine(&self, a: Scalar, other: &Self, b: Scalar) -> Self {
+        self.clone() * a + other.clone() * b
+    }
+
+    fn scale(&self, a: Scalar) -> Self {
+        let mut v = self.clone();
+        // Vectors are three-dimensional.
+        for i in 0..3 {
+            v[i] = scale_component(v[i], a);
+        }
+        v
+    }
+}
+
+/// Value and gradient.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dual<G> {
+    pub value: Scalar,
+    pub gradient: G,
+}
+
+impl<G: Tangent> Dual<G> {
+    pub fn new(value: Scalar, gradient: G) -> Self {
+        Dual { value, gradient }
+    }
+
+    /// Constant, of zero gradient.
+    pub fn constant(value: Scalar) -> Self {
+        Dual::new(value, G::zero())
+    }
+
+    /// f(x) for f(value) = `f` and f'(value) = `derivative`. Components of
+    /// the gradient that are zero stay zero where the derivative is
+    /// infinite, as that of sqrt at 0, so constants stay constant.
+    fn chain(&self, f: Scalar, derivative: Scalar) -> Self {
+        Dual::new(f, self.gradient.scale(derivative))
+    }
+
+    /// Function of the duals `arguments` given its value and its partial
+    /// derivatives in each, such as the `[rho, drho/dP, drho/dT]` of an
+    /// equation of state.
+    pub fn lift(value: Scalar, arguments: &[(Scalar, &Self)]) -> Self {
+        let gradient = arguments
+            .iter()
+            .fold(G::zero(), |g, (d, x)| g.combine(1.0, &x.gradient, *d));
+        Dual::new(value, gradient)
+    }
+
+    pub fn recip(self) -> Self {
+        self.chain(self.value.recip(), -self.value.powi(-2))
+    }
+
+    pub fn powi(self, n: i32) -> Self {
+        if n == 0 {
+            return Dual::constant(1.0);
+        }
+        self.chain(self.value.powi(n), n as Scalar * self.value.powi(n - 1))
+    }
+
+    /// x^n, differentiated in n only where n is not constant.
+    pub fn powf(self, n: Self) -> Self {
+        let f = self.value.powf(n.value);
+        let mut power = self.chain(f, n.value * self.value.powf(n.value - 1.0));
+        if n.gradient != G::zero() {
+            power.gradient = power
+                .gradient
+                .combine(1.0, &n.gradient, f * self.value.ln());
+        }
+        power
+    }
+
+    pub fn sqrt(self) -> Self {
+        let f = self.value.sqrt();
+        self.chain(f, 0.5 / f)
+    }
+
+    pub fn cbrt(self) -> Self {
+        let f = self.value.cbrt();
+        self.chain(f, f / (3.0 * self.value))
+    }
+
+    pub fn exp(self) -> Self {
+        let f = self.value.exp();
+        self.chain(f, f)
+    }
+
+    pub fn exp2(self) -> Self {
+        let f = self.value.exp2();
+        self.chain(f, f * std::f64::consts::LN_2)
+    }
+
+    pub fn exp_m1(self) -> Self {
+        self.chain(self.value.exp_m1(), self.value.exp())
+    }
+
+    pub fn ln(self) -> Self {
+        self.chain(self.value.ln(), self.value.recip())
+    }
+
+    pub fn ln_1p(self) -> Self {
+        self.chain(self.value.ln_1p(), (1.0 + self.value).recip())
+    }
+
+    pub fn log(self, base: Self) -> Self {
+        self.ln() / base.ln()
+    }
+
+    pub fn log2(self) -> Self {
+        self.chain(
+            self.value.log2(),
+            (self.value * std::f64::consts::LN_2).recip(),
+        )
+    }
+
+    pub fn log10(self) -> Self {
+        self.chain(
+            self.value.log10(),
+            (self.value * std::f64::consts::LN_10).recip(),
+        )
+    }
+
+    pub fn abs(self) -> Self {
+        self.chain(self.value.abs(), self.value.signum())
+    }
+
+    pub fn sin(self) -> Self {
+        self.chain(self.value.sin(), self.value.cos())
+    }
+
+    pub fn cos(self) -> Self {
+        self.chain(self.value.cos(), -self.value.sin())
+    }
+
+    pub fn tan(self) -> Self {
+        let f = self.value.tan();
+        self.chain(f, 1.0 + f * f)
+    }
+
+    pub fn asin(self) -> Self {
+        self.chain(
+            self.value.asin(),
+            (1.0 - self.value * self.value).sqrt().recip(),
+        )
+    }
+
+    pub fn acos(self) -> Self {
+        self.chain(
+            self.value.acos(),
+            -(1.0 - self.value * self.value).sqrt().recip(),
+        )
+    }
+
+    pub fn atan(self) -> Self {
+        self.chain(self.value.atan(), (1.0 + self.value * self.value).recip())
+    }
+
+    /// atan(self/other) in the quadrant of (other, self).
+    pub fn atan2(self, other: Self) -> Self {
+        let d = self.value * self.value + other.value * other.value;
+        let gradient = self
+            .gradient
+            .combine(other.value / d, &other.gradient, -self.value / d);
+        Dual::new(self.value.atan2(other.value), gradient)
+    }
+
+    pub fn sinh(self) -> Self {
+        self.chain(self.value.sinh(), self.value.cosh())
+    }
+
+    pub fn cosh(self) -> Self {
+        self.chain(self.value.cosh(), self.value.sinh())
+    }
+
+    pub fn tanh(self) -> Self {
+        let f = self.value.tanh();
+        self.chain(f, 1.0 - f * f)
+    }
+
+    pub fn asinh(self) -> Self {
+        self.chain(
+            self.value.asinh(),
+            (self.value * self.value + 1.0).sqrt().recip(),
+        )
+    }
+
+    pub fn acosh(self) -> Self {
+        self.chain(
+            self.value.acosh(),
+            (self.value * self.value - 1.0).sqrt().recip(),
+        )
+    }
+
+    pub fn atanh(self) -> Self {
+        self.chain(self.value.atanh(), (1.0 - self.value * self.value).recip())
+    }
+
+    pub fn hypot(self, other: Self) -> Self {
+        let f = self.value.hypot(other.value);
+        let gradient = self
+            .gradient
+            .combine(self.value / f, &other.gradient, other.value / f);
+        Dual::new(f, gradient)
+    }
+
+    /// The larger of the two, with its gradient.
+    pub fn max(self, other: Self) -> Self {
+        if other.value > self.value || self.value.is_nan() {
+            other
+        } else {
+            self
+        }
+    }
+
+    pub fn min(self, other: Self) -> Self {
+        if other.value < self.value || self.value.is_nan() {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+impl<const N: usize> Dual<[Scalar; N]> {
+    /// The independent variables `x`, each with a unit gradient.
+    pub fn variables(x: [Scalar; N]) -> [Self; N] {
+        let mut variables = [Self::constant(0.0); N];
+        for i in 0..N {
+            variables[i].value = x[i];
+            variables[i].gradient[i] = 1.0;
+        }
+        variables
+    }
+}
+
+/// Values and Jacobian, as rows of df_i/dx_j, of `f` at `x`.
+pub fn jacobian<F, const N: usize, const M: usize>(
+    f: F,
+    x: [Scalar; N],
+) -> ([Scalar; M], [[Scalar; N]; M])
+where
+    F: Fn([Dual<[Scalar; N]>; N]) -> [Dual<[Scalar; N]>; M],
+{
+    let y = f(Dual::variables(x));
+    (y.map(|y| y.value), y.map(|y| y.gradient))
+}
+
+impl<G: Tangent> Add for Dual<G> {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Dual::new(
+            self.value + other.value,
+            self.gradient.combine(1.0, &other.gradient, 1.0),
+        )
+    }
+}
+
+impl<G: Tangent> Sub for Dual<G> {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Dual::new(
+            self.value - other.value,
+            self.gradient.combine(1.0, &other.gradient, -1.0),
+        )
+    }
+}
+
+impl<G: Tangent> Mul for Dual<G> {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Dual::new(
+            self.value * other.value,
+            self.gradient
+                .combine(other.value, &other.gradient, self.value),
+        )
+    }
+}
+
+impl<G: Tangent> Div for Dual<G> {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        let f = self.value / other.value;
+        Dual::new(
+            f,
+            self.gradient
+                .combine(1.0 / other.value, &other.gradient, -f / other.value),
+        )
+    }
+}
+
+/// x mod y, with the derivatives 1 and -trunc(x/y) of x - y trunc(x/y)
+impl<G: Tangent> Rem for Dual<G> {
+    type Output = Self;
+
+    fn rem(self, other: Self) -> Self {
+        let q = (self.value / other.value).trunc();
+        Dual::new(
+            self.value % other.value,
+            self.gradient.combine(1.0, &other.gradient, -q),
+        )
+    }
+}
+
+impl<G: Tangent> Neg for Dual<G> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        self.chain(-self.value, -1.0)
+    }
+}
+
+macro_rules! scalar_operations(
+    ( $( $trait:ident, $method:ident, $assign:ident, $assign_method:ident );* ) => ( $(
+        impl<G: Tangent> $trait<Scalar> for Dual<G> {
+            type Output = Self;
+
+            fn $method(self, other: Scalar) -> Self {
+                self.$method(Dual::constant(other))
+            }
+        }
+
+        impl<G: Tangent> $trait<Dual<G>> for Scalar {
+            type Output = Dual<G>;
+
+            fn $method(self, other: Dual<G>) -> Dual<G> {
+                Dual::constant(self).$method(other)
+            }
+        }
+
+        impl<G: Tangent> $assign for Dual<G> {
+            fn $assign_method(&mut self, other: Self) {
+                *self = self.clone().$method(other);
+            }
+        }
+
+        impl<G: Tangent> $assign<Scalar> for Dual<G> {
+            fn $assign_method(&mut self, other: Scalar) {
+                *self = self.clone().$method(other);
+            }
+        }
+    )* )
+);
+
+scalar_operations!(
+    Add, add, AddAssign, add_assign;
+    Sub, sub, SubAssign, sub_assign;
+    Mul, mul, MulAssign, mul_assign;
+    Div, div, DivAssign, div_assign
+);
+
+impl<G: Tangent> PartialOrd for Dual<G> {
+    /// Orders by value alone.
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.value.partial_cmp(&other.value)
+    }
+}
+
+impl<G: Tangent> Zero for Dual<G> {
+    fn zero() -> Self {
+        Dual::constant(0.0)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.value == 0.0 && self.gradient == G::zero()
+    }
+}
+
+impl<G: Tangent> One for Dual<G> {
+    fn one() -> Self {
+        Dual::constant(1.0)
+    }
+}
+
+impl<G: Tangent> Num for Dual<G> {
+    type FromStrRadixErr = <Scalar as Num>::FromStrRadixErr;
+
+    fn from_str_radix(s: &str, radix: u32) -> Result<Self, Self::FromStrRadixErr> {
+        Scalar::from_str_radix(s, radix).map(Dual::constant)
+    }
+}
+
+impl<G: Tangent> ToPrimitive for Dual<G> {
+    fn to_i64(&self) -> Option<i64> {
+        self.value.to_i64()
+    }
+
+    fn to_u64(&self) -> Option<u64> {
+        self.value.to_u64()
+    }
+
+    fn to_f64(&self) -> Option<f64> {
+        Some(self.value)
+    }
+}
+
+impl<G: Tangent> NumCast for Dual<G> {
+    fn from<T: ToPrimitive>(n: T) -> Option<Self> {
+        n.to_f64().map(Dual::constant)
+    }
+}
+
+/// Functions whose derivative vanishes almost everywhere, and constants
+macro_rules! constant_functions(
+    ( $( $method:ident ),* ) => ( $(
+        fn $method(self) -> Self {
+            Dual::constant(self.value.$method())
+        }
+    )* )
+);
+
+macro_rules! constants(
+    ( $( $method:ident ),* ) => ( $(
+        fn $method() -> Self {
+            Dual::constant(Scalar::$method())
+        }
+    )* )
+);
+
+macro_rules! predicates(
+    ( $( $method:ident ),* ) => ( $(
+        fn $method(self) -> bool {
+            self.value.$method()
+        }
+    )* )
+);
+
+macro_rules! forward(
+    ( $( $method:ident ),* ) => ( $(
+        fn $method(self) -> Self {
+            Dual::$method(self)
+        }
+    )* )
+);
+
+macro_rules! forward_binary(
+    ( $( $method:ident ),* ) => ( $(
+        fn $method(self, other: Self) -> Self {
+            Dual::$method(self, other)
+        }
+    )* )
+);
+
+impl<G: Tangent + Copy> Float for Dual<G> {
+    constants!(
+        nan,
+        infinity,
+        neg_infinity,
+        neg_zero,
+        min_value,
+        min_positive_value,
+        max_value
+    );
+    predicates!(
+        is_nan,
+        is_infinite,
+        is_finite,
+        is_normal,
+        is_sign_positive,
+        is_sign_negative
+    );
+    constant_functions!(floor, ceil, round, trunc, signum);
+    forward!(
+        recip, sqrt, cbrt, exp, exp2, exp_m1, ln, ln_1p, log2, log10, abs, sin, cos, tan, asin,
+        acos, atan, sinh, cosh, tanh, asinh, acosh, atanh
+    );
+    forward_binary!(powf, log, atan2, hypot, max, min);
+
+    fn classify(self) -> FpCategory {
+        self.value.classify()
+    }
+
+    fn fract(self) -> Self {
+        Dual::new(self.value.fract(), self.gradient)
+    }
+
+    fn mul_add(self, a: Self, b: Self) -> Self {
+        self * a + b
+    }
+
+    fn powi(self, n: i32) -> Self {
+        Dual::powi(self, n)
+    }
+
+    fn abs_sub(self, other: Self) -> Self {
+        if self.value <= other.value {
+            Dual::constant(0.0)
+        } else {
+            self - other
+        }
+    }
+
+    fn sin_cos(self) -> (Self, Self) {
+        (self.sin(), self.cos())
+    }
+
+    fn integer_decode(self) -> (u64, i16, i8) {
+        self.value.integer_decode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jacobian::{central_differences, Comparison};
+
+    /// Written once for any float
+    fn f<F: Float>(x: F, y: F) -> [F; 3] {
+        let two = F::from(2.0).unwrap();
+        [
+            x.powi(3) * y.sin() / (x + y).sqrt(),
+            (x * y).exp().ln_1p() - x.atan2(y) + x.hypot(y).cbrt(),
+            x.powf(y) * two + (x / y).tanh().max(x.cos()),
+        ]
+    }
+
+    #[test]
+    fn test_jacobian() {
+        let x = [1.3, 0.7];
+        let (value, jacobian) = jacobian(|[x, y]| f(x, y), x);
+        // Optimised builds may round the same expression differently.
+        let close = |a: f64, b: f64| (a - b).abs() <= 1e-15 * b.abs();
+        assert!(value.iter().zip(f(x[0], x[1])).all(|(&a, b)| close(a, b)));
+        let numeric = central_differences(|x| f(x[0], x[1]).to_vec(), &x, &[1e-6; 2]);
+        let analytic = jacobian.iter().map(|row| row.to_vec()).collect();
+        assert!(Comparison::new(analytic, numeric, 0.0).max() < 1e-8);
+        // One direction at a time
+        let dx = f(Dual::new(x[0], 1.0), Dual::constant(x[1]));
+        assert!((0..3).all(|i| close(dx[i].gradient, jacobian[i][0])));
+    }
+
+    #[test]
+    fn test_vector() {
+        // Gradient of the distance |p| in space
+        let p = [3.0, 4.0, 12.0];
+        let mut distance = Dual::constant(0.0);
+        for (i, x) in p.iter().enumerate() {
+            let mut e = Vector::new();
+            e[i] = 1.0;
+            let x = Dual::new(*x, e);
+            distance += x.clone() * x;
+        }
+        let distance = distance.sqrt();
+        assert_eq!(distance.value, 13.0);
+        let expected = Vector::from_slice(&[3.0 / 13.0, 4.0 / 13.0, 12.0 / 13.0]);
+        for i in 0..3 {
+            assert!((distance.gradient[i] - expected[i]).abs() < 1e-15);
+        }
+    }
+
+    #[test]
+    fn test_lift() {
+        // rho = P/T through its partial derivatives
+        let [p, t] = Dual::variables([2.0, 4.0]);
+        let rho = Dual::lift(0.5, &[(0.25, &p), (-0.125, &t)]);
+        assert_eq!(rho, p / t);
+        let constant = Dual::<[Scalar; 2]>::constant(3.0);
+        assert_eq!((p.powf(constant)).gradient, [12.0, 0.0]);
+        assert_eq!(
+            <Dual<Scalar> as NumCast>::from(2u8),
+            Some(Dual::constant(2.0))
+        );
+    }
+
+    #[test]
+    fn test_constant_at_singularity() {
+        // Infinite derivatives at 0 leave constants constant.
+        let zero = Dual::<[Scalar; 2]>::constant(0.0);
+        assert_eq!(zero.sqrt(), Dual::constant(0.0));
+        assert_eq!(zero.cbrt(), Dual::constant(0.0));
+        assert_eq!(zero.powi(0), Dual::constant(1.0));
+        assert_eq!(zero.powf(Dual::constant(0.5)), Dual::constant(0.0));
+        // x^0 = 1 of a variable x too
+        let [x, _] = Dual::variables([0.0, 1.0]);
+        assert_eq!(x.powi(0), Dual::constant(1.0));
+        assert_eq!(x.sqrt().gradient, [Scalar::INFINITY, 0.0]);
+    }
+}
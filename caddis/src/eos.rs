@@ -0,0 +1,430 @@
+//! Equations of state for stellar matter, giving the density as a function
+//! of pressure and temperature as the Henyey method needs it.
+//!
+//! All quantities are SI. Mean molecular weights are dimensionless, in units
+//! of the atomic mass constant.
+
+use crate::units::{MOLAR_GAS_CONSTANT, MOLAR_MASS_CONSTANT, RADIATION_DENSITY_CONSTANT};
+
+type Scalar = f64;
+
+/// Molar gas constant divided by the molar mass constant (J kg^-1 K^-1)
+const GAS_CONSTANT: Scalar = MOLAR_GAS_CONSTANT / MOLAR_MASS_CONSTANT;
+/// Non-relativistic and extreme relativistic degenerate electron pressure
+/// coefficients, P = K (rho/mu_e)^(5/3) and P = K (rho/mu_e)^(4/3)
+const NON_RELATIVISTIC_DEGENERACY: Scalar = 1.003_6e7;
+const RELATIVISTIC_DEGENERACY: Scalar = 1.243_5e10;
+
+/// Smallest ratio of gas to total pressure; see `GasAndRadiation`
+const MIN_BETA: Scalar = 1e-3;
+
+const INVERSION_TOLERANCE: Scalar = 1e-14;
+const INVERSION_MAX_ITERATIONS: usize = 100;
+
+/// Mass fractions of hydrogen, helium and metals of a fully ionized mixture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Composition {
+    pub x: Scalar,
+    pub y: Scalar,
+    pub z: Scalar,
+}
+
+impl Composition {
+    pub fn new(x: Scalar, y: Scalar, z: Scalar) -> Self {
+        Composition { x, y, z }
+    }
+
+    pub fn solar() -> Self {
+        Composition::new(0.70, 0.28, 0.02)
+    }
+
+    /// Mean molecular weight per free particle, ions and electrons.
+    pub fn mean_molecular_weight(&self) -> Scalar {
+        1.0 / (2.0 * self.x + 0.75 * self.y + 0.5 * self.z)
+    }
+
+    /// Mean molecular weight per free electron.
+    pub fn electron_molecular_weight(&self) -> Scalar {
+        2.0 / (1.0 + self.x)
+    }
+
+    /// Mean molecular weight per ion, consistent with the two above.
+    pub fn ion_molecular_weight(&self) -> Scalar {
+        1.0 / (1.0 / self.mean_molecular_weight() - 1.0 / self.electron_molecular_weight())
+    }
+}
+
+pub trait EquationOfState {
+    /// Density and its partial derivatives with respect to pressure and
+    /// temperature, `[rho, drho/dP, drho/dT]`.
+    fn density(&self, pressure: Scalar, temperature: Scalar) -> [Scalar; 3];
+
+    /// Mean molecular weight of the mixture, used to set up starting models.
+    fn mean_molecular_weight(&self) -> Scalar;
+
+    /// The same equation of state for another composition, as burning
+    /// changes it.
+    fn with_composition(&self, composition: &Composition) -> Box<dyn EquationOfState>;
+
+    /// Adiabatic temperature gradient (d ln T / d ln P)_s, by default that of
+    /// a monatomic ideal gas.
+    fn adiabatic_gradient(&self, _pressure: Scalar, _temperature: Scalar) -> Scalar {
+        0.4
+    }
+
+    /// Specific heat at constant pressure, by default that of a monatomic
+    /// ideal gas.
+    fn specific_heat(&self, _pressure: Scalar, _temperature: Scalar) -> Scalar {
+        2.5 * GAS_CONSTANT / self.mean_molecular_weight()
+    }
+}
+
+/// Classical ideal gas, P = rho R T / mu.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IdealGas {
+    pub mu: Scalar,
+}
+
+impl IdealGas {
+    pub fn new(mu: Scalar) -> Self {
+        IdealGas { mu }
+    }
+
+    pub fn from_composition(composition: &Composition) -> Self {
+        IdealGas::new(composition.mean_molecular_weight())
+    }
+}
+
+impl EquationOfState for IdealGas {
+    fn density(&self, pressure: Scalar, temperature: Scalar) -> [Scalar; 3] {
+        let rho_p = self.mu / (GAS_CONSTANT * temperature);
+        let rho = rho_p * pressure;
+        [rho, rho_p, -rho / temperature]
+    }
+
+    fn mean_molecular_weight(&self) -> Scalar {
+        self.mu
+    }
+
+    fn with_composition(&self, composition: &Composition) -> Box<dyn EquationOfState> {
+        Box::new(IdealGas::from_composition(composition))
+    }
+}
+
+/// Ideal gas plus black body radiation, P = rho R T / mu + a T^4 / 3.
+///
+/// Where radiation alone would exceed the pressure, as in Newton iterates
+/// or trial steps that overshoot, the gas pressure is clamped at `MIN_BETA`
+/// of the total so that the density stays positive, with the partials of
+/// the clamped density. No star in equilibrium comes near the clamp.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GasAndRadiation {
+    pub gas: IdealGas,
+}
+
+impl GasAndRadiation {
+    pub fn new(mu: Scalar) -> Self {
+        GasAndRadiation {
+            gas: IdealGas::new(mu),
+        }
+    }
+
+    pub fn from_composition(composition: &Composition) -> Self {
+        GasAndRadiation {
+            gas: IdealGas::from_composition(composition),
+        }
+    }
+
+    pub fn radiation_pressure(temperature: Scalar) -> Scalar {
+        RADIATION_DENSITY_CONSTANT * temperature.powi(4) / 3.0
+    }
+
+    /// Ratio of gas to total pressure, at least `MIN_BETA`.
+    pub fn beta(&self, pressure: Scalar, temperature: Scalar) -> Scalar {
+        (1.0 - Self::radiation_pressure(temperature) / pressure).max(MIN_BETA)
+    }
+}
+
+impl EquationOfState for GasAndRadiation {
+    fn density(&self, pressure: Scalar, temperature: Scalar) -> [Scalar; 3] {
+        let p_rad = Self::radiation_pressure(temperature);
+        if pressure - p_rad < MIN_BETA * pressure {
+            let [rho, rho_p, rho_t] = self.gas.density(MIN_BETA * pressure, temperature);
+            return [rho, MIN_BETA * rho_p, rho_t];
+        }
+        let [rho, rho_p, _] = self.gas.density(pressure - p_rad, temperature);
+        let rho_t = -rho / temperature - rho_p * 4.0 * p_rad / temperature;
+        [rho, rho_p, rho_t]
+    }
+
+    fn mean_molecular_weight(&self) -> Scalar {
+        self.gas.mu
+    }
+
+    fn with_composition(&self, composition: &Composition) -> Box<dyn EquationOfState> {
+        Box::new(GasAndRadiation::from_composition(composition))
+    }
+
+    /// nabla_ad = (1 + (1 - beta)(4 + beta)/beta^2)
+    ///     / (5/2 + 4 (1 - beta)(4 + beta)/beta^2)
+    fn adiabatic_gradient(&self, pressure: Scalar, temperature: Scalar) -> Scalar {
+        let beta = self.beta(pressure, temperature);
+        let x = (1.0 - beta) * (4.0 + beta) / (beta * beta);
+        (1.0 + x) / (2.5 + 4.0 * x)
+    }
+
+    /// c_P = R/mu (3/2 + 3 (4 + beta)(1 - beta)/beta^2 + (4 - 3 beta)/beta^2)
+    fn specific_heat(&self, pressure: Scalar, temperature: Scalar) -> Scalar {
+        let beta = self.beta(pressure, temperature);
+        GAS_CONSTANT / self.gas.mu
+            * (1.5 + (3.0 * (4.0 + beta) * (1.0 - beta) + 4.0 - 3.0 * beta) / (beta * beta))
+    }
+}
+
+/// Ideal ions and an electron gas of arbitrary degeneracy and relativity.
+///
+/// The electron pressure interpolates between the classical and degenerate
+/// limits, and the degenerate pressure between the non-relativistic and
+/// extreme relativistic limits, each as
+///
+/// ```text
+/// P = (P_1^2 + P_2^2)^(1/2)        P_deg = (P_nr^-2 + P_r^-2)^(-1/2)
+/// ```
+///
+/// which is accurate to a few percent everywhere. A cold gas drops the
+/// thermal pressure of ions and electrons, as inside a white dwarf.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DegenerateGas {
+    /// Mean molecular weights per ion and per electron
+    pub mu_ion: Scalar,
+    pub mu_e: Scalar,
+    /// Whether the thermal pressure is included
+    pub thermal: bool,
+}
+
+impl DegenerateGas {
+    pub fn new(composition: &Composition) -> Self {
+        DegenerateGas {
+            mu_ion: composition.ion_molecular_weight(),
+            mu_e: composition.electron_molecular_weight(),
+            thermal: true,
+        }
+    }
+
+    /// Fully degenerate electrons at zero temperature.
+    pub fn cold(composition: &Composition) -> Self {
+        DegenerateGas {
+            thermal: false,
+            ..DegenerateGas::new(composition)
+        }
+    }
+
+    /// Pressure and its partial derivatives with respect to density and
+    /// temperature, `[P, dP/drho, dP/dT]`.
+    pub fn pressure(&self, rho: Scalar, temperature: Scalar) -> [Scalar; 3] {
+        let x = rho / self.mu_e;
+        let p_nr = NON_RELATIVISTIC_DEGENERACY * x.powf(5.0 / 3.0);
+        let p_r = RELATIVISTIC_DEGENERACY * x.powf(4.0 / 3.0);
+        let p_deg = (p_nr.powi(-2) + p_r.powi(-2)).powf(-0.5);
+        let p_deg_rho =
+            p_deg.powi(3) * (5.0 / 3.0 * p_nr.powi(-2) + 4.0 / 3.0 * p_r.powi(-2)) / rho;
+        if !self.thermal {
+            return [p_deg, p_deg_rho, 0.0];
+        }
+        let p_ion = rho * GAS_CONSTANT * temperature / self.mu_ion;
+        let p_classical = rho * GAS_CONSTANT * temperature / self.mu_e;
+        let p_e = p_classical.hypot(p_deg);
+        [
+            p_ion + p_e,
+            p_ion / rho + (p_classical * p_classical / rho + p_deg * p_deg_rho) / p_e,
+            p_ion / temperature + p_classical * p_classical / (temperature * p_e),
+        ]
+    }
+}
+
+impl DegenerateGas {
+    /// Heat capacity at constant volume at `rho` and `temperature`: ideal
+    /// ions, and electrons whose classical 3/2 R/mu_e is weighted by their
+    /// share (P_classical/P_e)^2 of the thermal pressure, which vanishes as
+    /// they become degenerate.
+    pub fn heat_capacity(&self, rho: Scalar, temperature: Scalar) -> Scalar {
+        let ions = 1.5 * GAS_CONSTANT / self.mu_ion;
+        if !self.thermal {
+            return ions;
+        }
+        let p_deg = self.pressure(rho, 0.0)[0];
+        let p_classical = rho * GAS_CONSTANT * temperature / self.mu_e;
+        let share = p_classical * p_classical / (p_classical * p_classical + p_deg * p_deg);
+        ions + 1.5 * GAS_CONSTANT / self.mu_e * share
+    }
+
+    /// `[Gamma_1, Gamma_3 - 1, chi_rho, c_V]` at `pressure` and
+    /// `temperature`, from
+    ///
+    /// ```text
+    /// Gamma_3 - 1 = P chi_T/(rho T c_V)    Gamma_1 = chi_rho + chi_T (Gamma_3 - 1)
+    /// ```
+    ///
+    /// with chi_rho and chi_T the logarithmic derivatives of `pressure`.
+    fn exponents(&self, pressure: Scalar, temperature: Scalar) -> [Scalar; 4] {
+        let rho = self.density(pressure, temperature)[0];
+        let [p, p_rho, p_t] = self.pressure(rho, temperature);
+        let (chi_rho, chi_t) = (rho * p_rho / p, temperature * p_t / p);
+        let c_v = self.heat_capacity(rho, temperature);
+        let gamma_3 = p * chi_t / (rho * temperature * c_v);
+        [chi_rho + chi_t * gamma_3, gamma_3, chi_rho, c_v]
+    }
+}
+
+impl EquationOfState for DegenerateGas {
+    /// Inverts `pressure` by Newton iteration on ln rho, along which ln P is
+    /// nearly linear with slope between 1 and 5/3.
+    fn density(&self, pressure: Scalar, temperature: Scalar) -> [Scalar; 3] {
+        let mut rho = self.mu_e * (pressure / NON_RELATIVISTIC_DEGENERACY).powf(0.6);
+        if self.thermal {
+            rho = rho.min(pressure * self.mean_molecular_weight() / (GAS_CONSTANT * temperature));
+        }
+        let mut p = self.pressure(rho, temperature);
+        for _ in 0..INVERSION_MAX_ITERATIONS {
+            let step = (pressure / p[0]).ln() * p[0] / (rho * p[1]);
+            rho *= step.exp();
+            p = self.pressure(rho, temperature);
+            if step.abs() < INVERSION_TOLERANCE {
+                break;
+            }
+        }
+        [rho, 1.0 / p[1], -p[2] / p[1]]
+    }
+
+    fn mean_molecular_weight(&self) -> Scalar {
+        1.0 / (1.0 / self.mu_ion + 1.0 / self.mu_e)
+    }
+
+    fn with_composition(&self, composition: &Composition) -> Box<dyn EquationOfState> {
+        Box::new(DegenerateGas {
+            thermal: self.thermal,
+            ..DegenerateGas::new(composition)
+        })
+    }
+
+    /// nabla_ad = (Gamma_3 - 1)/Gamma_1, from 2/5 for a classical gas to
+    /// that of the ions alone in degenerate matter, and zero for the cold
+    /// gas, whose pressure does not depend on T.
+    fn adiabatic_gradient(&self, pressure: Scalar, temperature: Scalar) -> Scalar {
+        let [gamma_1, gamma_3, _, _] = self.exponents(pressure, temperature);
+        gamma_3 / gamma_1
+    }
+
+    /// c_P = c_V Gamma_1/chi_rho, which tends to c_V of the ions alone in
+    /// degenerate matter.
+    fn specific_heat(&self, pressure: Scalar, temperature: Scalar) -> Scalar {
+        let [gamma_1, _, chi_rho, c_v] = self.exponents(pressure, temperature);
+        c_v * gamma_1 / chi_rho
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_partials<E: EquationOfState>(eos: &E, pressure: Scalar, temperature: Scalar) {
+        let [rho, rho_p, rho_t] = eos.density(pressure, temperature);
+        let (dp, dt) = (1e-6 * pressure, 1e-6 * temperature);
+        let fd_p = (eos.density(pressure + dp, temperature)[0]
+            - eos.density(pressure - dp, temperature)[0])
+            / (2.0 * dp);
+        let fd_t = (eos.density(pressure, temperature + dt)[0]
+            - eos.density(pressure, temperature - dt)[0])
+            / (2.0 * dt);
+        // Compare logarithmic derivatives, which are of order one.
+        assert!((fd_p - rho_p).abs() * pressure <= 1e-6 * rho);
+        assert!((fd_t - rho_t).abs() * temperature <= 1e-6 * rho);
+    }
+
+    #[test]
+    fn test_composition() {
+        let hydrogen = Composition::new(1.0, 0.0, 0.0);
+        assert!((hydrogen.mean_molecular_weight() - 0.5).abs() < 1e-15);
+        assert!((hydrogen.ion_molecular_weight() - 1.0).abs() < 1e-15);
+        let solar = Composition::solar();
+        assert!((solar.mean_molecular_weight() - 0.617).abs() < 1e-3);
+        assert!((solar.electron_molecular_weight() - 1.176).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_ideal_and_radiation() {
+        let gas = IdealGas::new(0.62);
+        let both = GasAndRadiation::new(0.62);
+        check_partials(&gas, 2.4e16, 1.5e7);
+        check_partials(&both, 2.4e16, 1.5e7);
+        // Radiation pressure is negligible at the centre of the Sun but not
+        // in a massive star.
+        assert!(both.beta(2.4e16, 1.5e7) > 0.999);
+        assert!(both.beta(1e14, 3e7) < 0.9);
+        // Radiation lowers nabla_ad from 2/5 towards 1/4.
+        assert!((both.adiabatic_gradient(2.4e16, 1.5e7) - 0.4).abs() < 1e-3);
+        assert!(both.adiabatic_gradient(1e14, 3e7) < 0.35);
+        assert!(
+            (both.specific_heat(2.4e16, 1.5e7) / gas.specific_heat(2.4e16, 1.5e7) - 1.0).abs()
+                < 1e-2
+        );
+        let rho = gas.density(2.4e16, 1.5e7)[0];
+        assert!((rho - 2.4e16 * 0.62 / (GAS_CONSTANT * 1.5e7)).abs() < 1e-12 * rho);
+    }
+
+    #[test]
+    fn test_radiation_dominated() {
+        // P_rad(1e8 K) = 2.5e16 Pa exceeds the pressure: the density stays
+        // positive, at the clamped ratio of gas pressure.
+        let both = GasAndRadiation::new(0.62);
+        let (pressure, temperature) = (1e16, 1e8);
+        assert!(GasAndRadiation::radiation_pressure(temperature) > pressure);
+        let [rho, rho_p, rho_t] = both.density(pressure, temperature);
+        let clamped = IdealGas::new(0.62).density(MIN_BETA * pressure, temperature)[0];
+        assert!(rho > 0.0 && rho_p > 0.0 && rho_t < 0.0);
+        assert!((rho - clamped).abs() < 1e-12 * rho);
+        assert_eq!(both.beta(pressure, temperature), MIN_BETA);
+        check_partials(&both, pressure, temperature);
+        assert!(both.adiabatic_gradient(pressure, temperature).is_finite());
+    }
+
+    #[test]
+    fn test_degenerate() {
+        let composition = Composition::new(0.0, 0.0, 1.0);
+        let hot = DegenerateGas::new(&composition);
+        let cold = DegenerateGas::cold(&composition);
+        for &(pressure, temperature) in &[(1e10, 1e7), (1e22, 1e7), (1e26, 1e6), (1e12, 1e5)] {
+            check_partials(&hot, pressure, temperature);
+            let [rho, _, _] = hot.density(pressure, temperature);
+            assert!((hot.pressure(rho, temperature)[0] - pressure).abs() < 1e-10 * pressure);
+        }
+        check_partials(&cold, 1e22, 1e7);
+
+        // Classical at low density, degenerate and cold at white dwarf
+        // densities.
+        let ideal = IdealGas::new(hot.mean_molecular_weight());
+        let rho = hot.density(1e10, 1e7)[0];
+        assert!((rho / ideal.density(1e10, 1e7)[0] - 1.0).abs() < 1e-3);
+        let rho = hot.density(1e22, 1e6)[0];
+        assert!((rho / cold.density(1e22, 1e6)[0] - 1.0).abs() < 1e-3);
+        assert!(rho > 1e8 && rho < 1e10);
+
+        // A classical gas has the ideal gas nabla_ad and c_P; degenerate
+        // electrons add almost nothing to the heat capacity of the ions.
+        let helium = Composition::new(0.0, 1.0, 0.0);
+        let (hot, cold) = (DegenerateGas::new(&helium), DegenerateGas::cold(&helium));
+        let gas = IdealGas::new(hot.mean_molecular_weight());
+        assert!((hot.adiabatic_gradient(1e10, 1e7) - 0.4).abs() < 1e-3);
+        assert!((hot.specific_heat(1e10, 1e7) / gas.specific_heat(1e10, 1e7) - 1.0).abs() < 1e-2);
+        let ions = 1.5 * GAS_CONSTANT / hot.mu_ion;
+        assert!((hot.specific_heat(1e22, 1e6) / ions - 1.0).abs() < 1e-2);
+        assert!(hot.specific_heat(1e22, 1e6) < 0.5 * gas.specific_heat(1e22, 1e6));
+        // The ions' Gamma_3 - 1 = 2/3 over the electrons' chi_rho, between
+        // 4/3 and 5/3
+        let chi_rho = hot.exponents(1e22, 1e6)[2];
+        assert!(chi_rho > 4.0 / 3.0 && chi_rho < 5.0 / 3.0);
+        assert!((hot.adiabatic_gradient(1e22, 1e6) - 2.0 / 3.0 / chi_rho).abs() < 1e-3);
+        assert_eq!(cold.adiabatic_gradient(1e22, 1e6), 0.0);
+        assert!((cold.specific_heat(1e22, 1e6) / ions - 1.0).abs() < 1e-12);
+    }
+}
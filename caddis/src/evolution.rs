@@ -0,0 +1,329 @@
+//! Evolution of a star through a sequence of Henyey models.
+//!
+//! Each step burns the composition of every interval at the structure of
+//! the last model, mixing convective zones, and then relaxes the structure
+//! to the new composition with the gravothermal release -T ds/dt of the
+//! step in the luminosity equation. The step adapts to keep the changes of
+//! composition and structure between models small, and the shells may be
+//! rezoned after each step.
+
+use crate::henyey::{Henyey, HenyeyError, Model};
+use crate::nuclear::{Network, HYDROGEN};
+use crate::profile::Profile;
+use crate::units::QuantityValue;
+
+type Scalar = f64;
+
+/// Julian year (s)
+const YEAR: Scalar = 3.155_76e7;
+
+/// Largest change of a hydrogen mass fraction aimed at in one step
+const MAX_HYDROGEN_CHANGE: Scalar = 0.02;
+/// Largest change of ln r, ln P or ln T aimed at in one step
+const MAX_STRUCTURE_CHANGE: Scalar = 0.05;
+/// Largest factor by which the step grows
+const MAX_GROWTH: Scalar = 2.0;
+/// Halvings of a step whose structure fails to converge before giving up
+const MAX_RETRIES: usize = 8;
+
+/// State of the star at one age.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Age (s)
+    pub age: Scalar,
+    pub luminosity: Scalar,
+    pub radius: Scalar,
+    pub effective_temperature: Scalar,
+    /// Hydrogen mass fraction of each interval, from the centre outwards
+    pub hydrogen: Vec<Scalar>,
+    pub model: Model,
+}
+
+/// Time-dependent driver of a Henyey model, keeping the history of models.
+pub struct Evolution {
+    henyey: Henyey,
+    /// Next time step (s)
+    dt: Scalar,
+    /// Largest change between neighbouring shells kept by rezoning, if any
+    max_change: Option<Scalar>,
+    history: Vec<Snapshot>,
+}
+
+impl Evolution {
+    /// Converges `henyey` to a zero age model of uniform composition
+    /// `network`, to be evolved with a first step of `dt` seconds. Like any
+    /// model with nuclear energy generation, `henyey` should start close to
+    /// the solution.
+    pub fn new(mut henyey: Henyey, network: Network, dt: Scalar) -> Result<Self, HenyeyError> {
+        henyey.set_composition(vec![network; henyey.shells() - 1]);
+        let model = henyey.solve()?;
+        let mut evolution = Evolution {
+            henyey,
+            dt,
+            max_change: None,
+            history: Vec::new(),
+        };
+        let zero_age = evolution.snapshot(0.0, model);
+        evolution.history.push(zero_age);
+        Ok(evolution)
+    }
+
+    /// Rezones the shells after every step, see `Henyey::rezone`.
+    pub fn with_rezoning(mut self, max_change: Scalar) -> Self {
+        self.max_change = Some(max_change);
+        self
+    }
+
+    pub fn history(&self) -> &[Snapshot] {
+        &self.history
+    }
+
+    pub fn last(&self) -> &Snapshot {
+        self.history.last().unwrap()
+    }
+
+    pub fn age(&self) -> Scalar {
+        self.last().age
+    }
+
+    /// Advances the star by one step, halving the step until the structure
+    /// converges, also after rezoning.
+    pub fn step(&mut self) -> Result<&Snapshot, HenyeyError> {
+        let last = self.last().clone();
+        let composition = self.henyey.composition().to_vec();
+        let mut error = HenyeyError::Singular;
+        for _ in 0..=MAX_RETRIES {
+            match self.advance(&last) {
+                Ok((snapshot, growth)) => {
+                    self.dt *= growth;
+                    self.history.push(snapshot);
+                    return Ok(self.last());
+                }
+                Err(e) => {
+                    error = e;
+                    self.henyey.restore(&last.model);
+                    self.henyey.set_composition(composition.clone());
+                    self.dt *= 0.5;
+                }
+            }
+        }
+        Err(error)
+    }
+
+    /// Burns and relaxes the structure over a step of `dt` from `last`,
+    /// rezoning if asked to, and returns the new snapshot with the factor
+    /// by which to scale the step.
+    fn advance(&mut self, last: &Snapshot) -> Result<(Snapshot, Scalar), HenyeyError> {
+        let age = last.age + self.dt;
+        self.henyey.burn(self.dt);
+        self.henyey.set_time_step(self.dt, &last.model);
+        let model = self.henyey.solve()?;
+        let mut snapshot = self.snapshot(age, model);
+        let growth = Self::growth(last, &snapshot);
+        if let Some(max_change) = self.max_change {
+            if self.henyey.rezone(max_change) {
+                self.henyey.set_time_step(self.dt, &last.model);
+                let model = self.henyey.solve()?;
+                snapshot = self.snapshot(age, model);
+            }
+        }
+        Ok((snapshot, growth))
+    }
+
+    /// Steps until the star reaches `age`, shortening the last step to end
+    /// there.
+    pub fn evolve(&mut self, age: Scalar) -> Result<(), HenyeyError> {
+        while self.age() < age {
+            self.dt = self.dt.min(age - self.age());
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// Hertzsprung-Russell track, log T_eff and log L/L_sun of every model.
+    pub fn track(&self) -> Vec<[Scalar; 2]> {
+        let solar_luminosity = QuantityValue::solar_luminosity().number;
+        self.history
+            .iter()
+            .map(|s| {
+                [
+                    s.effective_temperature.log10(),
+                    (s.luminosity / solar_luminosity).log10(),
+                ]
+            })
+            .collect()
+    }
+
+    /// History in the columns and units of MESA's `history.data`: age in
+    /// years, solar units and log10.
+    pub fn history_data(&self) -> Profile {
+        let names = [
+            "model_number",
+            "star_age",
+            "log_L",
+            "log_Teff",
+            "log_R",
+            "center_h1",
+            "num_zones",
+        ];
+        let mass = *self.last().model.m.last().unwrap();
+        let solar_luminosity = QuantityValue::solar_luminosity().number;
+        let solar_radius = QuantityValue::solar_radius().number;
+        let mut profile = Profile::new(&names).with_header("initial_mass", mass);
+        for (i, s) in self.history.iter().enumerate() {
+            profile.push(&[
+                (i + 1) as Scalar,
+                s.age / YEAR,
+                (s.luminosity / solar_luminosity).log10(),
+                s.effective_temperature.log10(),
+                (s.radius / solar_radius).log10(),
+                s.hydrogen[0],
+                s.model.m.len() as Scalar,
+            ]);
+        }
+        profile
+    }
+
+    fn snapshot(&self, age: Scalar, model: Model) -> Snapshot {
+        Snapshot {
+            age,
+            luminosity: *model.l.last().unwrap(),
+            radius: *model.r.last().unwrap(),
+            effective_temperature: self.henyey.effective_temperature(),
+            hydrogen: self
+                .henyey
+                .composition()
+                .iter()
+                .map(|network| network.abundances[HYDROGEN])
+                .collect(),
+            model,
+        }
+    }
+
+    /// Factor by which to scale the step after going from `previous` to
+    /// `next`, aiming at the largest changes allowed.
+    fn growth(previous: &Snapshot, next: &Snapshot) -> Scalar {
+        let hydrogen = previous
+            .hydrogen
+            .iter()
+            .zip(next.hydrogen.iter())
+            .fold(0.0, |a: Scalar, (x, y)| a.max((x - y).abs()));
+        let (a, b) = (&previous.model, &next.model);
+        let structure = [(&a.r, &b.r), (&a.P, &b.P), (&a.T, &b.T)]
+            .iter()
+            .flat_map(|(a, b)| a.iter().zip(b.iter()))
+            .filter(|(x, y)| **x > 0.0 && **y > 0.0)
+            .fold(0.0, |a: Scalar, (x, y)| a.max((y / x).ln().abs()));
+        (MAX_HYDROGEN_CHANGE / hydrogen)
+            .min(MAX_STRUCTURE_CHANGE / structure)
+            .min(MAX_GROWTH)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eos::{Composition, IdealGas};
+    use crate::opacity::{self, Opacity};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// Nominal solar mass, radius and luminosity in SI units
+    fn solar() -> [Scalar; 3] {
+        [
+            QuantityValue::solar_mass(),
+            QuantityValue::solar_radius(),
+            QuantityValue::solar_luminosity(),
+        ]
+        .map(|q| q.number)
+    }
+
+    /// Solar opacity that counts its calls and fails call `fail` with NaN
+    struct Flaky {
+        calls: Rc<Cell<usize>>,
+        fail: Rc<Cell<usize>>,
+    }
+
+    impl Opacity for Flaky {
+        fn opacity(&self, rho: Scalar, temperature: Scalar) -> [Scalar; 3] {
+            self.calls.set(self.calls.get() + 1);
+            if self.calls.get() == self.fail.get() {
+                return [Scalar::NAN; 3];
+            }
+            opacity::analytic(&Composition::solar(), 10.0).opacity(rho, temperature)
+        }
+    }
+
+    #[test]
+    fn test_rezone_failure() {
+        let network = Network::from_composition(&Composition::solar());
+        let [mass, radius, luminosity] = solar();
+        let (calls, fail) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(usize::MAX)));
+        let evolution = || {
+            let kappa = Flaky {
+                calls: calls.clone(),
+                fail: fail.clone(),
+            };
+            let mut h = Henyey::new(30usize, mass, radius)
+                .with_luminosity(1.5 * luminosity)
+                .with_opacity(kappa)
+                .with_equation_of_state(IdealGas::from_composition(&Composition::solar()));
+            h.solve().unwrap();
+            let h = h.with_energy_generation(network);
+            Evolution::new(h, network, 1e7 * YEAR).unwrap()
+        };
+        // Calls up to the rezoning, which are the same with and without it
+        let mut fixed = evolution();
+        calls.set(0);
+        fixed.step().unwrap();
+        let before = calls.get();
+
+        // Failing the solve after rezoning halves the step like any other.
+        let mut rezoned = evolution().with_rezoning(0.3);
+        calls.set(0);
+        fail.set(before + 1);
+        rezoned.step().unwrap();
+        assert!(calls.get() > before + 1);
+        assert_eq!(rezoned.history().len(), 2);
+        assert!((rezoned.age() / (0.5e7 * YEAR) - 1.0).abs() < 1e-12);
+        let model = &rezoned.last().model;
+        assert_eq!(rezoned.last().hydrogen.len() + 1, model.m.len());
+        assert!(model.r.iter().chain(&model.P).all(|x| x.is_finite()));
+    }
+
+    #[test]
+    fn test_main_sequence() {
+        let network = Network::from_composition(&Composition::solar());
+        let [mass, radius, luminosity] = solar();
+        let mut h = Henyey::new(30usize, mass, radius)
+            .with_luminosity(1.5 * luminosity)
+            .with_equation_of_state(IdealGas::from_composition(&Composition::solar()));
+        h.solve().unwrap();
+        let mut h = h.with_energy_generation(network);
+        h.solve().unwrap();
+        let mut evolution = Evolution::new(h, network, 1e7 * YEAR)
+            .unwrap()
+            .with_rezoning(0.3);
+        evolution.evolve(2e9 * YEAR).unwrap();
+
+        let history = evolution.history();
+        let (zams, last) = (&history[0], evolution.last());
+        assert!(history.len() > 5);
+        assert!((last.age - 2e9 * YEAR).abs() < 1.0);
+        // Hydrogen is burnt fastest at the centre and the Sun brightens.
+        assert!(last.hydrogen[0] < 0.6 && last.hydrogen[0] > 0.3);
+        let n = last.hydrogen.len();
+        assert!(n > 29 && last.model.m.len() == n + 1);
+        assert!(last.hydrogen[0] < last.hydrogen[n / 3]);
+        assert!((last.hydrogen[n - 1] - 0.7).abs() < 1e-3);
+        assert!(last.luminosity > 1.05 * zams.luminosity);
+        assert!(last.model.T[0] > zams.model.T[0]);
+        let track = evolution.track();
+        assert_eq!(track.len(), history.len());
+        assert!(track.windows(2).all(|w| w[1][1] > w[0][1]));
+        let data = evolution.history_data();
+        assert_eq!(data.len(), history.len());
+        assert_eq!(data.column("log_L").unwrap()[0], track[0][1]);
+        assert!((data.column("star_age").unwrap()[data.len() - 1] - 2e9).abs() < 1e-6);
+    }
+}
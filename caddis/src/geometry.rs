@@ -412,6 +412,61 @@ impl Sphere {
     pub fn height(self, point: Vector) -> Scalar {
         (point - self.center.unwrap()).norm() - self.radius.unwrap()
     }
+
+    pub fn center(&self) -> Vector {
+        self.center.clone().unwrap_or_else(Vector::new)
+    }
+
+    pub fn radius(&self) -> Scalar {
+        self.radius.unwrap()
+    }
+}
+
+/// Right circular cylinder of given radius and length, centred on `center`
+/// with its axis along `axis`.
+pub struct Cylinder {
+    pub center: Vector,
+    pub axis: Vector,
+    pub radius: Scalar,
+    pub length: Scalar,
+}
+
+impl Cylinder {
+    pub fn new(center: &Vector, axis: &Vector, radius: Scalar, length: Scalar) -> Self {
+        Cylinder {
+            center: center.clone(),
+            axis: axis.normalize(),
+            radius,
+            length,
+        }
+    }
+
+    pub fn volume(&self) -> Scalar {
+        self.radius.powi(2) * consts::PI * self.length
+    }
+
+    pub fn area(&self) -> Scalar {
+        2.0 * consts::PI * self.radius * (self.radius + self.length)
+    }
+}
+
+/// Rectangular box centred on `center` with edges along the coordinate axes.
+pub struct Cuboid {
+    pub center: Vector,
+    pub half_extents: Vector,
+}
+
+impl Cuboid {
+    pub fn new(center: &Vector, size: &Vector) -> Self {
+        Cuboid {
+            center: center.clone(),
+            half_extents: size.clone() * 0.5,
+        }
+    }
+
+    pub fn volume(&self) -> Scalar {
+        8.0 * self.half_extents[0] * self.half_extents[1] * self.half_extents[2]
+    }
 }
 
 #[allow(dead_code)]
@@ -1,155 +1,1311 @@
-#![allow(warnings, unsued)]
-use rgsl::types::matrix::MatrixF64;
-//use rgsl::types::matrix::MatrixF64View;
+#![allow(non_snake_case)]
 use rgsl::types::vector::VectorF64;
-use rgsl::types::vector::VectorF64View;
 use std::f64::consts::PI;
+use std::fmt;
 
-const NEWTONIAN_CONSTANT_OF_GRAVITATION: f64 = 6.674_30e-11;
-const MOLAR_GAS_CONSTANT: f64 = 8.314_462_618;
-const MEAN_MOLECULAR_WEIGHT: f64 = 0.5;
+use crate::convection::{Convection, Criterion};
+use crate::dual::Dual;
+use crate::eos::{Composition, EquationOfState, IdealGas};
+use crate::jacobian::{self, Comparison};
+use crate::nuclear::{EnergyGeneration, Network, SPECIES};
+use crate::opacity::{self, Opacity};
+use crate::polytrope::Polytrope;
+use crate::profile::Profile;
+use crate::roots;
+use crate::tridiagonal::BlockTridiagonal;
+use crate::units::{
+    DimensionError, QuantityValue, MOLAR_GAS_CONSTANT, MOLAR_MASS_CONSTANT,
+    NEWTONIAN_CONSTANT_OF_GRAVITATION, RADIATION_DENSITY_CONSTANT, SPEED_OF_LIGHT,
+    STEFAN_BOLTZMANN_CONSTANT,
+};
+
+/// Opacity of the photosphere of starting models (m^2 kg^-1)
+const PHOTOSPHERE_OPACITY: f64 = 0.034;
+/// Guillotine factor of the default bound-free opacity
+const GUILLOTINE: f64 = 10.0;
+
+/// Largest change of any logarithmic variable allowed in one Newton step
+const MAX_CORRECTION: f64 = 0.5;
+const TOLERANCE: f64 = 1e-8;
+const MAX_ITERATIONS: usize = 100;
+/// First change of ln L in the search for a fixed radius
+const LUMINOSITY_PROBE: f64 = 0.1;
+/// Largest change of ln L in one step of that search
+const MAX_LUMINOSITY_STEP: f64 = 1.0;
+/// Smallest interval rezoning creates, as a fraction of the mass
+const MIN_INTERVAL: f64 = 1e-10;
+/// Largest difference of any abundance between intervals rezoning merges
+const MAX_MERGED_ABUNDANCE_CHANGE: f64 = 1e-3;
+
+/// Outer boundary condition closing the Henyey matrix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Surface {
+    /// P = 0 and T = 0 at the outermost shell
+    Zero,
+    /// Eddington grey photosphere at optical depth 2/3: T = T_eff and
+    /// P = 2 G M / (3 r^2 kappa)
+    Grey,
+    /// P and T of a fitted model atmosphere
+    Fitted(Atmosphere),
+}
+
+/// Linear solver for the Newton corrections.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Solver {
+    /// Block Thomas elimination of the block-tridiagonal Henyey matrix,
+    /// linear in K
+    Block,
+    /// Dense LU decomposition, cubic in K, for verification
+    Dense,
+}
+
+/// Power-law fit to a grid of model atmospheres at the fitting point,
+///
+/// ```text
+/// P = pressure T_eff^a g^b
+/// T = temperature T_eff
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Atmosphere {
+    pub pressure: f64,
+    /// Exponents `a` of the effective temperature and `b` of the surface
+    /// gravity
+    pub exponents: [f64; 2],
+    pub temperature: f64,
+}
+
+impl Atmosphere {
+    pub fn new(pressure: f64, exponents: [f64; 2], temperature: f64) -> Self {
+        Atmosphere { pressure, exponents, temperature }
+    }
+
+    /// Fitted P and T for effective temperature `t_eff` and gravity `g`.
+    pub fn fit(&self, t_eff: f64, g: f64) -> [f64; 2] {
+        [
+            self.pressure * t_eff.powf(self.exponents[0]) * g.powf(self.exponents[1]),
+            self.temperature * t_eff,
+        ]
+    }
+}
 
 /// Henyey Matrix
+///
+/// Stellar structure on `K` shells in mass, from the centre (shell 0) to the
+/// surface (shell `K - 1`). Each shell carries radius `r`, pressure `P`,
+/// temperature `T` and luminosity `l`; at the centre `r` and `l` vanish, so
+/// the unknowns are
+///
+/// ```text
+/// Y = [P_0, T_0, r_1, P_1, T_1, l_1, ..., r_K-1, P_K-1, T_K-1, l_K-1]
+/// ```
+///
+/// The first shell is tied to the centre by the series expansions of the
+/// structure equations in m (`B`), the four structure equations are
+/// differenced between the remaining neighbouring shells (`A`) and two
+/// surface conditions close the system (`C`), giving 4K - 2 equations. The
+/// radius is an eigenvalue of the problem: `R` only sizes the starting model
+/// and the converged radius follows from `M` and `L`, unless `with_radius`
+/// fixes it and makes `L` the eigenvalue instead.
+///
+/// Floats are SI; `from_quantities` and `Model::quantities` convert from
+/// and to dimensioned quantities in any units.
 pub struct Henyey {
-    /// K - 1 shells (constant, integer)
+    /// K - 1 shells (integer, changed only by rezoning)
     K: usize,
     /// Total mass (constant)
     M: f64,
-    /// Total radius (constant)
+    /// Radius of the starting model (constant)
     R: f64,
+    /// Radius to which `solve` fits the luminosity, if fixed
+    radius: Option<f64>,
+    /// Total luminosity, released uniformly in mass unless there is an
+    /// energy source (constant)
+    L: f64,
+    /// Outer boundary condition
+    surface: Surface,
+    /// Equation of state, giving the density from P and T
+    eos: Box<dyn EquationOfState>,
+    /// Opacity, from the density and T
+    kappa: Box<dyn Opacity>,
+    /// Energy generation rate, from the density and T
+    energy: Option<Box<dyn EnergyGeneration>>,
+    /// Mixing-length convection in unstable layers, if any
+    convection: Option<Convection>,
+    /// Composition of each interval while evolving, interval `k` at index
+    /// `k - 1`; empty for a homogeneous star
+    composition: Vec<Network>,
+    /// Equation of state of each interval for its composition
+    layers: Vec<Box<dyn EquationOfState>>,
+    /// Time step and the averages of P and T over each interval at its
+    /// start, for the gravothermal energy release
+    previous: Option<(f64, Vec<[f64; 2]>)>,
+    /// Mass coordinate of each shell
+    m: Vec<f64>,
+    /// Polytrope of the starting model, if not a homogeneous sphere
+    polytrope: Option<Polytrope>,
+    /// Linear solver for the Newton corrections
+    solver: Solver,
 
-    /*
-    /// Pressure (variable)
-    P: f64,
-    /// Temperature (variable)
-    T: f64,
-    /// Net energy per second passing outward through a sphere of radius r.
-    l: f64,
-    /// Mass (variable)
-    m: f64,
-    /// Radius (variable)
-    r: f64,
-    */
-
-    /// Henyey matrix
+    /// Henyey matrix, with blocks of the unknowns of each shell and of the
+    /// equations coupling it to its neighbours: the first two centre
+    /// equations for shell 0, the last two equations of the interval below
+    /// and the first two of the interval above for every other shell
     Y: VectorF64,
-    pub H: MatrixF64,
+    pub H: BlockTridiagonal,
+    /// Residuals of the difference equations
+    F: VectorF64,
+    /// Characteristic magnitudes of r, P, T and l, by which rows are scaled
+    scale: [f64; 4],
+}
+
+/// Converged stellar model, one entry per shell from the centre outwards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Model {
+    pub m: Vec<f64>,
+    pub r: Vec<f64>,
+    pub P: Vec<f64>,
+    pub T: Vec<f64>,
+    pub l: Vec<f64>,
+    /// Whether the interval below each shell is convective
+    pub convective: Vec<bool>,
+    /// Newton iterations taken
+    pub iterations: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HenyeyError {
+    /// The linearised system could not be solved
+    Singular,
+    /// No convergence within the iteration limit; holds the last relative
+    /// correction, or the relative radius error of a fixed radius
+    NotConverged(f64),
+    /// A fixed radius with an energy source, which sets the luminosity
+    LuminosityNotFree,
+}
+
+impl Model {
+    /// m, r, P, T and l of shell `j` as quantities.
+    pub fn quantities(&self, j: usize) -> [QuantityValue; 5] {
+        [
+            QuantityValue::kg() * self.m[j],
+            QuantityValue::m() * self.r[j],
+            QuantityValue::Pa() * self.P[j],
+            QuantityValue::K() * self.T[j],
+            QuantityValue::W() * self.l[j],
+        ]
+    }
+
+    /// Radius of the outermost shell.
+    pub fn radius(&self) -> QuantityValue {
+        QuantityValue::m() * *self.r.last().unwrap()
+    }
+
+    /// Luminosity of the outermost shell.
+    pub fn luminosity(&self) -> QuantityValue {
+        QuantityValue::W() * *self.l.last().unwrap()
+    }
+}
+
+impl fmt::Display for HenyeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HenyeyError::Singular => write!(f, "singular Henyey matrix"),
+            HenyeyError::NotConverged(c) => {
+                write!(f, "no convergence, last relative correction {:e}", c)
+            },
+            HenyeyError::LuminosityNotFree => {
+                write!(f, "the luminosity of an energy source cannot fit the radius")
+            },
+        }
+    }
 }
 
 impl Henyey {
 
+    /// Sets up `k` shells equally spaced in mass for a star of mass `m`,
+    /// starting from a uniform density model of radius `r`.
+    ///
+    /// `r` is a first guess, not a constraint: with the luminosity fixed by
+    /// `with_luminosity` or an energy source, the structure equations and
+    /// boundary conditions determine the radius, and `solve` converges to it
+    /// from any reasonable `r`. `with_radius` fixes the radius instead.
     pub fn new(k: usize, m: f64, r: f64) -> Self {
-        let h: MatrixF64 = MatrixF64::new(k*4-2, k*4-2).unwrap();
-        let mut y: VectorF64 = VectorF64::new(k*4-2).unwrap();
-        y.set_all(1.0);
-        Henyey { K: k, M: m, R: r, Y: y, H: h }
+        Self::from_masses((0..k).map(|j| m * j as f64 / (k - 1) as f64).collect(), r)
     }
 
+    /// Like `new` with a dimensioned `mass` and `radius`, or an error if
+    /// they are not a mass and a length.
+    pub fn from_quantities(
+        k: usize, mass: QuantityValue, radius: QuantityValue
+    ) -> Result<Self, DimensionError> {
+        Ok(Self::new(k, mass.value_in(QuantityValue::kg())?, radius.value_in(QuantityValue::m())?))
+    }
 
-    pub fn C(&mut self) {
-        let mut matrix
-            = self.H.submatrix(3*self.K-2, 3*self.K-4, 4, 6);
-        let y = &self.Y.as_slice().unwrap()[3*self.K-4..3*self.K];
-        let derivative = |i, j| -> f64 {
-            match (i, j) {
-                _ => 7f64
+    /// Sets up shells at the mass coordinates `m`, increasing from 0 at the
+    /// centre to the mass of the star at the surface.
+    pub fn from_masses(m: Vec<f64>, r: f64) -> Self {
+        let k = m.len();
+        assert!(k >= 2 && m[0] == 0.0, "mass grid must start at the centre");
+        assert!(m.windows(2).all(|w| w[1] > w[0]), "mass grid must increase outwards");
+        let h = Self::blocks(k);
+        let y: VectorF64 = VectorF64::new(k*4-2).unwrap();
+        let f: VectorF64 = VectorF64::new(k*4-2).unwrap();
+        let mut henyey = Henyey {
+            K: k, M: m[k-1], R: r, radius: None, L: 0.0, surface: Surface::Zero, eos: Box::new(IdealGas::new(0.5)),
+            kappa: Box::new(opacity::analytic(&Composition::solar(), GUILLOTINE)), energy: None,
+            convection: None, composition: Vec::new(), layers: Vec::new(), previous: None,
+            m, polytrope: None, solver: Solver::Block, Y: y, H: h, F: f, scale: [1.0; 4]
+        };
+        henyey.starting_model();
+        henyey
+    }
+
+    /// Sets the total luminosity, released at a constant rate per unit mass.
+    /// With an energy source it only sets up the starting model.
+    pub fn with_luminosity(mut self, l: f64) -> Self {
+        self.L = l;
+        self.starting_model();
+        self
+    }
+
+    /// Fixes the radius of the converged model to `r`, which also sizes the
+    /// starting model. `solve` then varies the luminosity released by
+    /// `with_luminosity`, starting from it, until the radius matches; that of
+    /// an energy source cannot be varied.
+    pub fn with_radius(mut self, r: f64) -> Self {
+        self.R = r;
+        self.radius = Some(r);
+        self.starting_model();
+        self
+    }
+
+    /// Like `with_luminosity` with a dimensioned luminosity, or an error if
+    /// it is not a power.
+    pub fn with_luminosity_quantity(self, l: QuantityValue) -> Result<Self, DimensionError> {
+        Ok(self.with_luminosity(l.value_in(QuantityValue::W())?))
+    }
+
+    /// Replaces the surface conditions, P = 0 and T = 0 by default.
+    pub fn with_surface(mut self, surface: Surface) -> Self {
+        self.surface = surface;
+        self.starting_model();
+        self
+    }
+
+    /// Replaces the equation of state, an ideal gas of ionized hydrogen by
+    /// default.
+    pub fn with_equation_of_state<E: EquationOfState + 'static>(mut self, eos: E) -> Self {
+        self.eos = Box::new(eos);
+        self.starting_model();
+        self
+    }
+
+    /// Replaces the opacity, electron scattering plus Kramers bound-free and
+    /// free-free absorption of a solar mixture by default.
+    pub fn with_opacity<O: Opacity + 'static>(mut self, kappa: O) -> Self {
+        self.kappa = Box::new(kappa);
+        self
+    }
+
+    /// Releases energy at the rate of `energy` instead of uniformly, making
+    /// the luminosity an eigenvalue like the radius.
+    pub fn with_energy_generation<E: EnergyGeneration + 'static>(mut self, energy: E) -> Self {
+        self.energy = Some(Box::new(energy));
+        self
+    }
+
+    /// Starts from a polytrope of index `n` < 5 with temperatures from the
+    /// ideal gas law instead of a homogeneous sphere.
+    pub fn with_polytrope(mut self, n: f64) -> Self {
+        let polytrope = Polytrope::new(n);
+        assert!(polytrope.surface().is_some(), "polytrope of index {} has no surface", n);
+        self.polytrope = Some(polytrope);
+        self.starting_model();
+        self
+    }
+
+    /// Replaces the linear solver, block elimination by default.
+    pub fn with_solver(mut self, solver: Solver) -> Self {
+        self.solver = solver;
+        self
+    }
+
+    /// Carries energy by mixing-length convection wherever the stratification
+    /// is unstable, instead of by radiation everywhere.
+    pub fn with_convection(mut self, convection: Convection) -> Self {
+        self.convection = Some(convection);
+        self
+    }
+
+    /// Homogeneous sphere or polytrope in hydrostatic equilibrium, as a
+    /// starting guess, with temperatures from the ideal gas law.
+    /// The outermost shell is moved onto the surface conditions.
+    fn starting_model(&mut self) {
+        let G = NEWTONIAN_CONSTANT_OF_GRAVITATION;
+        let rho = 3.0 * self.M / (4.0 * PI * self.R.powi(3));
+        let P_c = 3.0 * G * self.M.powi(2) / (8.0 * PI * self.R.powi(4));
+        let mu = self.eos.mean_molecular_weight();
+        for j in 0..self.K {
+            let x = self.m[j] / self.M;
+            let y = match &self.polytrope {
+                Some(polytrope) => {
+                    let [r, P, _, T] = polytrope.at_mass(self.m[j], self.M, self.R, mu).unwrap();
+                    [r, P, T, self.L * x]
+                },
+                None => {
+                    let P = P_c * (1.0 - x.powf(2.0 / 3.0));
+                    let T = P * mu * MOLAR_MASS_CONSTANT / (MOLAR_GAS_CONSTANT * rho);
+                    [self.R * x.cbrt(), P, T, self.L * x]
+                },
+            };
+            self.set(j, y);
+        }
+        let t_eff = (self.L / (4.0 * PI * STEFAN_BOLTZMANN_CONSTANT * self.R.powi(2))).powf(0.25);
+        let g = G * self.M / self.R.powi(2);
+        let [P, T] = match self.surface {
+            Surface::Zero => [0.0, 0.0],
+            Surface::Grey => [2.0 * g / (3.0 * PHOTOSPHERE_OPACITY), t_eff],
+            Surface::Fitted(atmosphere) => atmosphere.fit(t_eff, g),
+        };
+        self.set(self.K-1, [self.R, P, T, self.L]);
+    }
+
+    /// Equation of state of interval `k`.
+    fn equation_of_state(&self, k: usize) -> &dyn EquationOfState {
+        match self.layers.get(k - 1) {
+            Some(eos) => eos.as_ref(),
+            None => self.eos.as_ref(),
+        }
+    }
+
+    /// Opacity at `P` and `T` in interval `k`, and its partial derivatives
+    /// in `P` and `T`.
+    fn opacity(&self, k: usize, P: f64, T: f64) -> [f64; 3] {
+        let [rho, rho_P, rho_T] = self.equation_of_state(k).density(P, T);
+        let [kappa, kappa_rho, kappa_T] = self.kappa.opacity(rho, T);
+        [kappa, kappa_rho * rho_P, kappa_T + kappa_rho * rho_T]
+    }
+
+    /// Empty Henyey matrix for `k` shells.
+    fn blocks(k: usize) -> BlockTridiagonal {
+        let mut sizes = vec![4; k];
+        sizes[0] = 2;
+        BlockTridiagonal::new(sizes)
+    }
+
+    /// Index into `Y` of variable `v` (r, P, T, l) of shell `j`, if it is
+    /// an unknown.
+    fn index(j: usize, v: usize) -> Option<usize> {
+        match (j, v) {
+            (0, 1) | (0, 2) => Some(v - 1),
+            (0, _) => None,
+            _ => Some(4*j - 2 + v),
+        }
+    }
+
+    /// r, P, T and l of shell `j`.
+    pub fn shell(&self, j: usize) -> [f64; 4] {
+        let mut y = [0f64; 4];
+        for (v, y) in y.iter_mut().enumerate() {
+            if let Some(i) = Self::index(j, v) {
+                *y = self.Y.get(i);
+            }
+        }
+        y
+    }
+
+    fn set(&mut self, j: usize, y: [f64; 4]) {
+        for (v, &y) in y.iter().enumerate() {
+            if let Some(i) = Self::index(j, v) {
+                self.Y.set(i, y);
             }
+        }
+    }
+
+    /// Current values of r, P, T and l on either side of interval `k`,
+    /// between shells `k - 1` and `k`.
+    fn interval(&self, k: usize) -> [f64; 8] {
+        let a = self.shell(k-1);
+        let b = self.shell(k);
+        [a[0], a[1], a[2], a[3], b[0], b[1], b[2], b[3]]
+    }
+
+    fn update_scale(&mut self) {
+        let surface = self.shell(self.K-1);
+        self.scale = [
+            surface[0],
+            self.Y.get(0).abs(),
+            self.Y.get(1).abs(),
+            self.L.abs().max(surface[3].abs()).max(f64::MIN_POSITIVE),
+        ];
+    }
+
+    /// Energy generation rate at `P` and `T` in interval `k`, and its
+    /// partial derivatives in `P` and `T`. During a time step it includes
+    /// the gravothermal release
+    ///
+    /// ```text
+    /// epsilon_g = -T ds/dt = -c_P dT/dt + delta/rho dP/dt
+    /// ```
+    ///
+    /// differenced from the start of the step. The partials of c_P and delta
+    /// are central differences.
+    fn epsilon(&self, k: usize, P: f64, T: f64) -> [f64; 3] {
+        let eos = self.equation_of_state(k);
+        let [rho, rho_P, rho_T] = eos.density(P, T);
+        let chain = |[epsilon, epsilon_rho, epsilon_T]: [f64; 3]| {
+            [epsilon, epsilon_rho * rho_P, epsilon_T + epsilon_rho * rho_T]
         };
+        let mut epsilon = match (self.composition.get(k - 1), &self.energy) {
+            (Some(network), _) => chain(network.energy(rho, T)),
+            (None, Some(energy)) => chain(energy.energy(rho, T)),
+            (None, None) => [self.L / self.M, 0.0, 0.0],
+        };
+        if let Some((dt, previous)) = &self.previous {
+            let [P_0, T_0] = previous[k - 1];
+            // c_P and delta/rho = -T rho_T/rho^2, the coefficients of dT/dt
+            // and dP/dt
+            let coefficients = |P: f64, T: f64| {
+                let [rho, _, rho_T] = eos.density(P, T);
+                [eos.specific_heat(P, T), -T * rho_T / rho.powi(2)]
+            };
+            let [c_P, delta_rho] = coefficients(P, T);
+            let (h_P, h_T) = (1e-6 * P, 1e-6 * T);
+            let [up, down] = [coefficients(P + h_P, T), coefficients(P - h_P, T)];
+            let [c_P_P, delta_rho_P] = [0, 1].map(|i| (up[i] - down[i]) / (2.0 * h_P));
+            let [up, down] = [coefficients(P, T + h_T), coefficients(P, T - h_T)];
+            let [c_P_T, delta_rho_T] = [0, 1].map(|i| (up[i] - down[i]) / (2.0 * h_T));
+            let (dT, dP) = ((T - T_0) / dt, (P - P_0) / dt);
+            epsilon[0] += -c_P * dT + delta_rho * dP;
+            epsilon[1] += -c_P_P * dT + delta_rho_P * dP + delta_rho / dt;
+            epsilon[2] += -c_P_T * dT - c_P / dt + delta_rho_T * dP;
+        }
+        epsilon
+    }
 
-        for i in 0..4 {
-            for j in 0..6 {
-                matrix.matrix_mut(|m| {m.unwrap().set(i,j,derivative(i,j));});
+    /// Composition gradient nabla_mu = d ln mu / d ln P at interval `k`,
+    /// differenced between its neighbours; zero for a homogeneous star.
+    fn nabla_mu(&self, k: usize) -> f64 {
+        if self.composition.is_empty() {
+            return 0.0;
+        }
+        let (inner, outer) = Self::neighbours(k, self.K);
+        let P = |j: usize| 0.5 * (self.shell(j-1)[1] + self.shell(j)[1]);
+        let mu = |j: usize| self.equation_of_state(j).mean_molecular_weight();
+        (mu(outer) / mu(inner)).ln() / (P(outer) / P(inner)).ln()
+    }
+
+    /// Intervals between which composition gradients at interval `k` of
+    /// `K - 1` are differenced.
+    fn neighbours(k: usize, K: usize) -> (usize, usize) {
+        (k.max(2) - 1, (k + 1).min(K - 1))
+    }
+
+    /// phi = d ln rho/d ln mu of the Ledoux criterion at `P` and `T` in
+    /// interval `k`, differenced like nabla_mu between the equations of
+    /// state of its neighbours at the same P and T. It is 1 for ideal gases
+    /// with radiation, whose density is proportional to mu, but not for
+    /// degenerate electrons, whose pressure follows mu_e alone.
+    fn phi(&self, k: usize, P: f64, T: f64) -> f64 {
+        if self.composition.is_empty() {
+            return 1.0;
+        }
+        let (inner, outer) = Self::neighbours(k, self.K);
+        let (inner, outer) = (self.equation_of_state(inner), self.equation_of_state(outer));
+        let mu = (outer.mean_molecular_weight() / inner.mean_molecular_weight()).ln();
+        if mu == 0.0 {
+            return 1.0;
+        }
+        (outer.density(P, T)[0] / inner.density(P, T)[0]).ln() / mu
+    }
+
+    /// phi/delta of the Ledoux criterion at `P` and `T` in interval `k`, with
+    /// delta = -d ln rho/d ln T from the equation of state and phi from
+    /// `phi`.
+    fn phi_over_delta(&self, k: usize, P: f64, T: f64) -> f64 {
+        let [rho, _, rho_T] = self.equation_of_state(k).density(P, T);
+        -self.phi(k, P, T) * rho / (T * rho_T)
+    }
+
+    /// Gradient with which convection in interval `k` compares nabla_rad:
+    /// nabla_ad, plus phi/delta nabla_mu under the Ledoux criterion, so that
+    /// the convective gradient is continuous at the boundary of stability.
+    fn reference_gradient(&self, k: usize, P: f64, T: f64, nabla_mu: f64) -> f64 {
+        let nabla_ad = self.equation_of_state(k).adiabatic_gradient(P, T);
+        match self.convection {
+            Some(convection) if convection.criterion == Criterion::Ledoux && nabla_mu != 0.0 => {
+                nabla_ad + self.phi_over_delta(k, P, T) * nabla_mu
+            },
+            _ => nabla_ad,
+        }
+    }
+
+    /// Temperature gradient nabla = d ln T / d ln P over interval `k`, its
+    /// partial derivatives in the shell averages of r, P, T and l, and
+    /// whether the interval is convective. Radiative layers have
+    ///
+    /// ```text
+    /// nabla_rad = 3 kappa l P/(16 pi a c G m T^4)
+    /// ```
+    ///
+    /// Convective ones take the mixing-length gradient about the reference
+    /// gradient, whose partials in P and T are central differences; those of
+    /// U hold c_P and delta fixed, and nabla_mu, which couples the
+    /// neighbouring intervals, is held fixed. Zero surface conditions force
+    /// nabla = 1 on the outermost interval, which is kept radiative.
+    fn gradient(&self, k: usize) -> ([f64; 5], bool) {
+        let G = NEWTONIAN_CONSTANT_OF_GRAVITATION;
+        let a = RADIATION_DENSITY_CONSTANT;
+        let c = SPEED_OF_LIGHT;
+        let y = self.interval(k);
+        let m = 0.5 * (self.m[k] + self.m[k-1]);
+        let [r, P, T, l] = [0, 1, 2, 3].map(|v| 0.5 * (y[v] + y[v + 4]));
+        let eos = self.equation_of_state(k);
+        let [rho, rho_P, rho_T] = eos.density(P, T);
+        let [kappa, kappa_P, kappa_T] = self.opacity(k, P, T);
+        let nabla_l = 3.0 * kappa * P / (16.0 * PI * a * c * G * m * T.powi(4));
+        let nabla = nabla_l * l;
+        let radiative = [
+            nabla,
+            0.0,
+            nabla * (kappa_P / kappa + 1.0 / P),
+            nabla * (kappa_T / kappa - 4.0 / T),
+            nabla_l,
+        ];
+        let nabla_ad = eos.adiabatic_gradient(P, T);
+        let nabla_mu = self.nabla_mu(k);
+        let phi_over_delta = self.phi_over_delta(k, P, T);
+        let outermost = k == self.K-1 && self.surface == Surface::Zero;
+        let convection = match self.convection {
+            Some(convection)
+                if !outermost
+                    && convection.is_unstable(nabla, nabla_ad, nabla_mu, phi_over_delta) =>
+            {
+                convection
+            },
+            _ => return (radiative, false),
+        };
+        let reference = self.reference_gradient(k, P, T, nabla_mu);
+        let (h_P, h_T) = (1e-6 * P, 1e-6 * T);
+        let reference_P = (self.reference_gradient(k, P + h_P, T, nabla_mu)
+            - self.reference_gradient(k, P - h_P, T, nabla_mu)) / (2.0 * h_P);
+        let reference_T = (self.reference_gradient(k, P, T + h_T, nabla_mu)
+            - self.reference_gradient(k, P, T - h_T, nabla_mu)) / (2.0 * h_T);
+        let g = G * m / r.powi(2);
+        let U = convection.efficiency(
+            T, rho, kappa, eos.specific_heat(P, T), -T * rho_T / rho, P / (rho * g), g
+        );
+        // U scales as T^3 rho^-1/2 kappa^-1 P^-3/2 r^-2 at fixed m.
+        let U_r = -2.0 * U / r;
+        let U_P = U * (-0.5 * rho_P / rho - kappa_P / kappa - 1.5 / P);
+        let U_T = U * (3.0 / T - 0.5 * rho_T / rho - kappa_T / kappa);
+        let [nabla, d_rad, d_reference, d_U] = convection.gradient(nabla, reference, U);
+        (
+            [
+                nabla,
+                d_U * U_r,
+                d_rad * radiative[2] + d_reference * reference_P + d_U * U_P,
+                d_rad * radiative[3] + d_reference * reference_T + d_U * U_T,
+                d_rad * radiative[4],
+            ],
+            true,
+        )
+    }
+
+    /// Whether the centre is convective, judged by the radiative gradient in
+    /// the limit m -> 0, where l/m -> epsilon.
+    fn centre_convective(&self) -> bool {
+        let G = NEWTONIAN_CONSTANT_OF_GRAVITATION;
+        let a = RADIATION_DENSITY_CONSTANT;
+        let c = SPEED_OF_LIGHT;
+        let (P_c, T_c) = (self.Y.get(0), self.Y.get(1));
+        match self.convection {
+            Some(convection) => {
+                let kappa = self.opacity(1, P_c, T_c)[0];
+                let epsilon = self.epsilon(1, P_c, T_c)[0];
+                let nabla = 3.0 * kappa * epsilon * P_c / (16.0 * PI * a * c * G * T_c.powi(4));
+                let nabla_ad = self.equation_of_state(1).adiabatic_gradient(P_c, T_c);
+                let phi_over_delta = self.phi_over_delta(1, P_c, T_c);
+                convection.is_unstable(nabla, nabla_ad, self.nabla_mu(1), phi_over_delta)
+            },
+            None => false,
+        }
+    }
+
+    /// Whether each interval is convective, from the centre outwards; the
+    /// central expansion counts for shells 0 and 1.
+    pub fn convective(&self) -> Vec<bool> {
+        let centre = self.centre_convective();
+        let mut convective = vec![centre, centre];
+        for k in 2..self.K {
+            convective.push(self.gradient(k).1);
+        }
+        convective
+    }
+
+    /// Profile of the current model, one row per shell of m, r, P, T, l,
+    /// rho, kappa, epsilon and nabla in SI units. Shell j takes the equation
+    /// of state, composition and temperature gradient of the interval below
+    /// it, and the centre those of interval 1.
+    pub fn profile(&self) -> Profile {
+        let names = ["m", "r", "P", "T", "l", "rho", "kappa", "epsilon", "nabla"];
+        let mut profile = Profile::new(&names)
+            .with_header("mass", self.M)
+            .with_header("shells", self.K)
+            .with_header("effective_temperature", self.effective_temperature());
+        for j in 0..self.K {
+            let k = j.max(1);
+            let [r, P, T, l] = self.shell(j);
+            let rho = self.equation_of_state(k).density(P, T)[0];
+            let kappa = self.opacity(k, P, T)[0];
+            let epsilon = self.epsilon(k, P, T)[0];
+            let nabla = self.gradient(k).0[0];
+            profile.push(&[self.m[j], r, P, T, l, rho, kappa, epsilon, nabla]);
+        }
+        profile
+    }
+
+    /// Number of shells K.
+    pub fn shells(&self) -> usize {
+        self.K
+    }
+
+    /// Composition of each interval from the centre outwards, empty for a
+    /// homogeneous star.
+    pub fn composition(&self) -> &[Network] {
+        &self.composition
+    }
+
+    /// Gives each interval its own composition, which replaces the energy
+    /// source by its network and sets the mean molecular weight of the
+    /// equation of state.
+    pub fn set_composition(&mut self, composition: Vec<Network>) {
+        self.layers = composition.iter()
+            .map(|network| self.eos.with_composition(&network.composition()))
+            .collect();
+        self.composition = composition;
+    }
+
+    /// Releases gravothermal energy over a time step of `dt` seconds that
+    /// started from `model`, interpolated linearly in mass onto the shells.
+    pub fn set_time_step(&mut self, dt: f64, model: &Model) {
+        let at = |m: f64| {
+            let j = model.m.iter().position(|&x| x >= m).unwrap_or(model.m.len() - 1).max(1);
+            let w = (m - model.m[j-1]) / (model.m[j] - model.m[j-1]);
+            [
+                (1.0 - w) * model.P[j-1] + w * model.P[j],
+                (1.0 - w) * model.T[j-1] + w * model.T[j],
+            ]
+        };
+        let previous = (1..self.K)
+            .map(|k| if k == 1 {
+                [model.P[0], model.T[0]]
+            } else {
+                let (a, b) = (at(self.m[k-1]), at(self.m[k]));
+                [0.5 * (a[0] + b[0]), 0.5 * (a[1] + b[1])]
+            })
+            .collect();
+        self.previous = Some((dt, previous));
+    }
+
+    /// Resets `Y` to the shells of `model`, moving to its mass grid if it is
+    /// on another one, as after rezoning. The time step is then dropped and
+    /// the composition must be set again for the new grid.
+    pub fn restore(&mut self, model: &Model) {
+        if model.m != self.m {
+            self.regrid(model.m.clone());
+        }
+        for j in 0..self.K {
+            self.set(j, [model.r[j], model.P[j], model.T[j], model.l[j]]);
+        }
+    }
+
+    /// Burns the composition of each interval for `dt` seconds at its
+    /// current density and temperature, those of the centre for interval 1,
+    /// then mixes each convective zone homogeneously.
+    pub fn burn(&mut self, dt: f64) {
+        let convective = self.convective();
+        for k in 1..self.K {
+            let y = self.interval(k);
+            let [P, T] = if k == 1 {
+                [y[1], y[2]]
+            } else {
+                [0.5 * (y[1] + y[5]), 0.5 * (y[2] + y[6])]
+            };
+            let rho = self.equation_of_state(k).density(P, T)[0];
+            self.composition[k-1].evolve(rho, T, dt);
+        }
+        let mut k = 1;
+        while k < self.K {
+            let start = k;
+            while k < self.K && convective[k] {
+                k += 1;
+            }
+            if k == start {
+                k += 1;
+                continue;
+            }
+            let mass = self.m[k-1] - self.m[start-1];
+            let mut mixed = [0f64; SPECIES];
+            for j in start..k {
+                let dm = self.m[j] - self.m[j-1];
+                for (x, y) in mixed.iter_mut().zip(self.composition[j-1].abundances.iter()) {
+                    *x += y * dm / mass;
+                }
+            }
+            for j in start..k {
+                self.composition[j-1].abundances = mixed;
             }
         }
+        let composition = std::mem::take(&mut self.composition);
+        self.set_composition(composition);
     }
 
-    pub fn A(&mut self, k: usize) {
-        let R = MOLAR_GAS_CONSTANT;
-        let mu = MEAN_MOLECULAR_WEIGHT;
+    /// Change between shells `a` and `b`: the largest difference of ln r,
+    /// ln P, ln T and l/L, leaving out the vanishing r at the centre and P
+    /// and T on zero surface conditions.
+    fn change(&self, a: [f64; 4], b: [f64; 4]) -> f64 {
+        let mut change = ((b[3] - a[3]) / self.scale[3]).abs();
+        for v in 0..3 {
+            if a[v] > 0.0 && b[v] > 0.0 {
+                change = change.max((b[v] / a[v]).ln().abs());
+            }
+        }
+        change
+    }
+
+    /// Rezones the shells so that the change between neighbours, as measured
+    /// by `change`, stays below `max_change`: intervals changing by more are
+    /// split in half by mass, and pairs of intervals changing by less than
+    /// half of it together are merged, keeping shell 1 for the central
+    /// expansion and never merging different compositions. New shells
+    /// interpolate r^3 and l linearly and P and T logarithmically in mass;
+    /// compositions are mass-averaged, conserving each species. Returns
+    /// whether the grid changed. The time step is dropped.
+    pub fn rezone(&mut self, max_change: f64) -> bool {
+        self.update_scale();
+        let network = |k: usize| self.composition.get(k - 1).copied();
+        let mut masses = vec![0.0];
+        let mut shells = vec![self.shell(0)];
+        let mut composition = Vec::new();
+        let mut k = 1;
+        while k < self.K {
+            let (a, b) = (self.shell(k-1), self.shell(k));
+            let dm = self.m[k] - self.m[k-1];
+            if self.change(a, b) > max_change && dm > 2.0 * MIN_INTERVAL * self.M {
+                let mut middle = [0f64; 4];
+                middle[0] = (0.5 * (a[0].powi(3) + b[0].powi(3))).cbrt();
+                for v in 1..3 {
+                    middle[v] = if a[v] > 0.0 && b[v] > 0.0 {
+                        (a[v] * b[v]).sqrt()
+                    } else {
+                        0.5 * (a[v] + b[v])
+                    };
+                }
+                middle[3] = 0.5 * (a[3] + b[3]);
+                masses.extend_from_slice(&[self.m[k-1] + 0.5 * dm, self.m[k]]);
+                shells.extend_from_slice(&[middle, b]);
+                composition.extend(network(k).into_iter().chain(network(k)));
+                k += 1;
+            } else if k >= 2 && k + 1 < self.K
+                && self.change(a, self.shell(k+1)) < 0.5 * max_change
+                && network(k).zip(network(k+1)).is_none_or(|(x, y)| {
+                    x.abundances.iter().zip(y.abundances.iter())
+                        .all(|(x, y)| (x - y).abs() < MAX_MERGED_ABUNDANCE_CHANGE)
+                })
+            {
+                masses.push(self.m[k+1]);
+                shells.push(self.shell(k+1));
+                if let (Some(x), Some(y)) = (network(k), network(k+1)) {
+                    let (dm_x, dm_y) = (dm, self.m[k+1] - self.m[k]);
+                    let mut mixed = x;
+                    for (i, abundance) in mixed.abundances.iter_mut().enumerate() {
+                        *abundance = (x.abundances[i] * dm_x + y.abundances[i] * dm_y)
+                            / (dm_x + dm_y);
+                    }
+                    composition.push(mixed);
+                }
+                k += 2;
+            } else {
+                masses.push(self.m[k]);
+                shells.push(b);
+                composition.extend(network(k));
+                k += 1;
+            }
+        }
+        if masses == self.m {
+            return false;
+        }
+        self.regrid(masses);
+        for (j, y) in shells.into_iter().enumerate() {
+            self.set(j, y);
+        }
+        if !self.composition.is_empty() {
+            self.set_composition(composition);
+        }
+        true
+    }
+
+    /// Moves to the mass grid `m`, leaving `Y` zero and dropping the time
+    /// step.
+    fn regrid(&mut self, m: Vec<f64>) {
+        let n = 4*m.len() - 2;
+        self.K = m.len();
+        self.m = m;
+        self.H = Self::blocks(self.K);
+        self.Y = VectorF64::new(n).unwrap();
+        self.F = VectorF64::new(n).unwrap();
+        self.previous = None;
+    }
+
+    /// Residuals of the four structure equations over interval `k`,
+    ///
+    /// ```text
+    /// dr/dm = 1/(4 pi r^2 rho)
+    /// dP/dm = -G m/(4 pi r^4)
+    /// dT/dm = -G m T nabla/(4 pi r^4 P)
+    /// dl/dm = epsilon
+    /// ```
+    ///
+    /// differenced with shell averages and scaled by `scale`.
+    fn residual(&self, k: usize) -> [f64; 4] {
+        self.interval_residual(k).map(|f| f.value)
+    }
+
+    /// Residuals of interval `k` as dual numbers in r, P, T and l of shells
+    /// `k - 1` and `k`, so that their gradients are the rows of `A(k)`.
+    fn interval_residual(&self, k: usize) -> [Dual<[f64; 8]>; 4] {
         let G = NEWTONIAN_CONSTANT_OF_GRAVITATION;
-        let a = 1.0;
-        let c = 1.0;
-        let mass: [f64;4] = [5.0, 4.0, 1.0, 0.0];
-        let kappa: [f64;4] = [5.0, 4.0, 1.0, 0.0];
-        let mut matrix = self.H.submatrix(4*k-2, 4*(k-1), 4, 8);
-        let y = &self.Y.as_slice().unwrap()[4*(k-1)..4*(k-1)+8];
-        /*
-        let r = || -> f64 {
-            (mass[k] - mass[k+1]).recip() + 2f64 * R * (y[2] + y[6])
-                / (PI * mu * (y[1] + y[5]) * (y[0] + y[4]).powi(3))
+        let y = Dual::variables(self.interval(k));
+        let dm = self.m[k] - self.m[k-1];
+        let m = 0.5 * (self.m[k] + self.m[k-1]);
+        let [r, P, T, l] = [0, 1, 2, 3].map(|v| 0.5 * (y[v] + y[v + 4]));
+        let [rho, rho_P, rho_T] = self.equation_of_state(k).density(P.value, T.value);
+        let rho = Dual::lift(rho, &[(rho_P, &P), (rho_T, &T)]);
+        let [nabla, nabla_r, nabla_P, nabla_T, nabla_l] = self.gradient(k).0;
+        let nabla = Dual::lift(nabla, &[(nabla_r, &r), (nabla_P, &P), (nabla_T, &T), (nabla_l, &l)]);
+        let [epsilon, epsilon_P, epsilon_T] = self.epsilon(k, P.value, T.value);
+        let epsilon = Dual::lift(epsilon, &[(epsilon_P, &P), (epsilon_T, &T)]);
+        [
+            (y[4] - y[0] - dm / (4.0 * PI * r.powi(2) * rho)) / self.scale[0],
+            (y[5] - y[1] + dm * G * m / (4.0 * PI * r.powi(4))) / self.scale[1],
+            (y[6] - y[2] + dm * G * m * T * nabla / (4.0 * PI * r.powi(4) * P)) / self.scale[2],
+            (y[7] - y[3] - dm * epsilon) / self.scale[3],
+        ]
+    }
+
+    /// Residuals of the central expansions between the centre and shell 1,
+    ///
+    /// ```text
+    /// r = (3 m/(4 pi rho_c))^(1/3)
+    /// P = P_c - 3 G/(8 pi) (4 pi rho_c/3)^(4/3) m^(2/3)
+    /// T^4 = T_c^4 - 1/(2 a c) (3/(4 pi))^(2/3) kappa_c epsilon rho_c^(4/3) m^(2/3)
+    /// l = epsilon m
+    /// ```
+    ///
+    /// The temperature row is scaled by `4 T_c^4` instead of `T_c`. A
+    /// convective centre is adiabatic instead,
+    ///
+    /// ```text
+    /// T = T_c - (pi/6)^(1/3) G nabla_ad rho_c^(4/3) T_c/P_c m^(2/3)
+    /// ```
+    fn centre_residual(&self) -> [f64; 4] {
+        let G = NEWTONIAN_CONSTANT_OF_GRAVITATION;
+        let a = RADIATION_DENSITY_CONSTANT;
+        let c = SPEED_OF_LIGHT;
+        let y = self.interval(1);
+        let m = self.m[1];
+        let rho = self.equation_of_state(1).density(y[1], y[2])[0];
+        let kappa = self.opacity(1, y[1], y[2])[0];
+        let epsilon = self.epsilon(1, y[1], y[2])[0];
+        let temperature = if self.centre_convective() {
+            let nabla_ad = self.equation_of_state(1).adiabatic_gradient(y[1], y[2]);
+            (y[6] - y[2] + (PI / 6.0).cbrt() * G * nabla_ad * rho.powf(4.0 / 3.0) * y[2] / y[1]
+                * m.powf(2.0 / 3.0)) / self.scale[2]
+        } else {
+            (y[6].powi(4) - y[2].powi(4) + (3.0 / (4.0 * PI)).powf(2.0 / 3.0) * kappa * epsilon
+                * rho.powf(4.0 / 3.0) * m.powf(2.0 / 3.0) / (2.0 * a * c))
+                / (4.0 * self.scale[2].powi(4))
+        };
+        [
+            (y[4] - (3.0 * m / (4.0 * PI * rho)).cbrt()) / self.scale[0],
+            (y[5] - y[1] + 3.0 * G / (8.0 * PI) * (4.0 * PI * rho / 3.0).powf(4.0 / 3.0)
+                * m.powf(2.0 / 3.0)) / self.scale[1],
+            temperature,
+            (y[7] - epsilon * m) / self.scale[3],
+        ]
+    }
+
+    /// Residuals of the surface conditions.
+    fn surface_residual(&self) -> [f64; 2] {
+        let G = NEWTONIAN_CONSTANT_OF_GRAVITATION;
+        let y = self.shell(self.K-1);
+        let [P, T] = match self.surface {
+            Surface::Zero => [0.0, 0.0],
+            Surface::Grey => {
+                let kappa = self.opacity(self.K-1, y[1], y[2])[0];
+                [2.0 * G * self.M / (3.0 * y[0].powi(2) * kappa), self.effective_temperature()]
+            },
+            Surface::Fitted(atmosphere) => {
+                atmosphere.fit(self.effective_temperature(), G * self.M / y[0].powi(2))
+            },
         };
-        */
+        [(y[1] - P) / self.scale[1], (y[2] - T) / self.scale[2]]
+    }
+
+    /// Effective temperature of the outermost shell, L = 4 pi r^2 sigma T_eff^4.
+    pub fn effective_temperature(&self) -> f64 {
+        let y = self.shell(self.K-1);
+        (y[3] / (4.0 * PI * STEFAN_BOLTZMANN_CONSTANT * y[0].powi(2))).powf(0.25)
+    }
+
+    /// Fills the Henyey matrix rows of the central interval with the
+    /// derivatives of its residuals with respect to P_c, T_c and the
+    /// variables of shell 1.
+    pub fn B(&mut self) {
+        let G = NEWTONIAN_CONSTANT_OF_GRAVITATION;
+        let a = RADIATION_DENSITY_CONSTANT;
+        let c = SPEED_OF_LIGHT;
+        let y = self.interval(1);
+        let (P_c, T_c) = (y[1], y[2]);
+        let m = self.m[1];
+        let [rho, rho_P, rho_T] = self.equation_of_state(1).density(P_c, T_c);
+        let [kappa, kappa_P, kappa_T] = self.opacity(1, P_c, T_c);
+        let [epsilon, epsilon_P, epsilon_T] = self.epsilon(1, P_c, T_c);
+        let r = (3.0 * m / (4.0 * PI * rho)).cbrt();
+        let dP = 3.0 * G / (8.0 * PI) * (4.0 * PI * rho / 3.0).powf(4.0 / 3.0) * m.powf(2.0 / 3.0);
+        // dT = q kappa epsilon
+        let q = (3.0 / (4.0 * PI)).powf(2.0 / 3.0) * rho.powf(4.0 / 3.0) * m.powf(2.0 / 3.0)
+            / (2.0 * a * c);
+        let dT = q * kappa * epsilon;
+        // Adiabatic T = T_c (1 - D) at a convective centre
+        let convective = self.centre_convective();
+        let nabla_ad = self.equation_of_state(1).adiabatic_gradient(P_c, T_c);
+        let D = (PI / 6.0).cbrt() * G * nabla_ad * rho.powf(4.0 / 3.0)
+            * m.powf(2.0 / 3.0) / P_c;
+        let scale_T = if convective { self.scale[2] } else { 4.0 * self.scale[2].powi(4) };
+        let scale = [self.scale[0], self.scale[1], scale_T, self.scale[3]];
 
+        // Columns P_c, T_c, r_1, P_1, T_1, l_1.
         let derivative = |i, j| -> f64 {
             match (i, j) {
-                (0, 0) => {
-                    (mass[k] - mass[k+1]).recip() + 2f64 * R * (y[2] + y[6])
-                        / (PI * mu * (y[1] + y[5]) * (y[0] + y[4]).powi(3))
-                },
-                (0, 1) | (0, 5) => {
-                    R * (y[2] + y[6])
-                        / (PI * mu * (y[1] + y[5]).powi(2) * (y[0] + y[4]).powi(2))
-                },
-                (0, 2) | (0, 6) => {
-                    -R * (y[2] + y[6])
-                        / (PI * mu * (y[1] + y[5]) * (y[0] + y[4]).powi(2))
-                },
-                (0, 3) | (0, 7) => 0f64,
-                (0, 4) => {
-                    -(mass[k] - mass[k+1]).recip() + 2f64 * R * (y[2] + y[6])
-                        / (PI * mu * (y[1] + y[5]) * (y[0] + y[4]).powi(3))
-                },
-                (1, 0) | (1, 4) => {
-                    -G * (mass[k] + mass[k+1]) / (2.0 * PI * (y[0] + y[4]))
-                },
-                (1, 1) | (1, 5) => {
-                    (mass[k] - mass[k+1]).recip()
-                },
-                (1, 2) | (1, 6) => 0f64,
-                (1, 3) | (1, 7) => 0f64,
-                (2, 0) | (2, 4) => {
-                    -3.0 * (kappa[k] + kappa[k+1]) * (y[3] + y[7])
-                        / (8.0 * PI.powi(2) * a * c
-                           * (y[0] + y[4]).powi(5) * (y[2] + y[6]).powi(3))
-                },
-                (2, 1) | (2, 5) => {
-                    3.0 * (y[3] + y[7])
-                        / (2.0 * PI.powi(2) * a * c
-                           * (y[0] + y[4]).powi(4) * (y[2] + y[6]).powi(3))
-                },
-                (2, 2) => {
-                    (mass[k] - mass[k+1]).recip() - (kappa[k] + kappa[k+1])
-                        * (y[3] + y[7])
-                        / (2.0 * PI.powi(2) * a * c
-                           * (y[0] + y[4]).powi(4) * (y[2] + y[6]).powi(4))
-                },
-                (2, 3) | (2, 7) => {
-                    3.0 * (kappa[k] + kappa[k+1])
-                        / (2.0 * PI.powi(2) * a * c
-                           * (y[0] + y[4]).powi(4) * (y[2] + y[6]).powi(3))
+                (0, 0) => r * rho_P / (3.0 * rho),
+                (0, 1) => r * rho_T / (3.0 * rho),
+                (0, 2) => 1.0,
+                (1, 0) => -1.0 + 4.0 * dP * rho_P / (3.0 * rho),
+                (1, 1) => 4.0 * dP * rho_T / (3.0 * rho),
+                (1, 3) => 1.0,
+                (2, 0) if convective => D * T_c * (4.0 * rho_P / (3.0 * rho) - 1.0 / P_c),
+                (2, 1) if convective => -1.0 + D + D * T_c * 4.0 * rho_T / (3.0 * rho),
+                (2, 4) if convective => 1.0,
+                (2, 0) => {
+                    q * (kappa_P * epsilon + kappa * epsilon_P) + dT * 4.0 * rho_P / (3.0 * rho)
                 },
-                (2, 6) => {
-                    -(mass[k] - mass[k+1]).recip() - (kappa[k] + kappa[k+1])
-                        * (y[3] + y[7])
-                        / (2.0 * PI.powi(2) * a * c
-                           * (y[0] + y[4]).powi(4) * (y[2] + y[6]).powi(4))
+                (2, 1) => {
+                    -4.0 * T_c.powi(3) + q * (kappa_T * epsilon + kappa * epsilon_T)
+                        + dT * 4.0 * rho_T / (3.0 * rho)
                 },
-                (3, 0) | (3, 4) => 0f64,
-                (3, 1) | (3, 5) => 0f64,
-                (3, 2) | (3, 6) => 0f64,
-                (3, 3) => {
-                    (mass[k] - mass[k+1]).recip()
+                (2, 4) => 4.0 * y[6].powi(3),
+                (3, 0) => -m * epsilon_P,
+                (3, 1) => -m * epsilon_T,
+                (3, 5) => 1.0,
+                _ => 0f64,
+            }
+        };
+        for (i, scale) in scale.iter().enumerate() {
+            for j in 0..6 {
+                self.H.set(i, j, derivative(i, j) / scale);
+            }
+        }
+    }
+
+    /// Fills the Henyey matrix rows of interval `k`, 2 <= k < K, with the
+    /// derivatives of its residuals with respect to the variables of shells
+    /// `k - 1` and `k`.
+    pub fn A(&mut self, k: usize) {
+        let residual = self.interval_residual(k);
+        for (i, residual) in residual.iter().enumerate() {
+            for (j, gradient) in residual.gradient.iter().enumerate() {
+                if let Some(col) = Self::index(k - 1 + j / 4, j % 4) {
+                    self.H.set(4*(k-1) + i, col, *gradient);
+                }
+            }
+        }
+    }
+
+    /// Fills the surface rows of the Henyey matrix with the derivatives of
+    /// the surface conditions with respect to r, P, T and l of the outermost
+    /// shell.
+    pub fn C(&mut self) {
+        let G = NEWTONIAN_CONSTANT_OF_GRAVITATION;
+        let y = self.shell(self.K-1);
+        let (r, l) = (y[0], y[3]);
+        let t_eff = self.effective_temperature();
+        // Derivatives of T_eff with respect to r and l
+        let t_eff_r = -t_eff / (2.0 * r);
+        let t_eff_l = t_eff / (4.0 * l);
+        let mut d = [[0f64; 4], [0f64; 4]];
+        d[0][1] = 1.0;
+        d[1][2] = 1.0;
+        match self.surface {
+            Surface::Zero => {},
+            Surface::Grey => {
+                let [kappa, kappa_P, kappa_T] = self.opacity(self.K-1, y[1], y[2]);
+                let P = 2.0 * G * self.M / (3.0 * r.powi(2) * kappa);
+                d[0][0] = 2.0 * P / r;
+                d[0][1] += P * kappa_P / kappa;
+                d[0][2] = P * kappa_T / kappa;
+                d[1][0] = -t_eff_r;
+                d[1][3] = -t_eff_l;
+            },
+            Surface::Fitted(atmosphere) => {
+                let [P, _] = atmosphere.fit(t_eff, G * self.M / r.powi(2));
+                let [a, b] = atmosphere.exponents;
+                d[0][0] = -P * (a * t_eff_r / t_eff - 2.0 * b / r);
+                d[0][3] = -P * a * t_eff_l / t_eff;
+                d[1][0] = -atmosphere.temperature * t_eff_r;
+                d[1][3] = -atmosphere.temperature * t_eff_l;
+            },
+        }
+        let row = 4*self.K - 4;
+        let col = Self::index(self.K-1, 0).unwrap();
+        for (i, d) in d.iter().enumerate() {
+            for (v, d) in d.iter().enumerate() {
+                self.H.set(row + i, col + v, d / self.scale[i + 1]);
+            }
+        }
+    }
+
+    /// Fills the whole Henyey matrix at the current `Y`.
+    fn jacobian(&mut self) {
+        self.H.set_zero();
+        self.B();
+        for k in 2..self.K {
+            self.A(k);
+        }
+        self.C();
+    }
+
+    /// Compares the Henyey matrix at the current `Y` with central differences
+    /// of the residuals, stepping each unknown by 1e-6 of its magnitude, or
+    /// of its scale where it vanishes. Rows are those of `B` (0 to 3), of
+    /// `A(k)` (4k - 4 to 4k - 1) and of `C` (the last two); entries are of
+    /// order one, so much smaller ones are compared absolutely.
+    pub fn check_jacobian(&mut self) -> Comparison {
+        self.update_scale();
+        self.jacobian();
+        let n = 4*self.K - 2;
+        let analytic = (0..n).map(|row| (0..n).map(|col| self.H.get(row, col)).collect()).collect();
+        let y: Vec<f64> = (0..n).map(|i| self.Y.get(i)).collect();
+        let steps: Vec<f64> = y.iter().enumerate().map(|(i, &y)| {
+            let v = if i < 2 { i + 1 } else { (i + 2) % 4 };
+            1e-6 * if y != 0.0 { y.abs() } else { self.scale[v] }
+        }).collect();
+        let numeric = jacobian::central_differences(|x| {
+            for (i, x) in x.iter().enumerate() {
+                self.Y.set(i, *x);
+            }
+            self.residuals();
+            (0..n).map(|i| self.F.get(i)).collect()
+        }, &y, &steps);
+        for (i, y) in y.iter().enumerate() {
+            self.Y.set(i, *y);
+        }
+        self.residuals();
+        Comparison::new(analytic, numeric, 1e-3)
+    }
+
+    /// Evaluates the residuals of all difference equations into `F`.
+    fn residuals(&mut self) {
+        for k in 1..self.K {
+            let f = if k == 1 { self.centre_residual() } else { self.residual(k) };
+            for (i, &f) in f.iter().enumerate() {
+                self.F.set(4*(k-1) + i, f);
+            }
+        }
+        let f = self.surface_residual();
+        self.F.set(4*self.K - 4, f[0]);
+        self.F.set(4*self.K - 3, f[1]);
+    }
+
+    /// Largest residual of the difference equations at the current `Y`.
+    pub fn residual_norm(&mut self) -> f64 {
+        self.update_scale();
+        self.residuals();
+        self.F.as_slice().unwrap().iter().fold(0.0, |a: f64, f| a.max(f.abs()))
+    }
+
+    /// Unknowns `Y`, to which the rows of the system are now scaled.
+    pub fn unknowns(&mut self) -> Vec<f64> {
+        self.update_scale();
+        self.Y.as_slice().unwrap().to_vec()
+    }
+
+    fn set_unknowns(&mut self, y: &[f64]) {
+        for (i, y) in y.iter().enumerate() {
+            self.Y.set(i, *y);
+        }
+    }
+
+    /// Relaxes `Y` by damped Newton iteration until the largest relative
+    /// correction falls below `TOLERANCE`. With a fixed radius, secant steps
+    /// in ln L between relaxations bring ln r at the surface to within
+    /// `TOLERANCE` of it.
+    pub fn solve(&mut self) -> Result<Model, HenyeyError> {
+        let radius = match self.radius {
+            Some(radius) => radius,
+            None => return self.relax(),
+        };
+        if self.energy.is_some() || !self.composition.is_empty() {
+            return Err(HenyeyError::LuminosityNotFree);
+        }
+        let (mut iterations, mut step, mut error) = (0, 0f64, f64::INFINITY);
+        // ln L, ln r - ln radius and model of the last relaxation
+        let mut last: Option<(f64, f64, Model)> = None;
+        for _ in 0..MAX_ITERATIONS {
+            let mut model = match self.relax() {
+                Ok(model) => model,
+                // Too long a step: go back halfway.
+                Err(e) => match &last {
+                    Some((ln_L, _, model)) if step.abs() > TOLERANCE => {
+                        step *= 0.5;
+                        self.restore(model);
+                        self.L = ln_L.exp();
+                        self.scale_luminosity(step.exp());
+                        continue;
+                    },
+                    _ => return Err(e),
                 },
-                (3, 7) => {
-                    -(mass[k] - mass[k+1]).recip()
+            };
+            iterations += model.iterations;
+            error = (model.r[self.K-1] / radius).ln();
+            if error.abs() < TOLERANCE {
+                model.iterations = iterations;
+                return Ok(model);
+            }
+            // A first small step finds which way the radius moves.
+            let ln_L = self.L.ln();
+            step = match &last {
+                Some((ln_L_0, error_0, _)) if error != *error_0 => {
+                    -error * (ln_L - ln_L_0) / (error - error_0)
                 },
-                _ => 0.0,
+                _ => LUMINOSITY_PROBE,
+            }.clamp(-MAX_LUMINOSITY_STEP, MAX_LUMINOSITY_STEP);
+            last = Some((ln_L, error, model));
+            self.scale_luminosity(step.exp());
+        }
+        Err(HenyeyError::NotConverged(error.abs()))
+    }
+
+    /// Multiplies the luminosity, and that of every shell, by `ratio`.
+    fn scale_luminosity(&mut self, ratio: f64) {
+        self.L *= ratio;
+        for j in 0..self.K {
+            let y = self.shell(j);
+            self.set(j, [y[0], y[1], y[2], y[3] * ratio]);
+        }
+    }
+
+    /// Damped Newton iteration of `solve` at the current luminosity.
+    fn relax(&mut self) -> Result<Model, HenyeyError> {
+        let mut correction = f64::INFINITY;
+        for iteration in 1..=MAX_ITERATIONS {
+            self.update_scale();
+            self.residuals();
+            self.jacobian();
+
+            let b: Vec<f64> = self.F.as_slice().unwrap().iter().map(|f| -f).collect();
+            let dy = match self.solver {
+                Solver::Block => self.H.solve(&b),
+                Solver::Dense => self.H.solve_dense(&b),
+            }.ok_or(HenyeyError::Singular)?;
+
+            // r, P and T stay positive inside the star, so their corrections
+            // are applied to the logarithm; l passes through zero at the
+            // centre and is corrected directly.
+            correction = 0.0;
+            for j in 0..self.K {
+                for (v, &y) in self.shell(j).iter().enumerate() {
+                    if let Some(i) = Self::index(j, v) {
+                        let size = if v < 3 && y > 0.0 { y } else { self.scale[v] };
+                        correction = correction.max(dy[i].abs() / size);
+                    }
+                }
             }
-        };
-        for i in 0..4 {
-            for j in 0..8 {
-                matrix.matrix_mut(|m| {m.unwrap().set(i,j,derivative(i,j));});
+            // max passes over NaN, so check the corrections themselves.
+            if !correction.is_finite() || dy.iter().any(|d| d.is_nan()) {
+                return Err(HenyeyError::Singular);
+            }
+            let damping = (MAX_CORRECTION / correction).min(1.0);
+            for j in 0..self.K {
+                for (v, &y) in self.shell(j).iter().enumerate() {
+                    if let Some(i) = Self::index(j, v) {
+                        let d = damping * dy[i];
+                        if v < 3 && y > 0.0 {
+                            self.Y.set(i, y * (d / y).exp());
+                        } else {
+                            self.Y.set(i, y + d);
+                        }
+                    }
+                }
             }
+            // Rounding must not lift P and T off zero surface conditions,
+            // as logarithmic corrections could never bring them back.
+            if self.surface == Surface::Zero {
+                let y = self.shell(self.K-1);
+                self.set(self.K-1, [y[0], 0.0, 0.0, y[3]]);
+            }
+            if correction < TOLERANCE {
+                return Ok(self.model(iteration));
+            }
+        }
+        Err(HenyeyError::NotConverged(correction))
+    }
+
+    fn model(&self, iterations: usize) -> Model {
+        let shells: Vec<[f64; 4]> = (0..self.K).map(|j| self.shell(j)).collect();
+        Model {
+            m: self.m.clone(),
+            r: shells.iter().map(|y| y[0]).collect(),
+            P: shells.iter().map(|y| y[1]).collect(),
+            T: shells.iter().map(|y| y[2]).collect(),
+            l: shells.iter().map(|y| y[3]).collect(),
+            convective: self.convective(),
+            iterations,
+        }
+    }
+}
+
+/// The difference equations as a system in `Y` for `roots`, with rows
+/// scaled as at the last call of `unknowns` and Newton corrections solved on
+/// the Henyey matrix. Each evaluation leaves `Y` at its point.
+impl roots::System for Henyey {
+    fn residual(&mut self, y: &[f64]) -> Vec<f64> {
+        self.set_unknowns(y);
+        self.residuals();
+        self.F.as_slice().unwrap().to_vec()
+    }
+
+    fn scale(&self, y: &[f64]) -> Vec<f64> {
+        y.iter().enumerate().map(|(i, &y)| {
+            let v = if i < 2 { i + 1 } else { (i + 2) % 4 };
+            if v < 3 && y > 0.0 { y } else { self.scale[v] }
+        }).collect()
+    }
+
+    fn jacobian(&mut self, y: &[f64], _f: &[f64]) -> Vec<Vec<f64>> {
+        self.set_unknowns(y);
+        self.jacobian();
+        (0..y.len()).map(|row| (0..y.len()).map(|col| self.H.get(row, col)).collect()).collect()
+    }
+
+    fn correction(&mut self, y: &[f64], f: &[f64]) -> Option<Vec<f64>> {
+        self.set_unknowns(y);
+        self.jacobian();
+        let b: Vec<f64> = f.iter().map(|f| -f).collect();
+        match self.solver {
+            Solver::Block => self.H.solve(&b),
+            Solver::Dense => self.H.solve_dense(&b),
         }
     }
 }
@@ -159,13 +1315,461 @@ impl Henyey {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::eos::{Composition, DegenerateGas, GasAndRadiation};
+    use crate::nuclear::Network;
+    use crate::opacity::OpacityTable;
+    use crate::roots::{Newton, NewtonKrylov, System};
+    use crate::symbolic;
+    use crate::units::{SOLAR_LUMINOSITY, SOLAR_MASS, SOLAR_RADIUS};
 
     #[test]
     fn test_new() {
         let mut h = Henyey::new(4usize, 1.0, 1.0);
-        h.A(4usize);
-        //assert_eq!(h.H.get(0usize,0usize), 0f64);
-        //assert_eq!(h.H.get(2usize,2usize), 1f64);
-        //assert!(h.H.is_null());
+        h.A(3usize);
+        assert_eq!(h.shell(0)[0], 0f64);
+        assert_eq!(h.shell(3)[0], 1f64);
+        assert!(h.H.get(8usize, 6usize) != 0f64);
+    }
+
+    /// Largest discrepancy between the Henyey matrix and central differences
+    /// of the residuals, checked block by block.
+    fn jacobian_error(h: &mut Henyey) -> f64 {
+        let comparison = h.check_jacobian();
+        let n = 4*h.K - 2;
+        let blocks = (1..h.K).map(|k| 4*(k-1)..4*k).chain(std::iter::once(n-2..n));
+        for rows in blocks {
+            let error = comparison.max_in(rows.clone());
+            assert!(error < 1e-5, "rows {:?}: {}", rows, comparison.report(1e-5));
+        }
+        comparison.max()
+    }
+
+    #[test]
+    fn test_jacobian() {
+        let atmosphere = Atmosphere::new(1e5, [0.5, 0.2], 0.85);
+        for surface in &[Surface::Zero, Surface::Grey, Surface::Fitted(atmosphere)] {
+            let mut h = Henyey::new(6usize, SOLAR_MASS, SOLAR_RADIUS)
+                .with_luminosity(SOLAR_LUMINOSITY)
+                .with_surface(*surface);
+            assert!(jacobian_error(&mut h) < 1e-5, "{:?}", surface);
+        }
+    }
+
+    #[test]
+    fn test_symbolic() {
+        // The dr/dm rows against the derivatives of the equations of doc/stars.tex
+        let h = Henyey::new(6usize, SOLAR_MASS, SOLAR_RADIUS).with_luminosity(SOLAR_LUMINOSITY);
+        let f = symbolic::dr_dm().substitute("rho", &symbolic::ideal_gas_density());
+        for k in 1..h.K {
+            let y = h.interval(k);
+            let mu = h.equation_of_state(k).mean_molecular_weight() * MOLAR_MASS_CONSTANT;
+            let bindings = [
+                ("r", QuantityValue::m() * (0.5 * (y[0] + y[4]))),
+                ("P", QuantityValue::Pa() * (0.5 * (y[1] + y[5]))),
+                ("T", QuantityValue::K() * (0.5 * (y[2] + y[6]))),
+                ("mu", QuantityValue::kg() / QuantityValue::mol() * mu),
+            ];
+            let dm = h.m[k] - h.m[k-1];
+            let row = h.interval_residual(k)[0].gradient;
+            for (v, x) in ["r", "P", "T"].iter().enumerate() {
+                let d = 0.5 * dm * f.derivative(x).evaluate(&bindings).unwrap().number;
+                let jump = if v == 0 { 1.0 } else { 0.0 };
+                for (i, expected) in [(v, -jump - d), (v + 4, jump - d)].iter() {
+                    let expected = expected / h.scale[0];
+                    assert!((row[*i] - expected).abs() < 1e-12 * expected.abs(),
+                            "interval {}, column {}: {} != {}", k, i, row[*i], expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_equation_of_state() {
+        let composition = Composition::solar();
+        let mut h = Henyey::new(6usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY)
+            .with_equation_of_state(GasAndRadiation::from_composition(&composition));
+        assert!(jacobian_error(&mut h) < 1e-5);
+        let mut h = Henyey::new(6usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY)
+            .with_surface(Surface::Grey)
+            .with_equation_of_state(DegenerateGas::new(&composition));
+        assert!(jacobian_error(&mut h) < 1e-5);
+    }
+
+    #[test]
+    fn test_opacity_table() {
+        // log kappa of electron scattering plus a Kramers-like slope.
+        let mut text = String::from("logT logR = -8 -6 -4 -2 0 2\n");
+        for t in 0..12 {
+            let log_t = 3.5 + 0.5 * t as f64;
+            text += &format!("{}", log_t);
+            for log_r in &[-8.0, -6.0, -4.0, -2.0, 0.0, 2.0] {
+                let kramers: f64 = 4e7 * (10f64).powf(log_r - 0.5 * log_t);
+                text += &format!(" {}", (0.34 + kramers).log10());
+            }
+            text += "\n";
+        }
+        let table: OpacityTable = text.parse().unwrap();
+        let mut h = Henyey::new(6usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY)
+            .with_opacity(table);
+        assert!(jacobian_error(&mut h) < 1e-5);
+    }
+
+    #[test]
+    fn test_energy_generation() {
+        let network = Network::from_composition(&Composition::solar());
+        for surface in &[Surface::Zero, Surface::Grey] {
+            let mut h = Henyey::new(6usize, SOLAR_MASS, SOLAR_RADIUS)
+                .with_luminosity(SOLAR_LUMINOSITY)
+                .with_surface(*surface)
+                .with_energy_generation(network);
+            assert!(jacobian_error(&mut h) < 1e-5, "{:?}", surface);
+        }
+
+        // Hydrogen burning sets the luminosity of the zero age Sun, starting
+        // from a model with uniform release and a similar central temperature.
+        let mut h = Henyey::new(50usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(1.5 * SOLAR_LUMINOSITY)
+            .with_equation_of_state(IdealGas::from_composition(&Composition::solar()));
+        h.solve().unwrap();
+        let mut h = h.with_energy_generation(network);
+        let model = h.solve().unwrap();
+        assert!(model.l[49] > 0.5 * SOLAR_LUMINOSITY && model.l[49] < SOLAR_LUMINOSITY);
+        assert!(model.T[0] > 1.3e7 && model.T[0] < 1.6e7);
+        for j in 1..50 {
+            assert!(model.l[j] >= model.l[j-1]);
+        }
+    }
+
+    #[test]
+    fn test_convection() {
+        let network = Network::from_composition(&Composition::solar());
+        let mut h = Henyey::new(6usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY)
+            .with_surface(Surface::Grey)
+            .with_energy_generation(network)
+            .with_convection(Convection::new(1.8));
+        assert!(h.convective()[4]);
+        assert!(jacobian_error(&mut h) < 1e-5);
+
+        // Carbon burning towards CN equilibrium gives the zero age Sun a
+        // small convective core.
+        let mut h = Henyey::new(50usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(1.5 * SOLAR_LUMINOSITY)
+            .with_equation_of_state(IdealGas::from_composition(&Composition::solar()));
+        h.solve().unwrap();
+        let mut h = h.with_energy_generation(network);
+        h.solve().unwrap();
+        // Starting from the radiative solution
+        let mut h = h.with_convection(Convection::new(1.8));
+        assert!(h.centre_convective());
+        assert!(jacobian_error(&mut h) < 1e-5);
+        let model = h.solve().unwrap();
+        assert!(model.convective[..4].iter().all(|c| *c));
+        assert!(model.convective[10..].iter().all(|c| !*c));
+        assert!(model.l[49] > 0.5 * SOLAR_LUMINOSITY && model.l[49] < SOLAR_LUMINOSITY);
+    }
+
+    #[test]
+    fn test_ledoux() {
+        // A helium-enriched interior under radiation pressure: the Ledoux
+        // term and its partials follow from the equation of state.
+        let interior: Vec<Network> = (1..6)
+            .map(|k| {
+                let x = 0.3 + 0.08 * k as f64;
+                Network::from_composition(&Composition::new(x, 0.98 - x, 0.02))
+            })
+            .collect();
+        let mut h = Henyey::new(6usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY)
+            .with_surface(Surface::Grey)
+            .with_equation_of_state(GasAndRadiation::from_composition(&Composition::solar()))
+            .with_convection(Convection::new(1.8).with_criterion(Criterion::Ledoux));
+        h.set_composition(interior);
+        let [P, T] = [h.shell(2)[1], h.shell(2)[2]];
+        let layer = GasAndRadiation::from_composition(&Composition::new(0.54, 0.44, 0.02));
+        let beta = layer.beta(P, T);
+        assert!((h.phi_over_delta(3, P, T) * (4.0 - 3.0 * beta) / beta - 1.0).abs() < 1e-3);
+        assert!(h.nabla_mu(3) > 0.0);
+        assert!(h.convective()[3] && h.convective()[4]);
+        assert!(jacobian_error(&mut h) < 1e-5);
+    }
+
+    #[test]
+    fn test_phi() {
+        // Degenerate electrons make the density follow mu_e rather than mu.
+        let interior: Vec<Network> = (1..6)
+            .map(|k| {
+                let x = 0.3 + 0.08 * k as f64;
+                Network::from_composition(&Composition::new(x, 0.98 - x, 0.02))
+            })
+            .collect();
+        let mut h = Henyey::new(6usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY)
+            .with_equation_of_state(DegenerateGas::new(&Composition::solar()));
+        h.set_composition(interior.clone());
+        // Interval 3 is differenced between intervals 2 and 4.
+        let inner = DegenerateGas::new(&interior[1].composition());
+        let outer = DegenerateGas::new(&interior[3].composition());
+        let mu = (outer.mean_molecular_weight() / inner.mean_molecular_weight()).ln();
+        let degenerate = (outer.mu_e / inner.mu_e).ln() / mu;
+        assert!((degenerate - 1.0).abs() > 0.1);
+        assert!((h.phi(3, 1e20, 1e6) / degenerate - 1.0).abs() < 0.02);
+        assert!((h.phi(3, 1e10, 1e7) - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_time_step() {
+        let network = Network::from_composition(&Composition::solar());
+        let mut h = Henyey::new(6usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY);
+        h.set_composition(vec![network; 5]);
+        let mut previous = h.model(0);
+        for j in 0..6 {
+            previous.P[j] *= 1.01;
+            previous.T[j] *= 0.99;
+        }
+        h.set_time_step(1e13, &previous);
+        assert!(jacobian_error(&mut h) < 1e-5);
+    }
+
+    #[test]
+    fn test_time_step_thermodynamics() {
+        // c_P and delta vary with P and T under radiation pressure and
+        // degeneracy.
+        fn check<E: EquationOfState + 'static>(eos: E) {
+            let network = Network::from_composition(&Composition::solar());
+            let mut h = Henyey::new(6usize, SOLAR_MASS, SOLAR_RADIUS)
+                .with_luminosity(SOLAR_LUMINOSITY)
+                .with_equation_of_state(eos);
+            h.set_composition(vec![network; 5]);
+            let mut previous = h.model(0);
+            for j in 0..6 {
+                previous.P[j] *= 1.01;
+                previous.T[j] *= 0.99;
+            }
+            h.set_time_step(1e13, &previous);
+            assert!(jacobian_error(&mut h) < 1e-5);
+        }
+        check(GasAndRadiation::from_composition(&Composition::solar()));
+        check(DegenerateGas::new(&Composition::solar()));
+    }
+
+    #[test]
+    fn test_rezone() {
+        let mut h = Henyey::new(100usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY);
+        let radius = h.solve().unwrap().r[99];
+        // Shells crowding towards the centre and the surface
+        let masses = (0..30).map(|j| {
+            let x = j as f64 / 29.0;
+            SOLAR_MASS * x * x * (3.0 - 2.0 * x)
+        });
+        let mut h = Henyey::from_masses(masses.collect(), SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY);
+        let model = h.solve().unwrap();
+        assert!((model.r[29] / radius - 1.0).abs() < 0.1);
+
+        // Refining resolves the steep outer layers.
+        let mut h = Henyey::new(20usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY);
+        h.solve().unwrap();
+        let mut passes = 0;
+        while h.rezone(0.3) {
+            h.solve().unwrap();
+            passes += 1;
+            assert!(passes < 10);
+        }
+        assert!(h.K > 20);
+        for k in 1..h.K {
+            assert!(h.change(h.shell(k-1), h.shell(k)) <= 0.3);
+        }
+
+        // Coarsening merges homogeneous intervals only, conserving hydrogen.
+        let intervals = h.K - 1;
+        h.set_composition((1..h.K).map(|k| {
+            let x = if k < 10 { 0.4 + 0.03 * k as f64 } else { 0.7 };
+            Network::from_composition(&Composition::new(x, 0.98 - x, 0.02))
+        }).collect());
+        let hydrogen = |h: &Henyey| -> Vec<f64> {
+            (1..h.K).map(|k| (h.m[k] - h.m[k-1]) * h.composition[k-1].abundances[0]).collect()
+        };
+        let before = hydrogen(&h);
+        assert!(h.rezone(2.0));
+        let after = hydrogen(&h);
+        assert!(h.K - 1 < intervals && after.len() == h.K - 1);
+        assert_eq!(before[..9], after[..9]);
+        let total: f64 = before.iter().sum();
+        assert!((after.iter().sum::<f64>() / total - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_solver() {
+        let mut block = Henyey::new(50usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY);
+        let mut dense = Henyey::new(50usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY)
+            .with_solver(Solver::Dense);
+        let (a, b) = (block.solve().unwrap(), dense.solve().unwrap());
+        assert_eq!(a.iterations, b.iterations);
+        for j in 0..50 {
+            assert!((a.r[j] - b.r[j]).abs() < 1e-10 * a.r[49]);
+            assert!((a.P[j] - b.P[j]).abs() < 1e-10 * a.P[0]);
+            assert!((a.T[j] - b.T[j]).abs() < 1e-10 * a.T[0]);
+            assert!((a.l[j] - b.l[j]).abs() < 1e-10 * a.l[49]);
+        }
+
+        // Thousands of shells
+        let mut h = Henyey::new(2000usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY);
+        h.solve().unwrap();
+        assert!(h.residual_norm() < 1e-8);
+    }
+
+    #[test]
+    fn test_roots() {
+        // Newton's method on the Henyey matrix, and Newton-Krylov on the
+        // residuals alone, recover a converged model from a perturbed one.
+        let mut h = Henyey::new(20usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY);
+        h.solve().unwrap();
+        let y = h.unknowns();
+        let scale = h.scale(&y);
+        let start: Vec<f64> = y.iter().enumerate()
+            .map(|(i, y)| y + 1e-3 * (i as f64).sin() * scale[i]).collect();
+        let newton = Newton::new(1e-9).solve(&mut h, &start).unwrap();
+        let krylov = NewtonKrylov::new(1e-9).with_restart(78).solve(&mut h, &start).unwrap();
+        assert!(newton.report.iterations < 10);
+        for i in 0..y.len() {
+            assert!((newton.x[i] - y[i]).abs() < 1e-6 * scale[i]);
+            assert!((krylov.x[i] - y[i]).abs() < 1e-6 * scale[i]);
+        }
+    }
+
+    #[test]
+    fn test_radius() {
+        // Fixing the radius makes the luminosity the eigenvalue.
+        let reference = Henyey::new(30usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY)
+            .solve()
+            .unwrap();
+        let radius = reference.r[29];
+        let model = Henyey::new(30usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(0.5 * SOLAR_LUMINOSITY)
+            .with_radius(radius)
+            .solve()
+            .unwrap();
+        assert!((model.r[29] / radius - 1.0).abs() < 1e-7);
+        assert!((model.l[29] / SOLAR_LUMINOSITY - 1.0).abs() < 1e-6);
+        assert!(model.iterations > reference.iterations);
+        // The solar mass at the solar radius
+        let model = Henyey::new(30usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY)
+            .with_radius(SOLAR_RADIUS)
+            .solve()
+            .unwrap();
+        assert!((model.r[29] / SOLAR_RADIUS - 1.0).abs() < 1e-7);
+        // An energy source fixes the luminosity itself.
+        let network = Network::from_composition(&Composition::solar());
+        let mut h = Henyey::new(30usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_energy_generation(network)
+            .with_radius(SOLAR_RADIUS);
+        assert_eq!(h.solve(), Err(HenyeyError::LuminosityNotFree));
+    }
+
+    #[test]
+    fn test_polytrope() {
+        // The same compact star from a homogeneous and from a more centrally
+        // condensed n = 3 start
+        let mut uniform = Henyey::new(50usize, SOLAR_MASS, 1e8)
+            .with_luminosity(SOLAR_LUMINOSITY);
+        let mut polytrope = Henyey::new(50usize, SOLAR_MASS, 1e8)
+            .with_luminosity(SOLAR_LUMINOSITY)
+            .with_polytrope(3.0);
+        let (a, b) = (uniform.solve().unwrap(), polytrope.solve().unwrap());
+        assert!(2 * b.iterations < a.iterations);
+        for j in 0..50 {
+            assert!((a.r[j] - b.r[j]).abs() < 1e-6 * a.r[49]);
+            assert!((a.P[j] - b.P[j]).abs() < 1e-6 * a.P[0]);
+        }
+    }
+
+    #[test]
+    fn test_solve() {
+        let mut h = Henyey::new(50usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY);
+        let model = h.solve().unwrap();
+        assert!(h.residual_norm() < 1e-8);
+        assert_eq!(model.P[49], 0f64);
+        assert_eq!(model.T[49], 0f64);
+        assert!((model.l[49] - SOLAR_LUMINOSITY).abs() < 1e-6 * SOLAR_LUMINOSITY);
+        // Pressure, temperature and radius are monotonic through the star.
+        for j in 1..50 {
+            assert!(model.r[j] > model.r[j-1]);
+            assert!(model.P[j] < model.P[j-1]);
+            assert!(model.T[j] < model.T[j-1]);
+        }
+    }
+
+    #[test]
+    fn test_grey_photosphere() {
+        let mut h = Henyey::new(50usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY)
+            .with_surface(Surface::Grey);
+        let model = h.solve().unwrap();
+        let t_eff = h.effective_temperature();
+        assert!((model.T[49] - t_eff).abs() < 1e-8 * t_eff);
+        assert!(model.P[49] > 0.0 && model.P[49] < 1e-6 * model.P[0]);
+    }
+
+    #[test]
+    fn test_quantities() {
+        let mut h = Henyey::from_quantities(
+            50, QuantityValue::solar_mass(), QuantityValue::m() * 6.957e5 * 1e3
+        ).unwrap().with_luminosity_quantity(QuantityValue::solar_luminosity()).unwrap();
+        let model = h.solve().unwrap();
+        let reference = Henyey::new(50usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY)
+            .solve()
+            .unwrap();
+        assert_eq!(model, reference);
+        let [m, r, _, _, _] = model.quantities(49);
+        assert_eq!(m.value_in(QuantityValue::solar_mass()), Ok(1.0));
+        assert_eq!(r.value_in(QuantityValue::m()), Ok(model.r[49]));
+        assert_eq!(model.luminosity().value_in(QuantityValue::W()), Ok(SOLAR_LUMINOSITY));
+        assert!((model.radius().value_in(QuantityValue::solar_radius()).unwrap() - 0.07).abs() < 0.03);
+        // Swapped arguments are caught instead of building a star.
+        let error = Henyey::from_quantities(
+            50, QuantityValue::solar_radius(), QuantityValue::solar_mass()
+        ).err().unwrap();
+        assert_eq!(error.expected, QuantityValue::kg().dimension);
+        assert!(Henyey::new(50usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity_quantity(QuantityValue::K()).is_err());
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut h = Henyey::new(50usize, SOLAR_MASS, SOLAR_RADIUS)
+            .with_luminosity(SOLAR_LUMINOSITY)
+            .with_surface(Surface::Grey);
+        let model = h.solve().unwrap();
+        let profile = h.profile();
+        assert_eq!(profile.len(), 50);
+        assert_eq!(profile.column("T").unwrap(), &model.T[..]);
+        assert_eq!(profile.column("l").unwrap(), &model.l[..]);
+        // Ideal gas of mean molecular weight 0.5
+        let rho = profile.column("rho").unwrap();
+        for (j, rho) in rho.iter().enumerate() {
+            let ideal = model.P[j] * 0.5 * MOLAR_MASS_CONSTANT / (MOLAR_GAS_CONSTANT * model.T[j]);
+            assert!((rho / ideal - 1.0).abs() < 1e-12);
+        }
+        let epsilon = profile.column("epsilon").unwrap();
+        assert!(epsilon.iter().all(|e| (e * SOLAR_MASS / SOLAR_LUMINOSITY - 1.0).abs() < 1e-12));
+        assert!(profile.column("nabla").unwrap().iter().all(|&nabla| nabla > 0.0));
+        let mut csv = Vec::new();
+        profile.write_csv(&mut csv).unwrap();
+        assert_eq!(Profile::read_csv(&csv[..]).unwrap(), profile);
     }
 }
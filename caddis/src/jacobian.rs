@@ -0,0 +1,237 @@
+//! Verification of analytic Jacobians against numerical derivatives.
+//!
+//! A residual function f: R^n -> R^m is differentiated numerically, by
+//! central differences
+//!
+//! ```text
+//! df_i/dx_j = (f_i(x + h e_j) - f_i(x - h e_j))/(2 h)
+//! ```
+//!
+//! with error O(h^2), or, for functions that also take complex arguments,
+//! by the complex step
+//!
+//! ```text
+//! df_i/dx_j = Im f_i(x + i h e_j)/h
+//! ```
+//!
+//! which has no cancellation, so h can be tiny and the derivatives are exact
+//! to rounding. A `Comparison` then holds the element-wise relative errors
+//! of an analytic Jacobian.
+
+use std::fmt;
+use std::ops::Range;
+
+use num::complex::Complex64;
+
+type Scalar = f64;
+
+/// Jacobian of `f` at `x` by central differences with step `steps[j]` in
+/// `x[j]`, as rows of df_i/dx_j.
+pub fn central_differences<F>(mut f: F, x: &[Scalar], steps: &[Scalar]) -> Vec<Vec<Scalar>>
+where
+    F: FnMut(&[Scalar]) -> Vec<Scalar>,
+{
+    let mut columns = Vec::with_capacity(x.len());
+    let mut y = x.to_vec();
+    for (j, h) in steps.iter().enumerate() {
+        y[j] = x[j] + h;
+        let forward = f(&y);
+        y[j] = x[j] - h;
+        let backward = f(&y);
+        y[j] = x[j];
+        columns.push(
+            forward
+                .iter()
+                .zip(backward.iter())
+                .map(|(a, b)| (a - b) / (2.0 * h))
+                .collect::<Vec<_>>(),
+        );
+    }
+    transpose(&columns)
+}
+
+/// Jacobian of `f` at `x` by complex steps of `h`, as rows of df_i/dx_j.
+pub fn complex_step<F>(mut f: F, x: &[Scalar], h: Scalar) -> Vec<Vec<Scalar>>
+where
+    F: FnMut(&[Complex64]) -> Vec<Complex64>,
+{
+    let mut z: Vec<Complex64> = x.iter().map(|&x| Complex64::new(x, 0.0)).collect();
+    let mut columns = Vec::with_capacity(x.len());
+    for j in 0..x.len() {
+        z[j].im = h;
+        columns.push(f(&z).iter().map(|f| f.im / h).collect::<Vec<_>>());
+        z[j].im = 0.0;
+    }
+    transpose(&columns)
+}
+
+fn transpose(columns: &[Vec<Scalar>]) -> Vec<Vec<Scalar>> {
+    let rows = columns.first().map_or(0, Vec::len);
+    (0..rows)
+        .map(|i| columns.iter().map(|column| column[i]).collect())
+        .collect()
+}
+
+/// Analytic against numerical Jacobian, element by element.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub analytic: Vec<Vec<Scalar>>,
+    pub numeric: Vec<Vec<Scalar>>,
+    /// Magnitude below which entries are compared absolutely
+    pub floor: Scalar,
+}
+
+impl Comparison {
+    pub fn new(analytic: Vec<Vec<Scalar>>, numeric: Vec<Vec<Scalar>>, floor: Scalar) -> Self {
+        assert_eq!(analytic.len(), numeric.len(), "rows");
+        assert!(
+            analytic
+                .iter()
+                .zip(numeric.iter())
+                .all(|(a, n)| a.len() == n.len()),
+            "columns"
+        );
+        Comparison {
+            analytic,
+            numeric,
+            floor,
+        }
+    }
+
+    /// Relative error |a - n|/(|a| + |n| + floor) of element (`row`, `col`).
+    pub fn error(&self, row: usize, col: usize) -> Scalar {
+        let (a, n) = (self.analytic[row][col], self.numeric[row][col]);
+        (a - n).abs() / (a.abs() + n.abs() + self.floor)
+    }
+
+    /// Element-wise relative errors.
+    pub fn errors(&self) -> Vec<Vec<Scalar>> {
+        (0..self.analytic.len())
+            .map(|row| {
+                (0..self.analytic[row].len())
+                    .map(|col| self.error(row, col))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Row, column and error of the worst element of the block of `rows`,
+    /// NaN errors counting as worst.
+    pub fn worst_in(&self, rows: Range<usize>) -> Option<(usize, usize, Scalar)> {
+        rows.flat_map(|row| (0..self.analytic[row].len()).map(move |col| (row, col)))
+            .map(|(row, col)| (row, col, self.error(row, col)))
+            .max_by(|a, b| match (a.2.is_nan(), b.2.is_nan()) {
+                (false, false) => a.2.total_cmp(&b.2),
+                (x, y) => x.cmp(&y),
+            })
+    }
+
+    pub fn worst(&self) -> Option<(usize, usize, Scalar)> {
+        self.worst_in(0..self.analytic.len())
+    }
+
+    /// Largest error of the block of `rows`, infinite if any is NaN.
+    pub fn max_in(&self, rows: Range<usize>) -> Scalar {
+        match self.worst_in(rows) {
+            Some((_, _, e)) if e.is_nan() => Scalar::INFINITY,
+            Some((_, _, e)) => e,
+            None => 0.0,
+        }
+    }
+
+    pub fn max(&self) -> Scalar {
+        self.max_in(0..self.analytic.len())
+    }
+
+    /// The worst element and, one per line, every element whose error
+    /// exceeds `tolerance`.
+    pub fn report(&self, tolerance: Scalar) -> String {
+        let mut report = self.to_string();
+        for (row, col, a, n, e) in self.failures(tolerance) {
+            report.push_str(&format!(
+                "\n  ({}, {}): analytic {:e}, numeric {:e}, error {:e}",
+                row, col, a, n, e
+            ));
+        }
+        report
+    }
+
+    /// Elements whose error exceeds `tolerance`, as (row, column, analytic,
+    /// numeric, error).
+    pub fn failures(&self, tolerance: Scalar) -> Vec<(usize, usize, Scalar, Scalar, Scalar)> {
+        let mut failures = Vec::new();
+        for (row, (a, n)) in self.analytic.iter().zip(self.numeric.iter()).enumerate() {
+            for col in 0..a.len() {
+                let e = self.error(row, col);
+                if e > tolerance || e.is_nan() {
+                    failures.push((row, col, a[col], n[col], e));
+                }
+            }
+        }
+        failures
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.worst() {
+            Some((row, col, e)) => write!(
+                f,
+                "largest relative error {:e} at ({}, {}): analytic {:e}, numeric {:e}",
+                e, row, col, self.analytic[row][col], self.numeric[row][col]
+            ),
+            None => write!(f, "empty Jacobian"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// f(x, y) = (x^2 sin y, exp(x y), x/y)
+    fn f(x: &[Complex64]) -> Vec<Complex64> {
+        vec![x[0] * x[0] * x[1].sin(), (x[0] * x[1]).exp(), x[0] / x[1]]
+    }
+
+    fn analytic(x: Scalar, y: Scalar) -> Vec<Vec<Scalar>> {
+        vec![
+            vec![2.0 * x * y.sin(), x * x * y.cos()],
+            vec![y * (x * y).exp(), x * (x * y).exp()],
+            vec![1.0 / y, -x / (y * y)],
+        ]
+    }
+
+    #[test]
+    fn test_derivatives() {
+        let x = [1.3, 0.7];
+        let real = |x: &[Scalar]| -> Vec<Scalar> {
+            let z: Vec<Complex64> = x.iter().map(|&x| Complex64::new(x, 0.0)).collect();
+            f(&z).iter().map(|f| f.re).collect()
+        };
+        let central = central_differences(real, &x, &[1e-5, 1e-5]);
+        let complex = complex_step(f, &x, 1e-20);
+        let comparison = Comparison::new(analytic(x[0], x[1]), central, 0.0);
+        assert!(comparison.max() < 1e-9);
+        let comparison = Comparison::new(analytic(x[0], x[1]), complex, 0.0);
+        assert!(comparison.max() < 1e-15);
+        assert_eq!(comparison.failures(1e-15), []);
+    }
+
+    #[test]
+    fn test_report() {
+        let mut wrong = analytic(1.3, 0.7);
+        wrong[2][1] *= 1.01;
+        wrong[0][0] = Scalar::NAN;
+        let comparison = Comparison::new(wrong, analytic(1.3, 0.7), 1e-3);
+        assert_eq!(comparison.worst().map(|w| (w.0, w.1)), Some((0, 0)));
+        assert_eq!(comparison.max(), Scalar::INFINITY);
+        assert!(comparison.max_in(1..3) > 4e-3 && comparison.max_in(1..2) == 0.0);
+        let failures = comparison.failures(1e-3);
+        assert_eq!(failures.len(), 2);
+        assert_eq!((failures[1].0, failures[1].1), (2, 1));
+        let report = comparison.report(1e-3);
+        assert!(report.starts_with("largest relative error NaN at (0, 0)"));
+        assert_eq!(report.lines().count(), 3);
+    }
+}
@@ -1,57 +1,45 @@
 pub mod geometry;
 pub mod units;
 pub mod henyey;
+pub mod scene;
+pub mod sdf;
+pub mod bvh;
+pub mod geodesy;
+pub mod section;
+pub mod convection;
+pub mod eos;
+pub mod nuclear;
+pub mod opacity;
+pub mod polytrope;
+pub mod tridiagonal;
+pub mod evolution;
+pub mod shooting;
+pub mod profile;
+pub mod jacobian;
+pub mod dual;
+pub mod tape;
+pub mod category;
+pub mod symbolic;
+pub mod ode;
+pub mod roots;
 
 use geometry::FiniteDimInnerSpace;
 use geometry::NormedSpace;
 use geometry::Vector;
-use num_traits::identities::{One, Zero};
 use units::QuantityValue;
 use henyey::Henyey;
 
-#[allow(dead_code, unused_variables)]
-trait Category {
-    fn id(f: Self) -> Self;
-}
-
-trait Monoidal {
-}
-
-
-#[allow(dead_code, unused_variables)]
-struct D<A, B> {
-    f: fn(A) -> B,
-    df: fn(A) -> B,
-}
-
-#[allow(dead_code, unused_variables)]
-impl<A> D<A, A> {
-    fn linear_d(f: fn(A) -> A) -> Self {
-        D {f: f, df: f}
-    }
-}
-
-impl<A> Category for D<A, A> {
-    fn id(f: Self) -> Self {
-        D {f:f.f, df:f.f}
-    }
-}
-
 fn main() {
-
-    let d = D {
-        f: |a: Vector| a + Vector::zero(),
-        df: |a: Vector| a + Vector::zero(),
-    };
-
-    let e = D {
-        f: |a| a + 1.0,
-        df: |a| a + 1.0,
-    };
-
-    let b = (d.f)(Vector::zero());
-    let c = (e.f)(1.0);
-    println!("{:?}, {:?}", b,c);
+    // `caddis scene <path>` writes the design shown by www/index.html.
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, command, path] = args.as_slice() {
+        if command == "scene" {
+            if let Err(e) = scene::capsule().save(path) {
+                println!("{}: {}", path, e);
+            }
+            return;
+        }
+    }
 
         let a = QuantityValue {
         number: 10000f64,
@@ -197,9 +185,15 @@ fn main() {
         Vector::orthonormal_subspace_basis(z, |_s: &Vector| { true })
     );
 
-        let mut h = Henyey::new(4usize, 1.0, 1.0);
-        h.A(1usize);
-        h.A(2usize);
-        h.C();
-        println!("{:?}", h.H);
+        let mut h = Henyey::from_quantities(
+            100usize,
+            QuantityValue::solar_mass(),
+            QuantityValue::solar_radius(),
+        )
+        .and_then(|h| h.with_luminosity_quantity(QuantityValue::solar_luminosity()))
+        .unwrap();
+        match h.solve() {
+            Ok(_) => h.profile().write_csv(std::io::stdout()).unwrap(),
+            Err(e) => println!("{}", e),
+        }
 }
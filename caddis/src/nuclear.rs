@@ -0,0 +1,439 @@
+//! Nuclear energy generation by hydrogen burning.
+//!
+//! Rates are the leading non-resonant terms of the Caughlan & Fowler (1988)
+//! fits, NA <sigma v> = A T9^(-2/3) exp(-tau T9^(-1/3)) in cm^3 mol^-1 s^-1,
+//! without screening. Energy generation rates are SI, W kg^-1, and exclude
+//! the energy carried away by neutrinos.
+
+use rgsl::linear_algebra::{LU_decomp, LU_solve};
+use rgsl::types::matrix::MatrixF64;
+use rgsl::types::permutation::Permutation;
+use rgsl::types::vector::VectorF64;
+
+use crate::eos::Composition;
+
+type Scalar = f64;
+
+/// Coefficients of the textbook pp-chain and CNO-cycle approximations,
+/// epsilon = E rho X X_i T6^(-2/3) exp(-tau T6^(-1/3)) (SI)
+const PP_CHAIN: Scalar = 0.238;
+const PP_TAU: Scalar = 33.80;
+const CNO_CYCLE: Scalar = 8.67e20;
+const CNO_TAU: Scalar = 152.28;
+/// N_A MeV per gram, turning MeV per reaction at a rate in mol g^-1 s^-1
+/// into W kg^-1
+const MEV_PER_MOLE: Scalar = 9.648_533e13;
+/// Fractions of the metals in carbon, nitrogen and oxygen; the rest is inert
+const CNO_FRACTIONS: [Scalar; 3] = [0.173, 0.053, 0.482];
+
+const MAX_NEWTON_ITERATIONS: usize = 20;
+const NEWTON_TOLERANCE: Scalar = 1e-12;
+/// Deepest halving of a network time step before it is accepted as is
+const MAX_SUBDIVISIONS: usize = 30;
+
+pub const SPECIES: usize = 6;
+pub const HYDROGEN: usize = 0;
+pub const HELIUM_3: usize = 1;
+pub const HELIUM_4: usize = 2;
+pub const CARBON: usize = 3;
+pub const NITROGEN: usize = 4;
+pub const OXYGEN: usize = 5;
+pub const MASS_NUMBERS: [Scalar; SPECIES] = [1.0, 3.0, 4.0, 12.0, 14.0, 16.0];
+
+pub trait EnergyGeneration {
+    /// Energy generation rate per unit mass and its partial derivatives with
+    /// respect to density and temperature, `[epsilon, depsilon/drho,
+    /// depsilon/dT]`.
+    fn energy(&self, rho: Scalar, temperature: Scalar) -> [Scalar; 3];
+}
+
+/// Temperature factor t^(-2/3) exp(-tau t^(-1/3)) of a non-resonant rate and
+/// its logarithmic derivative.
+fn non_resonant(t: Scalar, tau: Scalar) -> [Scalar; 2] {
+    let t_third = t.cbrt();
+    [
+        (-tau / t_third).exp() / (t_third * t_third),
+        -2.0 / 3.0 + tau / (3.0 * t_third),
+    ]
+}
+
+/// pp chain, epsilon = 0.238 rho X^2 T6^(-2/3) exp(-33.80 T6^(-1/3)).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PPChain {
+    pub x: Scalar,
+}
+
+impl PPChain {
+    pub fn new(composition: &Composition) -> Self {
+        PPChain { x: composition.x }
+    }
+}
+
+impl EnergyGeneration for PPChain {
+    fn energy(&self, rho: Scalar, temperature: Scalar) -> [Scalar; 3] {
+        let [f, n] = non_resonant(1e-6 * temperature, PP_TAU);
+        let epsilon = PP_CHAIN * rho * self.x * self.x * f;
+        [epsilon, epsilon / rho, n * epsilon / temperature]
+    }
+}
+
+/// CNO cycle in equilibrium, epsilon = 8.67e20 rho X X_CNO T6^(-2/3)
+/// exp(-152.28 T6^(-1/3)).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CNOCycle {
+    pub x: Scalar,
+    pub x_cno: Scalar,
+}
+
+impl CNOCycle {
+    pub fn new(composition: &Composition) -> Self {
+        CNOCycle {
+            x: composition.x,
+            x_cno: composition.z * CNO_FRACTIONS.iter().sum::<Scalar>(),
+        }
+    }
+}
+
+impl EnergyGeneration for CNOCycle {
+    fn energy(&self, rho: Scalar, temperature: Scalar) -> [Scalar; 3] {
+        let [f, n] = non_resonant(1e-6 * temperature, CNO_TAU);
+        let epsilon = CNO_CYCLE * rho * self.x * self.x_cno * f;
+        [epsilon, epsilon / rho, n * epsilon / temperature]
+    }
+}
+
+/// Sum of two energy sources.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sum<A, B>(pub A, pub B);
+
+impl<A: EnergyGeneration, B: EnergyGeneration> EnergyGeneration for Sum<A, B> {
+    fn energy(&self, rho: Scalar, temperature: Scalar) -> [Scalar; 3] {
+        let a = self.0.energy(rho, temperature);
+        let b = self.1.energy(rho, temperature);
+        [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+    }
+}
+
+/// Two-body reaction a + b, with the fast steps that follow it folded in.
+struct Reaction {
+    a: usize,
+    b: usize,
+    /// Rate fit coefficient and Gamow exponent in T9
+    coefficient: Scalar,
+    tau: Scalar,
+    /// Net change of the molar abundances per reaction
+    change: [Scalar; SPECIES],
+    /// Energy released per reaction, less neutrino losses (MeV)
+    q: Scalar,
+}
+
+/// p(p,e+ nu)d(p,gamma)He-3, He-3(He-3,2p)He-4, He-3(He-4,gamma)Be-7 and
+/// on to two He-4, C-12(p,gamma) and on to N-14, N-14(p,gamma) and on to
+/// C-12 + He-4, and O-16(p,gamma) and on to N-14 + He-4.
+const REACTIONS: [Reaction; 6] = [
+    Reaction {
+        a: HYDROGEN,
+        b: HYDROGEN,
+        coefficient: 4.01e-15,
+        tau: 3.380,
+        change: [-3.0, 1.0, 0.0, 0.0, 0.0, 0.0],
+        q: 6.671,
+    },
+    Reaction {
+        a: HELIUM_3,
+        b: HELIUM_3,
+        coefficient: 6.04e10,
+        tau: 12.276,
+        change: [2.0, -2.0, 1.0, 0.0, 0.0, 0.0],
+        q: 12.860,
+    },
+    Reaction {
+        a: HELIUM_3,
+        b: HELIUM_4,
+        coefficient: 5.61e6,
+        tau: 12.826,
+        change: [-1.0, -1.0, 1.0, 0.0, 0.0, 0.0],
+        q: 19.0,
+    },
+    Reaction {
+        a: CARBON,
+        b: HYDROGEN,
+        coefficient: 2.04e7,
+        tau: 13.690,
+        change: [-2.0, 0.0, 0.0, -1.0, 1.0, 0.0],
+        q: 11.0,
+    },
+    Reaction {
+        a: NITROGEN,
+        b: HYDROGEN,
+        coefficient: 4.90e7,
+        tau: 15.228,
+        change: [-2.0, 0.0, 1.0, 1.0, -1.0, 0.0],
+        q: 14.0,
+    },
+    Reaction {
+        a: OXYGEN,
+        b: HYDROGEN,
+        coefficient: 1.50e8,
+        tau: 16.692,
+        change: [-2.0, 0.0, 1.0, 0.0, 1.0, -1.0],
+        q: 3.6,
+    },
+];
+
+/// Hydrogen burning network of H, He-3, He-4, C-12, N-14 and O-16, following
+/// the pp chains and the CN and ON cycles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Network {
+    /// Mass fractions, indexed by `HYDROGEN` to `OXYGEN`
+    pub abundances: [Scalar; SPECIES],
+}
+
+impl Network {
+    pub fn new(abundances: [Scalar; SPECIES]) -> Self {
+        Network { abundances }
+    }
+
+    /// Network without He-3, with the metals split into solar fractions of
+    /// carbon, nitrogen and oxygen.
+    pub fn from_composition(composition: &Composition) -> Self {
+        let [c, n, o] = CNO_FRACTIONS;
+        let z = composition.z;
+        Network::new([composition.x, 0.0, composition.y, c * z, n * z, o * z])
+    }
+
+    /// Hydrogen, helium and metal mass fractions of the network, counting
+    /// everything but hydrogen and helium as metals.
+    pub fn composition(&self) -> Composition {
+        let x = self.abundances[HYDROGEN];
+        let y = self.abundances[HELIUM_3] + self.abundances[HELIUM_4];
+        Composition::new(x, y, 1.0 - x - y)
+    }
+
+    /// Molar abundances Y = X / A (mol g^-1).
+    fn molar(&self) -> [Scalar; SPECIES] {
+        let mut y = self.abundances;
+        for (y, a) in y.iter_mut().zip(MASS_NUMBERS.iter()) {
+            *y /= a;
+        }
+        y
+    }
+
+    /// Rates of all reactions (mol g^-1 s^-1) at molar abundances `y`, and
+    /// their logarithmic derivatives with respect to T.
+    fn rates(y: &[Scalar; SPECIES], rho: Scalar, temperature: Scalar) -> [[Scalar; 2]; 6] {
+        let mut rates = [[0.0; 2]; 6];
+        for (rate, reaction) in rates.iter_mut().zip(REACTIONS.iter()) {
+            let [f, n] = non_resonant(1e-9 * temperature, reaction.tau);
+            let symmetry = if reaction.a == reaction.b { 0.5 } else { 1.0 };
+            let lambda = 1e-3 * rho * reaction.coefficient * f * symmetry;
+            *rate = [lambda * y[reaction.a] * y[reaction.b], n];
+        }
+        rates
+    }
+
+    /// Rates of change of the molar abundances at `y`, and their Jacobian.
+    fn derivatives(
+        y: &[Scalar; SPECIES],
+        rho: Scalar,
+        temperature: Scalar,
+    ) -> ([Scalar; SPECIES], [[Scalar; SPECIES]; SPECIES]) {
+        let mut f = [0.0; SPECIES];
+        let mut jacobian = [[0.0; SPECIES]; SPECIES];
+        for (rate, reaction) in Self::rates(y, rho, temperature)
+            .iter()
+            .zip(REACTIONS.iter())
+        {
+            let (a, b) = (reaction.a, reaction.b);
+            // Derivatives of the rate with respect to y_a and y_b
+            let r_a = if y[a] > 0.0 { rate[0] / y[a] } else { 0.0 };
+            let r_b = if y[b] > 0.0 { rate[0] / y[b] } else { 0.0 };
+            for (i, change) in reaction.change.iter().enumerate() {
+                f[i] += change * rate[0];
+                jacobian[i][a] += change * r_a;
+                jacobian[i][b] += change * r_b;
+            }
+        }
+        (f, jacobian)
+    }
+
+    /// Rates of change of the mass fractions (s^-1).
+    pub fn abundance_derivatives(&self, rho: Scalar, temperature: Scalar) -> [Scalar; SPECIES] {
+        let (mut f, _) = Self::derivatives(&self.molar(), rho, temperature);
+        for (f, a) in f.iter_mut().zip(MASS_NUMBERS.iter()) {
+            *f *= a;
+        }
+        f
+    }
+
+    /// Burns at constant `rho` and `temperature` for `dt` seconds by
+    /// backward Euler steps, halving the step wherever Newton's method fails
+    /// to converge.
+    pub fn evolve(&mut self, rho: Scalar, temperature: Scalar, dt: Scalar) {
+        let y = self.burn(self.molar(), rho, temperature, dt, 0);
+        for (i, y) in y.iter().enumerate() {
+            self.abundances[i] = y * MASS_NUMBERS[i];
+        }
+    }
+
+    fn burn(
+        &self,
+        y: [Scalar; SPECIES],
+        rho: Scalar,
+        temperature: Scalar,
+        dt: Scalar,
+        depth: usize,
+    ) -> [Scalar; SPECIES] {
+        match Self::backward_euler(&y, rho, temperature, dt) {
+            Some(next) => next,
+            None if depth < MAX_SUBDIVISIONS => {
+                let half = self.burn(y, rho, temperature, 0.5 * dt, depth + 1);
+                self.burn(half, rho, temperature, 0.5 * dt, depth + 1)
+            }
+            None => y,
+        }
+    }
+
+    /// Solves y - y_0 - dt f(y) = 0 by Newton's method, or None if it does
+    /// not converge or an abundance turns negative.
+    fn backward_euler(
+        y0: &[Scalar; SPECIES],
+        rho: Scalar,
+        temperature: Scalar,
+        dt: Scalar,
+    ) -> Option<[Scalar; SPECIES]> {
+        let norm: Scalar = y0.iter().sum();
+        let mut y = *y0;
+        let mut lu = MatrixF64::new(SPECIES, SPECIES).unwrap();
+        let mut b = VectorF64::new(SPECIES).unwrap();
+        let mut dy = VectorF64::new(SPECIES).unwrap();
+        let mut p = Permutation::new(SPECIES).unwrap();
+        let mut signum = 0;
+        for _ in 0..MAX_NEWTON_ITERATIONS {
+            let (f, jacobian) = Self::derivatives(&y, rho, temperature);
+            for (i, row) in jacobian.iter().enumerate() {
+                b.set(i, y0[i] + dt * f[i] - y[i]);
+                for (j, df) in row.iter().enumerate() {
+                    let identity = if i == j { 1.0 } else { 0.0 };
+                    lu.set(i, j, identity - dt * df);
+                }
+            }
+            if LU_decomp(&mut lu, &mut p, &mut signum).is_err()
+                || LU_solve(&lu, &p, &b, &mut dy).is_err()
+            {
+                return None;
+            }
+            let mut correction: Scalar = 0.0;
+            for (i, y) in y.iter_mut().enumerate() {
+                *y += dy.get(i);
+                correction = correction.max(dy.get(i).abs());
+            }
+            if y.iter().any(|y| *y < -NEWTON_TOLERANCE * norm) {
+                return None;
+            }
+            if correction <= NEWTON_TOLERANCE * norm {
+                for y in y.iter_mut() {
+                    *y = y.max(0.0);
+                }
+                return Some(y);
+            }
+        }
+        None
+    }
+}
+
+impl EnergyGeneration for Network {
+    fn energy(&self, rho: Scalar, temperature: Scalar) -> [Scalar; 3] {
+        let mut energy = [0.0; 3];
+        for (rate, reaction) in Self::rates(&self.molar(), rho, temperature)
+            .iter()
+            .zip(REACTIONS.iter())
+        {
+            let epsilon = MEV_PER_MOLE * reaction.q * rate[0];
+            energy[0] += epsilon;
+            energy[1] += epsilon / rho;
+            energy[2] += rate[1] * epsilon / temperature;
+        }
+        energy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Centre of the present Sun
+    const RHO: Scalar = 1.5e5;
+    const T: Scalar = 1.57e7;
+
+    fn check_partials<E: EnergyGeneration>(source: &E, rho: Scalar, temperature: Scalar) {
+        let [epsilon, epsilon_rho, epsilon_t] = source.energy(rho, temperature);
+        let fd_rho = (source.energy(rho * 1.000001, temperature)[0]
+            - source.energy(rho * 0.999999, temperature)[0])
+            / (0.000002 * rho);
+        let fd_t = (source.energy(rho, temperature * 1.000001)[0]
+            - source.energy(rho, temperature * 0.999999)[0])
+            / (0.000002 * temperature);
+        assert!((fd_rho - epsilon_rho).abs() * rho < 1e-6 * epsilon);
+        assert!((fd_t - epsilon_t).abs() * temperature < 1e-6 * epsilon);
+    }
+
+    #[test]
+    fn test_analytic() {
+        let solar = Composition::solar();
+        let (pp, cno) = (PPChain::new(&solar), CNOCycle::new(&solar));
+        check_partials(&Sum(pp, cno), RHO, T);
+        // pp chain in the Sun, CNO cycle in hotter stars.
+        assert!(pp.energy(RHO, T)[0] > cno.energy(RHO, T)[0]);
+        assert!(pp.energy(RHO, 2.5e7)[0] < cno.energy(RHO, 2.5e7)[0]);
+        // Temperature exponents of about 4 and 18 at 15 MK.
+        let [epsilon, _, epsilon_t] = pp.energy(RHO, 1.5e7);
+        assert!((epsilon_t * 1.5e7 / epsilon - 3.8).abs() < 0.2);
+        let [epsilon, _, epsilon_t] = cno.energy(RHO, 1.5e7);
+        assert!((epsilon_t * 1.5e7 / epsilon - 19.9).abs() < 0.2);
+    }
+
+    #[test]
+    fn test_network_energy() {
+        let mut network = Network::from_composition(&Composition::solar());
+        // He-3 at its equilibrium abundance
+        network.evolve(RHO, T, 1e15);
+        check_partials(&network, RHO, T);
+        let epsilon = network.energy(RHO, T)[0];
+        assert!(epsilon > 1e-3 && epsilon < 1e-2);
+        let analytic = Sum(
+            PPChain::new(&Composition::solar()),
+            CNOCycle::new(&Composition::solar()),
+        );
+        let ratio = epsilon / analytic.energy(RHO, T)[0];
+        assert!(ratio > 0.5 && ratio < 2.0);
+    }
+
+    #[test]
+    fn test_network_evolve() {
+        let solar = Composition::solar();
+        let mut network = Network::from_composition(&solar);
+        let total: Scalar = network.abundances.iter().sum();
+        // Five billion years at the centre of the Sun
+        network.evolve(RHO, T, 1.6e17);
+        let burnt = network.abundances;
+        assert!((burnt.iter().sum::<Scalar>() - total).abs() < 1e-10);
+        assert!(burnt[HYDROGEN] < 0.5 && burnt[HYDROGEN] > 0.2);
+        assert!((burnt[HELIUM_4] - solar.y - (solar.x - burnt[HYDROGEN])).abs() < 1e-2);
+        assert!(burnt[HELIUM_3] > 0.0 && burnt[HELIUM_3] < 1e-3);
+        // CN cycle in equilibrium, carbon turned into nitrogen
+        assert!(burnt[NITROGEN] > 10.0 * burnt[CARBON]);
+
+        // Backward Euler is first order in the step.
+        let stepped = |steps: usize| {
+            let mut network = Network::from_composition(&solar);
+            for _ in 0..steps {
+                network.evolve(RHO, T, 1.6e17 / steps as Scalar);
+            }
+            network.abundances[HYDROGEN]
+        };
+        let (coarse, fine, finest) = (stepped(10), stepped(100), stepped(1000));
+        assert!((fine - finest).abs() < 0.2 * (coarse - fine).abs());
+        assert!((finest - burnt[HYDROGEN]).abs() < 0.05);
+    }
+}
@@ -0,0 +1,864 @@
+//! Initial value problems dy/dt = f(t, y).
+//!
+//! Three integrators share one interface, generic over the `State` y:
+//!
+//! * `Rk4`, the classical fourth order Runge-Kutta method at a fixed step;
+//! * `DormandPrince`, the adaptive 5(4) pair with local extrapolation and
+//!   its fourth order dense output;
+//! * `Bdf`, the implicit, L-stable, second order backward differentiation
+//!   formula at variable step, for stiff problems such as reaction
+//!   networks, with Newton iterations on a finite-difference Jacobian.
+//!
+//! Adaptive steps keep the largest component of the local error estimate
+//! below `absolute + relative |y|`. A step whose derivatives are not finite
+//! is retried at a fifth of its size, so a right-hand side may return NaN
+//! to reject states outside its domain. Integration runs backwards if
+//! `t1 < t0`.
+
+use std::fmt;
+
+use rgsl::linear_algebra::{LU_decomp, LU_solve};
+use rgsl::types::matrix::MatrixF64;
+use rgsl::types::permutation::Permutation;
+use rgsl::types::vector::VectorF64;
+
+use crate::geometry::{FiniteDimVectorSpace, Vector};
+
+type Scalar = f64;
+
+const SAFETY: Scalar = 0.9;
+/// Bounds of the factor of successive step sizes
+const MIN_FACTOR: Scalar = 0.2;
+const MAX_FACTOR: Scalar = 5.0;
+/// Largest ratio of successive BDF2 steps, inside its zero-stability bound
+/// 1 + sqrt 2
+const MAX_BDF_RATIO: Scalar = 2.0;
+/// Smallest step, relative to t
+const MIN_STEP: Scalar = 1e-14;
+const MAX_STEPS: usize = 100_000;
+const MAX_NEWTON_ITERATIONS: usize = 8;
+/// Newton corrections converge below this fraction of the tolerance
+const NEWTON_TOLERANCE: Scalar = 1e-2;
+
+/// Dormand-Prince 5(4) tableau
+const C: [Scalar; 7] = [0.0, 0.2, 0.3, 0.8, 8.0 / 9.0, 1.0, 1.0];
+const A: [[Scalar; 6]; 7] = [
+    [0.0; 6],
+    [0.2, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [3.0 / 40.0, 9.0 / 40.0, 0.0, 0.0, 0.0, 0.0],
+    [44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0, 0.0, 0.0, 0.0],
+    [
+        19372.0 / 6561.0,
+        -25360.0 / 2187.0,
+        64448.0 / 6561.0,
+        -212.0 / 729.0,
+        0.0,
+        0.0,
+    ],
+    [
+        9017.0 / 3168.0,
+        -355.0 / 33.0,
+        46732.0 / 5247.0,
+        49.0 / 176.0,
+        -5103.0 / 18656.0,
+        0.0,
+    ],
+    [
+        35.0 / 384.0,
+        0.0,
+        500.0 / 1113.0,
+        125.0 / 192.0,
+        -2187.0 / 6784.0,
+        11.0 / 84.0,
+    ],
+];
+/// Fifth order weights minus the embedded fourth order ones
+const E: [Scalar; 7] = [
+    71.0 / 57600.0,
+    0.0,
+    -71.0 / 16695.0,
+    71.0 / 1920.0,
+    -17253.0 / 339200.0,
+    22.0 / 525.0,
+    -1.0 / 40.0,
+];
+/// Dense output weights, after Hairer's DOPRI5
+const D: [Scalar; 7] = [
+    -12715105075.0 / 11282082432.0,
+    0.0,
+    87487479700.0 / 32700410799.0,
+    -10690763975.0 / 1880347072.0,
+    701980252875.0 / 199316789632.0,
+    -1453857185.0 / 822651844.0,
+    69997945.0 / 29380423.0,
+];
+
+/// Vector space of the solution, with components for error norms and
+/// linear algebra.
+pub trait State: Clone {
+    fn dimension(&self) -> usize;
+    fn component(&self, i: usize) -> Scalar;
+    /// State of the same shape as `self` with components `components`.
+    fn with_components(&self, components: &[Scalar]) -> Self;
+    /// self + a x
+    fn add_scaled(&self, a: Scalar, x: &Self) -> Self;
+
+    /// a self
+    fn scale(&self, a: Scalar) -> Self {
+        self.add_scaled(-1.0, self).add_scaled(a, self)
+    }
+
+    fn components(&self) -> Vec<Scalar> {
+        (0..self.dimension()).map(|i| self.component(i)).collect()
+    }
+
+    fn is_finite(&self) -> bool {
+        (0..self.dimension()).all(|i| self.component(i).is_finite())
+    }
+}
+
+impl State for Scalar {
+    fn dimension(&self) -> usize {
+        1
+    }
+
+    fn component(&self, _: usize) -> Scalar {
+        *self
+    }
+
+    fn with_components(&self, components: &[Scalar]) -> Self {
+        components[0]
+    }
+
+    fn add_scaled(&self, a: Scalar, x: &Self) -> Self {
+        self + a * x
+    }
+}
+
+impl<const N: usize> State for [Scalar; N] {
+    fn dimension(&self) -> usize {
+        N
+    }
+
+    fn component(&self, i: usize) -> Scalar {
+        self[i]
+    }
+
+    fn with_components(&self, components: &[Scalar]) -> Self {
+        let mut y = [0.0; N];
+        y.copy_from_slice(components);
+        y
+    }
+
+    fn add_scaled(&self, a: Scalar, x: &Self) -> Self {
+        let mut y = *self;
+        for (y, x) in y.iter_mut().zip(x.iter()) {
+            *y += a * x;
+        }
+        y
+    }
+}
+
+impl State for Vec<Scalar> {
+    fn dimension(&self) -> usize {
+        self.len()
+    }
+
+    fn component(&self, i: usize) -> Scalar {
+        self[i]
+    }
+
+    fn with_components(&self, components: &[Scalar]) -> Self {
+        components.to_vec()
+    }
+
+    fn add_scaled(&self, a: Scalar, x: &Self) -> Self {
+        self.iter().zip(x.iter()).map(|(y, x)| y + a * x).collect()
+    }
+}
+
+impl State for Vector {
+    fn dimension(&self) -> usize {
+        <Vector as FiniteDimVectorSpace>::dimension()
+    }
+
+    fn component(&self, i: usize) -> Scalar {
+        self[i]
+    }
+
+    fn with_components(&self, components: &[Scalar]) -> Self {
+        Vector::from_slice(components)
+    }
+
+    fn add_scaled(&self, a: Scalar, x: &Self) -> Self {
+        self.clone() + x.clone() * a
+    }
+}
+
+/// Pairs, such as the position and velocity of an orbit.
+impl<P: State, Q: State> State for (P, Q) {
+    fn dimension(&self) -> usize {
+        self.0.dimension() + self.1.dimension()
+    }
+
+    fn component(&self, i: usize) -> Scalar {
+        let n = self.0.dimension();
+        if i < n {
+            self.0.component(i)
+        } else {
+            self.1.component(i - n)
+        }
+    }
+
+    fn with_components(&self, components: &[Scalar]) -> Self {
+        let (p, q) = components.split_at(self.0.dimension());
+        (self.0.with_components(p), self.1.with_components(q))
+    }
+
+    fn add_scaled(&self, a: Scalar, x: &Self) -> Self {
+        (self.0.add_scaled(a, &x.0), self.1.add_scaled(a, &x.1))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OdeError {
+    /// The step fell below the resolution of t at this t.
+    StepTooSmall(Scalar),
+    /// The allowed number of steps ran out at this t.
+    TooManySteps(Scalar),
+}
+
+impl fmt::Display for OdeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OdeError::StepTooSmall(t) => write!(f, "step size too small at t = {:e}", t),
+            OdeError::TooManySteps(t) => write!(f, "too many steps at t = {:e}", t),
+        }
+    }
+}
+
+/// Work done by an integration.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Statistics {
+    pub accepted: usize,
+    pub rejected: usize,
+    /// Evaluations of the right-hand side, including those of Jacobians
+    pub evaluations: usize,
+    pub jacobians: usize,
+    pub factorizations: usize,
+    pub newton_iterations: usize,
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} steps ({} rejected), {} evaluations, {} Jacobians, {} factorizations, {} Newton iterations",
+            self.accepted,
+            self.rejected,
+            self.evaluations,
+            self.jacobians,
+            self.factorizations,
+            self.newton_iterations
+        )
+    }
+}
+
+/// Accepted steps of an integration and an interpolant between them.
+#[derive(Debug, Clone)]
+pub struct Solution<S> {
+    pub t: Vec<Scalar>,
+    pub y: Vec<S>,
+    pub statistics: Statistics,
+    /// Coefficients r of y(t_i + theta h_i) = r_0 + theta (r_1 + (1 - theta)
+    /// (r_2 + theta (r_3 + (1 - theta) r_4)))
+    dense: Vec<[S; 5]>,
+}
+
+impl<S: State> Solution<S> {
+    fn new(t: Scalar, y: S) -> Self {
+        Solution {
+            t: vec![t],
+            y: vec![y],
+            statistics: Statistics::default(),
+            dense: Vec::new(),
+        }
+    }
+
+    /// Appends the step to `t` ending at `y`, where the derivatives at its
+    /// ends are `f0` and `f1`, with the fifth coefficient `r4` of the
+    /// interpolant; cubic Hermite if zero.
+    fn push(&mut self, t: Scalar, y: S, f0: &S, f1: &S, r4: S) {
+        let (t0, y0) = (*self.t.last().unwrap(), self.y.last().unwrap().clone());
+        let h = t - t0;
+        let r1 = y.add_scaled(-1.0, &y0);
+        let r2 = f0.scale(h).add_scaled(-1.0, &r1);
+        let r3 = r1.add_scaled(-h, f1).add_scaled(-1.0, &r2);
+        self.dense.push([y0, r1, r2, r3, r4]);
+        self.t.push(t);
+        self.y.push(y);
+    }
+
+    pub fn last(&self) -> &S {
+        self.y.last().unwrap()
+    }
+
+    /// Interpolated solution at `t`, or None outside the integration.
+    pub fn at(&self, t: Scalar) -> Option<S> {
+        let (first, last) = (self.t[0], *self.t.last().unwrap());
+        if (t - first) * (t - last) > 0.0 {
+            return None;
+        }
+        if self.dense.is_empty() {
+            return Some(self.y[0].clone());
+        }
+        // Steps are ordered along the direction of integration.
+        let i = self.t[1..]
+            .iter()
+            .position(|&ti| (ti - t) * (last - first) >= 0.0)
+            .unwrap_or(self.dense.len() - 1);
+        let theta = (t - self.t[i]) / (self.t[i + 1] - self.t[i]);
+        let [r0, r1, r2, r3, r4] = &self.dense[i];
+        let inner = r3.add_scaled(1.0 - theta, r4);
+        let inner = r2.add_scaled(theta, &inner);
+        let inner = r1.add_scaled(1.0 - theta, &inner);
+        Some(r0.add_scaled(theta, &inner))
+    }
+}
+
+/// Largest component of `error` relative to `absolute + relative |y|`,
+/// with |y| the larger of `y0` and `y1`.
+fn error_norm<S: State>(error: &S, y0: &S, y1: &S, absolute: Scalar, relative: Scalar) -> Scalar {
+    (0..error.dimension()).fold(0.0, |norm: Scalar, i| {
+        let e = error.component(i).abs();
+        if e == 0.0 {
+            return norm;
+        }
+        let y = y0.component(i).abs().max(y1.component(i).abs());
+        norm.max(e / (absolute + relative * y))
+    })
+}
+
+/// Classical Runge-Kutta at a fixed step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rk4 {
+    steps: usize,
+}
+
+impl Rk4 {
+    /// Integrator taking `steps` equal steps.
+    pub fn new(steps: usize) -> Self {
+        assert!(steps > 0, "no steps");
+        Rk4 { steps }
+    }
+
+    pub fn solve<S, F>(&self, mut f: F, t0: Scalar, y0: S, t1: Scalar) -> Solution<S>
+    where
+        S: State,
+        F: FnMut(Scalar, &S) -> S,
+    {
+        let h = (t1 - t0) / self.steps as Scalar;
+        let mut solution = Solution::new(t0, y0.clone());
+        let mut y = y0;
+        let mut k1 = f(t0, &y);
+        for n in 0..self.steps {
+            let t = t0 + n as Scalar * h;
+            let k2 = f(t + 0.5 * h, &y.add_scaled(0.5 * h, &k1));
+            let k3 = f(t + 0.5 * h, &y.add_scaled(0.5 * h, &k2));
+            let k4 = f(t + h, &y.add_scaled(h, &k3));
+            let increment = k1
+                .add_scaled(2.0, &k2)
+                .add_scaled(2.0, &k3)
+                .add_scaled(1.0, &k4);
+            y = y.add_scaled(h / 6.0, &increment);
+            let next = if n + 1 == self.steps {
+                t1
+            } else {
+                t0 + (n + 1) as Scalar * h
+            };
+            let f1 = f(next, &y);
+            solution.push(next, y.clone(), &k1, &f1, k1.scale(0.0));
+            k1 = f1;
+        }
+        solution.statistics.accepted = self.steps;
+        solution.statistics.evaluations = 4 * self.steps + 1;
+        solution
+    }
+}
+
+/// Dormand-Prince 5(4) with step size control and dense output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DormandPrince {
+    relative: Scalar,
+    absolute: Scalar,
+    initial_step: Option<Scalar>,
+    max_steps: usize,
+}
+
+impl DormandPrince {
+    /// Integrator of relative tolerance `relative`.
+    pub fn new(relative: Scalar) -> Self {
+        DormandPrince {
+            relative,
+            absolute: 0.0,
+            initial_step: None,
+            max_steps: MAX_STEPS,
+        }
+    }
+
+    pub fn with_absolute_tolerance(mut self, absolute: Scalar) -> Self {
+        self.absolute = absolute;
+        self
+    }
+
+    /// First step size, by default estimated from the derivatives.
+    pub fn with_initial_step(mut self, h: Scalar) -> Self {
+        self.initial_step = Some(h.abs());
+        self
+    }
+
+    /// Largest number of attempted steps.
+    pub fn with_max_steps(mut self, steps: usize) -> Self {
+        self.max_steps = steps;
+        self
+    }
+
+    pub fn solve<S, F>(
+        &self,
+        mut f: F,
+        t0: Scalar,
+        y0: S,
+        t1: Scalar,
+    ) -> Result<Solution<S>, OdeError>
+    where
+        S: State,
+        F: FnMut(Scalar, &S) -> S,
+    {
+        let direction = (t1 - t0).signum();
+        let mut solution = Solution::new(t0, y0.clone());
+        let (mut t, mut y) = (t0, y0);
+        let mut k1 = f(t, &y);
+        let mut statistics = Statistics {
+            evaluations: 1,
+            ..Statistics::default()
+        };
+        let mut h = direction
+            * self
+                .initial_step
+                .unwrap_or_else(|| initial_step(&y, &k1, (t1 - t0).abs()));
+        for _ in 0..self.max_steps {
+            if (t1 - t) * direction <= 0.0 {
+                solution.statistics = statistics;
+                return Ok(solution);
+            }
+            if (t + h - t1) * direction > 0.0 {
+                h = t1 - t;
+            }
+            match self.step(&mut f, t, &y, &k1, h, &mut statistics) {
+                Some((next, k, error)) if error <= 1.0 => {
+                    let r4 = k
+                        .iter()
+                        .zip(D.iter())
+                        .fold(k[0].scale(0.0), |r, (k, d)| r.add_scaled(h * d, k));
+                    t += h;
+                    solution.push(t, next.clone(), &k[0], &k[6], r4);
+                    statistics.accepted += 1;
+                    y = next;
+                    k1 = k[6].clone();
+                    h *= (SAFETY * error.powf(-0.2)).min(MAX_FACTOR);
+                }
+                Some((_, _, error)) => {
+                    statistics.rejected += 1;
+                    h *= (SAFETY * error.powf(-0.2)).max(MIN_FACTOR);
+                }
+                None => {
+                    statistics.rejected += 1;
+                    h *= MIN_FACTOR;
+                }
+            }
+            if h.abs() < MIN_STEP * t.abs() {
+                return Err(OdeError::StepTooSmall(t));
+            }
+        }
+        Err(OdeError::TooManySteps(t))
+    }
+
+    /// Step `h` from `t`, returning the fifth order solution, the stages and
+    /// the error relative to the tolerance, or None if a stage is not
+    /// finite. The seventh stage is the derivative at the new solution.
+    #[allow(clippy::type_complexity)]
+    fn step<S, F>(
+        &self,
+        f: &mut F,
+        t: Scalar,
+        y: &S,
+        k1: &S,
+        h: Scalar,
+        statistics: &mut Statistics,
+    ) -> Option<(S, Vec<S>, Scalar)>
+    where
+        S: State,
+        F: FnMut(Scalar, &S) -> S,
+    {
+        let mut k = vec![k1.clone()];
+        for i in 1..7 {
+            let z = (0..i).fold(y.clone(), |z, j| z.add_scaled(h * A[i][j], &k[j]));
+            statistics.evaluations += 1;
+            let ki = f(t + C[i] * h, &z);
+            if !z.is_finite() || !ki.is_finite() {
+                return None;
+            }
+            k.push(ki);
+        }
+        let next = (0..6).fold(y.clone(), |z, j| z.add_scaled(h * A[6][j], &k[j]));
+        let error = (0..7).fold(y.scale(0.0), |e, i| e.add_scaled(h * E[i], &k[i]));
+        let error = error_norm(&error, y, &next, self.absolute, self.relative);
+        Some((next, k, error))
+    }
+}
+
+/// First step, a small fraction of the shortest scale |y/f| of the
+/// components, and of the span of the integration.
+fn initial_step<S: State>(y: &S, f: &S, span: Scalar) -> Scalar {
+    let scale = (0..y.dimension())
+        .filter(|&i| f.component(i) != 0.0 && y.component(i) != 0.0)
+        .fold(span, |a, i| a.min((y.component(i) / f.component(i)).abs()));
+    1e-3 * scale
+}
+
+/// Variable-step BDF2, started by a backward Euler step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bdf {
+    relative: Scalar,
+    absolute: Scalar,
+    initial_step: Option<Scalar>,
+    max_steps: usize,
+}
+
+impl Bdf {
+    /// Integrator of relative tolerance `relative` and absolute tolerance
+    /// `absolute`.
+    pub fn new(relative: Scalar, absolute: Scalar) -> Self {
+        Bdf {
+            relative,
+            absolute,
+            initial_step: None,
+            max_steps: MAX_STEPS,
+        }
+    }
+
+    pub fn with_initial_step(mut self, h: Scalar) -> Self {
+        self.initial_step = Some(h.abs());
+        self
+    }
+
+    pub fn with_max_steps(mut self, steps: usize) -> Self {
+        self.max_steps = steps;
+        self
+    }
+
+    /// Integrates by
+    ///
+    /// ```text
+    /// y_n+1 = psi + gamma h f(t_n+1, y_n+1)
+    /// psi = (1 + w)^2/(1 + 2 w) y_n - w^2/(1 + 2 w) y_n-1
+    /// gamma = (1 + w)/(1 + 2 w)
+    /// ```
+    ///
+    /// with w = h_n/h_n-1 (w = 0 is backward Euler). The local error is
+    /// estimated from the difference to the quadratic predictor through
+    /// y_n-1, y_n and f_n.
+    pub fn solve<S, F>(
+        &self,
+        mut f: F,
+        t0: Scalar,
+        y0: S,
+        t1: Scalar,
+    ) -> Result<Solution<S>, OdeError>
+    where
+        S: State,
+        F: FnMut(Scalar, &S) -> S,
+    {
+        let direction = (t1 - t0).signum();
+        let mut solution = Solution::new(t0, y0.clone());
+        let mut statistics = Statistics::default();
+        let (mut t, mut y) = (t0, y0.clone());
+        let mut fy = f(t, &y);
+        statistics.evaluations += 1;
+        let mut previous: Option<(Scalar, S)> = None;
+        let mut jacobian: Option<Vec<Vec<Scalar>>> = None;
+        let mut h = direction
+            * self
+                .initial_step
+                .unwrap_or_else(|| initial_step(&y, &fy, (t1 - t0).abs()));
+        for _ in 0..self.max_steps {
+            if (t1 - t) * direction <= 0.0 {
+                solution.statistics = statistics;
+                return Ok(solution);
+            }
+            if (t + h - t1) * direction > 0.0 {
+                h = t1 - t;
+            }
+            let (gamma, psi, predictor, ratio) = match &previous {
+                Some((k, y_1)) => {
+                    let w = h / k;
+                    let psi = y
+                        .scale((1.0 + w).powi(2) / (1.0 + 2.0 * w))
+                        .add_scaled(-w * w / (1.0 + 2.0 * w), y_1);
+                    // p(t_n + s) = y_n + f_n s + a s^2 through y_n-1
+                    let a = y_1
+                        .add_scaled(-1.0, &y)
+                        .add_scaled(*k, &fy)
+                        .scale(1.0 / (k * k));
+                    let predictor = y.add_scaled(h, &fy).add_scaled(h * h, &a);
+                    ((1.0 + w) / (1.0 + 2.0 * w), psi, predictor, milne(w))
+                }
+                None => (1.0, y.clone(), y.add_scaled(h, &fy), 0.5),
+            };
+            let newton = self.newton(
+                &mut f,
+                t + h,
+                &psi,
+                &predictor,
+                gamma * h,
+                &mut jacobian,
+                &mut statistics,
+            );
+            let next = match newton {
+                Some(next) => next,
+                None => {
+                    statistics.rejected += 1;
+                    h *= MIN_FACTOR;
+                    if h.abs() < MIN_STEP * t.abs() {
+                        return Err(OdeError::StepTooSmall(t));
+                    }
+                    continue;
+                }
+            };
+            let difference = next.add_scaled(-1.0, &predictor);
+            let error =
+                ratio.abs() * error_norm(&difference, &y, &next, self.absolute, self.relative);
+            let order = if previous.is_some() { 3.0 } else { 2.0 };
+            if error <= 1.0 {
+                // The converged solution satisfies the formula exactly.
+                let f1 = next.add_scaled(-1.0, &psi).scale(1.0 / (gamma * h));
+                t += h;
+                solution.push(t, next.clone(), &fy, &f1, fy.scale(0.0));
+                statistics.accepted += 1;
+                previous = Some((h, y));
+                y = next;
+                fy = f1;
+                h *= (SAFETY * error.powf(-1.0 / order)).min(MAX_BDF_RATIO);
+            } else {
+                statistics.rejected += 1;
+                h *= (SAFETY * error.powf(-1.0 / order)).max(MIN_FACTOR);
+            }
+            if h.abs() < MIN_STEP * t.abs() {
+                return Err(OdeError::StepTooSmall(t));
+            }
+        }
+        Err(OdeError::TooManySteps(t))
+    }
+
+    /// Solves y = psi + c f(t, y) by Newton's method from `guess`, with the
+    /// Jacobian of the last call unless it fails to converge.
+    #[allow(clippy::too_many_arguments)]
+    fn newton<S, F>(
+        &self,
+        f: &mut F,
+        t: Scalar,
+        psi: &S,
+        guess: &S,
+        c: Scalar,
+        jacobian: &mut Option<Vec<Vec<Scalar>>>,
+        statistics: &mut Statistics,
+    ) -> Option<S>
+    where
+        S: State,
+        F: FnMut(Scalar, &S) -> S,
+    {
+        let n = guess.dimension();
+        let fresh = jacobian.is_none();
+        let j = match jacobian.take() {
+            Some(j) => j,
+            None => finite_difference_jacobian(f, t, guess, statistics),
+        };
+        let mut m = MatrixF64::new(n, n).unwrap();
+        for (row, jr) in j.iter().enumerate() {
+            for (col, jrc) in jr.iter().enumerate() {
+                let identity = if row == col { 1.0 } else { 0.0 };
+                m.set(row, col, identity - c * jrc);
+            }
+        }
+        *jacobian = Some(j);
+        let mut p = Permutation::new(n).unwrap();
+        let mut signum = 0;
+        statistics.factorizations += 1;
+        let factorized = LU_decomp(&mut m, &mut p, &mut signum).is_ok();
+        let mut y = guess.clone();
+        if factorized {
+            for _ in 0..MAX_NEWTON_ITERATIONS {
+                statistics.newton_iterations += 1;
+                statistics.evaluations += 1;
+                let fy = f(t, &y);
+                if !fy.is_finite() {
+                    break;
+                }
+                // Residual psi + c f - y
+                let residual = psi.add_scaled(c, &fy).add_scaled(-1.0, &y);
+                let mut b = VectorF64::new(n).unwrap();
+                for i in 0..n {
+                    b.set(i, residual.component(i));
+                }
+                let mut dy = VectorF64::new(n).unwrap();
+                if LU_solve(&m, &p, &b, &mut dy).is_err() {
+                    break;
+                }
+                let dy: Vec<Scalar> = (0..n).map(|i| dy.get(i)).collect();
+                let dy = y.with_components(&dy);
+                y = y.add_scaled(1.0, &dy);
+                if !y.is_finite() {
+                    break;
+                }
+                if error_norm(&dy, &y, &y, self.absolute, self.relative) <= NEWTON_TOLERANCE {
+                    return Some(y);
+                }
+            }
+        }
+        if fresh {
+            return None;
+        }
+        // Retry once with a new Jacobian.
+        *jacobian = None;
+        self.newton(f, t, psi, guess, c, jacobian, statistics)
+    }
+}
+
+/// Error of BDF2 at step ratio `w` relative to its difference from the
+/// quadratic predictor, both of order h^3 y'''.
+fn milne(w: Scalar) -> Scalar {
+    // Local errors for y = t^3/6 from t_n = 0, with h_n-1 = 1 and h_n = w
+    let corrector = w * w / (6.0 * (1.0 + 2.0 * w))
+        + w.powi(3) * (1.0 + w) / (2.0 * (1.0 + 2.0 * w))
+        - w.powi(3) / 6.0;
+    let predictor = w * w * (1.0 + w) / 6.0;
+    corrector / (corrector + predictor)
+}
+
+/// Rows df_i/dy_j at (`t`, `y`) by forward differences.
+fn finite_difference_jacobian<S, F>(
+    f: &mut F,
+    t: Scalar,
+    y: &S,
+    statistics: &mut Statistics,
+) -> Vec<Vec<Scalar>>
+where
+    S: State,
+    F: FnMut(Scalar, &S) -> S,
+{
+    let n = y.dimension();
+    let f0 = f(t, y);
+    statistics.jacobians += 1;
+    statistics.evaluations += n + 1;
+    let mut components = y.components();
+    let mut j = vec![vec![0.0; n]; n];
+    for col in 0..n {
+        let x = components[col];
+        let step = Scalar::EPSILON.sqrt() * x.abs().max(1e-8);
+        components[col] = x + step;
+        let shifted = f(t, &y.with_components(&components));
+        components[col] = x;
+        for (row, jr) in j.iter_mut().enumerate() {
+            jr[col] = (shifted.component(row) - f0.component(row)) / step;
+        }
+    }
+    j
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::NormedSpace;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn test_rk4() {
+        let error = |steps| {
+            (Rk4::new(steps)
+                .solve(|_, y: &Scalar| *y, 0.0, 1.0, 1.0)
+                .last()
+                - 1f64.exp())
+            .abs()
+        };
+        let ratio = error(10) / error(20);
+        assert!((ratio - 16.0).abs() < 1.0, "{}", ratio);
+        let solution = Rk4::new(10).solve(|_, y: &Scalar| *y, 0.0, 1.0, 1.0);
+        assert_eq!(solution.statistics.evaluations, 41);
+        assert!((solution.at(0.55).unwrap() - 0.55f64.exp()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_dormand_prince() {
+        // Circular Kepler orbit of unit radius and period 2 pi
+        let kepler = |_, (r, v): &(Vector, Vector)| (v.clone(), r.clone() * -r.norm().powi(-3));
+        let start = (
+            Vector::from_slice(&[1.0, 0.0, 0.0]),
+            Vector::from_slice(&[0.0, 1.0, 0.0]),
+        );
+        let solution = DormandPrince::new(1e-10)
+            .with_absolute_tolerance(1e-12)
+            .solve(kepler, 0.0, start.clone(), 2.0 * PI)
+            .unwrap();
+        let (r, v) = solution.last();
+        assert!((r.clone() - start.0.clone()).norm() < 1e-8);
+        assert!((v.clone() - start.1.clone()).norm() < 1e-8);
+        for &t in &[0.3, PI, 5.0] {
+            let (r, _) = solution.at(t).unwrap();
+            let expected = Vector::from_slice(&[t.cos(), t.sin(), 0.0]);
+            assert!((r - expected).norm() < 1e-8, "{}", t);
+        }
+        assert_eq!(solution.at(7.0).map(|_| ()), None);
+        let statistics = solution.statistics;
+        assert_eq!(
+            statistics.evaluations,
+            1 + 6 * (statistics.accepted + statistics.rejected)
+        );
+
+        // Backwards, with a right-hand side rejecting negative y
+        let f = |_, y: &Scalar| if *y > 0.0 { -y } else { Scalar::NAN };
+        let solution = DormandPrince::new(1e-10)
+            .with_initial_step(1.0)
+            .solve(f, 1.0, 1.0, -1.0)
+            .unwrap();
+        assert!((solution.last() - 2f64.exp()).abs() < 1e-8);
+        assert!((solution.at(0.0).unwrap() - 1f64.exp()).abs() < 1e-8);
+    }
+
+    #[test]
+    fn test_bdf() {
+        // Robertson's reactions, stiff through rates spanning 9 decades
+        let robertson = |_, y: &[Scalar; 3]| {
+            let [a, b, c] = [0.04 * y[0], 1e4 * y[1] * y[2], 3e7 * y[1] * y[1]];
+            [-a + b, a - b - c, c]
+        };
+        let solution = Bdf::new(1e-6, 1e-10)
+            .solve(robertson, 0.0, [1.0, 0.0, 0.0], 40.0)
+            .unwrap();
+        let y = solution.last();
+        // Hairer and Wanner's reference solution
+        let expected = [0.715_827_068_7, 9.185_534_764e-6, 0.284_163_745_7];
+        for i in 0..3 {
+            assert!((y[i] / expected[i] - 1.0).abs() < 1e-3, "{:?}", y);
+        }
+        assert!((y.iter().sum::<Scalar>() - 1.0).abs() < 1e-8);
+        let statistics = solution.statistics;
+        assert!(statistics.accepted < 2000, "{}", statistics);
+        assert!(statistics.jacobians < statistics.accepted);
+        // An explicit method needs steps of order the fastest time scale.
+        let explicit = DormandPrince::new(1e-6)
+            .with_absolute_tolerance(1e-10)
+            .with_max_steps(2000)
+            .solve(robertson, 0.0, [1.0, 0.0, 0.0], 40.0);
+        assert!(matches!(explicit, Err(OdeError::TooManySteps(_))));
+    }
+}
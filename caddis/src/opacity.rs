@@ -0,0 +1,454 @@
+//! Radiative opacities for the Henyey method.
+//!
+//! Opacities are functions of density and temperature and come with their
+//! partial derivatives, which the Henyey matrix needs. Quantities are SI
+//! except in tables, which follow the OPAL conventions: log10 of kappa in
+//! cm^2 g^-1 against log T and log R = log rho - 3 log T6, with rho in
+//! g cm^-3 and T6 = T / 10^6 K.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::eos::Composition;
+
+type Scalar = f64;
+
+/// Coefficients of the Kramers laws kappa = K rho T^-3.5 (SI), before the
+/// composition factors
+const BOUND_FREE: Scalar = 4.34e21;
+const FREE_FREE: Scalar = 3.68e18;
+/// Thomson scattering off free electrons, per unit (1 + X) (m^2 kg^-1)
+const THOMSON: Scalar = 0.02;
+
+pub trait Opacity {
+    /// Opacity and its partial derivatives with respect to density and
+    /// temperature, `[kappa, dkappa/drho, dkappa/dT]`.
+    fn opacity(&self, rho: Scalar, temperature: Scalar) -> [Scalar; 3];
+}
+
+/// Electron scattering in the Thomson limit, independent of rho and T.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thomson {
+    pub kappa: Scalar,
+}
+
+impl Thomson {
+    pub fn new(composition: &Composition) -> Self {
+        Thomson {
+            kappa: THOMSON * (1.0 + composition.x),
+        }
+    }
+}
+
+impl Opacity for Thomson {
+    fn opacity(&self, _rho: Scalar, _temperature: Scalar) -> [Scalar; 3] {
+        [self.kappa, 0.0, 0.0]
+    }
+}
+
+/// Kramers law, kappa = coefficient rho T^-3.5.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Kramers {
+    pub coefficient: Scalar,
+}
+
+impl Kramers {
+    pub fn new(coefficient: Scalar) -> Self {
+        Kramers { coefficient }
+    }
+
+    /// Bound-free absorption by metals, reduced by the guillotine factor.
+    pub fn bound_free(composition: &Composition, guillotine: Scalar) -> Self {
+        Kramers::new(BOUND_FREE * composition.z * (1.0 + composition.x) / guillotine)
+    }
+
+    /// Free-free absorption by hydrogen and helium.
+    pub fn free_free(composition: &Composition) -> Self {
+        Kramers::new(FREE_FREE * (1.0 - composition.z) * (1.0 + composition.x))
+    }
+}
+
+impl Opacity for Kramers {
+    fn opacity(&self, rho: Scalar, temperature: Scalar) -> [Scalar; 3] {
+        let kappa_rho = self.coefficient * temperature.powf(-3.5);
+        let kappa = kappa_rho * rho;
+        [kappa, kappa_rho, -3.5 * kappa / temperature]
+    }
+}
+
+/// Sum of two opacity sources.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sum<A, B>(pub A, pub B);
+
+impl<A: Opacity, B: Opacity> Opacity for Sum<A, B> {
+    fn opacity(&self, rho: Scalar, temperature: Scalar) -> [Scalar; 3] {
+        let a = self.0.opacity(rho, temperature);
+        let b = self.1.opacity(rho, temperature);
+        [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+    }
+}
+
+/// Electron scattering plus bound-free and free-free Kramers absorption.
+pub fn analytic(
+    composition: &Composition,
+    guillotine: Scalar,
+) -> Sum<Thomson, Sum<Kramers, Kramers>> {
+    Sum(
+        Thomson::new(composition),
+        Sum(
+            Kramers::bound_free(composition, guillotine),
+            Kramers::free_free(composition),
+        ),
+    )
+}
+
+/// Table of log kappa on a rectilinear grid in log R and log T, interpolated
+/// by bicubic Hermite patches with node slopes from three-point differences.
+///
+/// The text format is that of a single OPAL table: a header line holding
+/// `logR` followed by the log R values, then one line per log T holding log T
+/// and log kappa at each log R. Lines may stop short, as OPAL rows do at high
+/// density; missing entries are NaN. Slopes next to them are taken from the
+/// finite side, and only cells with a missing corner interpolate to NaN.
+/// Blank lines and lines starting with `#` are skipped. Outside the grid the
+/// table is clamped to its edge.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpacityTable {
+    pub log_r: Vec<Scalar>,
+    pub log_t: Vec<Scalar>,
+    /// log kappa, indexed `[t][r]`
+    pub log_kappa: Vec<Vec<Scalar>>,
+    /// Slopes in log R and log T, and the cross derivative, at every node
+    slopes: [Vec<Vec<Scalar>>; 3],
+}
+
+impl OpacityTable {
+    /// Table of `log_kappa[t][r]` on increasing grids of at least three log R
+    /// and three log T values, or an error describing a mismatched shape.
+    pub fn new(
+        log_r: Vec<Scalar>,
+        log_t: Vec<Scalar>,
+        log_kappa: Vec<Vec<Scalar>>,
+    ) -> Result<Self, String> {
+        if log_r.len() < 3 || log_t.len() < 3 {
+            return Err("at least three log R and three log T values needed".to_string());
+        }
+        if !log_r.windows(2).all(|w| w[1] > w[0]) || !log_t.windows(2).all(|w| w[1] > w[0]) {
+            return Err("log R and log T values must increase".to_string());
+        }
+        if log_kappa.len() != log_t.len() {
+            return Err(format!(
+                "{} rows of log kappa for {} log T values",
+                log_kappa.len(),
+                log_t.len()
+            ));
+        }
+        if let Some((j, row)) = log_kappa
+            .iter()
+            .enumerate()
+            .find(|(_, row)| row.len() != log_r.len())
+        {
+            return Err(format!(
+                "{} values at log T = {} for {} log R values",
+                row.len(),
+                log_t[j],
+                log_r.len()
+            ));
+        }
+        let d_r: Vec<Vec<Scalar>> = log_kappa.iter().map(|row| slopes(&log_r, row)).collect();
+        let d_t = transpose(
+            &transpose(&log_kappa)
+                .iter()
+                .map(|column| slopes(&log_t, column))
+                .collect::<Vec<_>>(),
+        );
+        let d_rt = transpose(
+            &transpose(&d_r)
+                .iter()
+                .map(|column| slopes(&log_t, column))
+                .collect::<Vec<_>>(),
+        );
+        Ok(OpacityTable {
+            log_r,
+            log_t,
+            log_kappa,
+            slopes: [d_r, d_t, d_rt],
+        })
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// log kappa and its derivatives with respect to log R and log T.
+    pub fn interpolate(&self, log_r: Scalar, log_t: Scalar) -> [Scalar; 3] {
+        let (i, u, hu, in_r) = locate(&self.log_r, log_r);
+        let (j, v, hv, in_t) = locate(&self.log_t, log_t);
+        let [d_r, d_t, d_rt] = &self.slopes;
+        let (mut f, mut f_u, mut f_v) = (0.0, 0.0, 0.0);
+        for (b, jj) in [j, j + 1].iter().enumerate() {
+            for (a, ii) in [i, i + 1].iter().enumerate() {
+                let corner = [
+                    self.log_kappa[*jj][*ii],
+                    hu * d_r[*jj][*ii],
+                    hv * d_t[*jj][*ii],
+                    hu * hv * d_rt[*jj][*ii],
+                ];
+                for (k, c) in corner.iter().enumerate() {
+                    let (pu, du) = hermite(k % 2, a, u);
+                    let (pv, dv) = hermite(k / 2, b, v);
+                    f += c * pu * pv;
+                    f_u += c * du * pv;
+                    f_v += c * pu * dv;
+                }
+            }
+        }
+        [
+            f,
+            if in_r { f_u / hu } else { 0.0 },
+            if in_t { f_v / hv } else { 0.0 },
+        ]
+    }
+}
+
+impl Opacity for OpacityTable {
+    fn opacity(&self, rho: Scalar, temperature: Scalar) -> [Scalar; 3] {
+        let log_t = temperature.log10();
+        let log_r = (1e-3 * rho).log10() - 3.0 * (log_t - 6.0);
+        let [f, f_r, f_t] = self.interpolate(log_r, log_t);
+        let kappa = 0.1 * (10.0 as Scalar).powf(f);
+        [
+            kappa,
+            kappa * f_r / rho,
+            kappa * (f_t - 3.0 * f_r) / temperature,
+        ]
+    }
+}
+
+impl FromStr for OpacityTable {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let mut log_r: Option<Vec<Scalar>> = None;
+        let mut log_t = Vec::new();
+        let mut log_kappa = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(header) = line.find("logR") {
+                if log_r.is_some() {
+                    break;
+                }
+                let values = line[header + 4..]
+                    .split(|c: char| c.is_whitespace() || c == '=')
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.parse().map_err(|_| format!("bad log R value {:?}", s)))
+                    .collect::<Result<Vec<Scalar>, String>>()?;
+                log_r = Some(values);
+                continue;
+            }
+            let columns = match &log_r {
+                Some(columns) => columns.len(),
+                None => continue,
+            };
+            let mut numbers = line
+                .split_whitespace()
+                .map(|s| s.parse().map_err(|_| format!("bad number {:?}", s)));
+            log_t.push(numbers.next().unwrap()?);
+            let mut row = numbers.collect::<Result<Vec<Scalar>, String>>()?;
+            if row.len() > columns {
+                return Err(format!(
+                    "too many values at log T = {}",
+                    log_t.last().unwrap()
+                ));
+            }
+            row.resize(columns, Scalar::NAN);
+            log_kappa.push(row);
+        }
+        let log_r = log_r.ok_or_else(|| "no logR header".to_string())?;
+        OpacityTable::new(log_r, log_t, log_kappa)
+    }
+}
+
+/// Cell index, fractional position and width of the cell holding `x`, and
+/// whether `x` was inside the grid.
+fn locate(grid: &[Scalar], x: Scalar) -> (usize, Scalar, Scalar, bool) {
+    let n = grid.len();
+    let inside = x >= grid[0] && x <= grid[n - 1];
+    let x = x.max(grid[0]).min(grid[n - 1]);
+    let i = grid.partition_point(|g| *g <= x).clamp(1, n - 1) - 1;
+    let h = grid[i + 1] - grid[i];
+    (i, (x - grid[i]) / h, h, inside)
+}
+
+/// Cubic Hermite basis function for the value (`kind` 0) or slope (1) at
+/// end `end` of the unit interval, and its derivative, at `t`.
+fn hermite(kind: usize, end: usize, t: Scalar) -> (Scalar, Scalar) {
+    match (kind, end) {
+        (0, 0) => (2.0 * t.powi(3) - 3.0 * t * t + 1.0, 6.0 * t * t - 6.0 * t),
+        (0, _) => (-2.0 * t.powi(3) + 3.0 * t * t, -6.0 * t * t + 6.0 * t),
+        (_, 0) => (t.powi(3) - 2.0 * t * t + t, 3.0 * t * t - 4.0 * t + 1.0),
+        (_, _) => (t.powi(3) - t * t, 3.0 * t * t - 2.0 * t),
+    }
+}
+
+/// Slopes at every node from the derivative of the parabola through it and
+/// its neighbours, which is exact for quadratics on any spacing. Next to
+/// missing values the parabola moves to the finite side, or gives way to the
+/// chord to the only finite neighbour; missing values get NaN slopes.
+fn slopes(x: &[Scalar], f: &[Scalar]) -> Vec<Scalar> {
+    let n = x.len();
+    let finite = |s: &usize| f[*s..*s + 3].iter().all(|f| f.is_finite());
+    (0..n)
+        .map(|i| {
+            if !f[i].is_finite() {
+                return Scalar::NAN;
+            }
+            // Parabolas through node i: centred, then forward and backward
+            let starts = [i.max(1).min(n - 2) - 1, i.min(n - 3), i.max(2) - 2];
+            if let Some(s) = starts.iter().find(|s| finite(s)) {
+                let (x0, x1, x2) = (x[*s], x[s + 1], x[s + 2]);
+                let t = x[i];
+                return f[*s] * (2.0 * t - x1 - x2) / ((x0 - x1) * (x0 - x2))
+                    + f[s + 1] * (2.0 * t - x0 - x2) / ((x1 - x0) * (x1 - x2))
+                    + f[s + 2] * (2.0 * t - x0 - x1) / ((x2 - x0) * (x2 - x1));
+            }
+            [i + 1, i.wrapping_sub(1)]
+                .iter()
+                .find(|&&j| j < n && f[j].is_finite())
+                .map_or(0.0, |&j| (f[j] - f[i]) / (x[j] - x[i]))
+        })
+        .collect()
+}
+
+fn transpose(rows: &[Vec<Scalar>]) -> Vec<Vec<Scalar>> {
+    (0..rows[0].len())
+        .map(|i| rows.iter().map(|row| row[i]).collect())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quadratic(log_r: Scalar, log_t: Scalar) -> Scalar {
+        0.3 + 0.5 * log_r - 2.0 * log_t + 0.1 * log_r * log_t + 0.05 * log_r * log_r
+    }
+
+    fn table() -> OpacityTable {
+        let log_r = [-8.0, -7.0, -6.5, -5.0, -4.0, -3.0];
+        let log_t = [3.75, 4.0, 4.5, 5.0, 6.0, 7.0, 8.0];
+        let mut text = String::from("# test table\n   logT   logR =");
+        for r in &log_r {
+            text += &format!(" {:.2}", r);
+        }
+        text += "\n\n";
+        for t in &log_t {
+            text += &format!("{:.2}", t);
+            for r in &log_r {
+                text += &format!(" {:.17e}", quadratic(*r, *t));
+            }
+            text += "\n";
+        }
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_analytic() {
+        let solar = Composition::solar();
+        let kappa = analytic(&solar, 10.0);
+        let (rho, t) = (1.5e5, 1.5e7);
+        let [k, k_rho, k_t] = kappa.opacity(rho, t);
+        assert!((Thomson::new(&solar).kappa - 0.034).abs() < 1e-12);
+        assert!(k > 0.034);
+        let fd_rho =
+            (kappa.opacity(rho * 1.001, t)[0] - kappa.opacity(rho * 0.999, t)[0]) / (0.002 * rho);
+        let fd_t =
+            (kappa.opacity(rho, t * 1.001)[0] - kappa.opacity(rho, t * 0.999)[0]) / (0.002 * t);
+        assert!((fd_rho - k_rho).abs() < 1e-6 * k_rho.abs());
+        assert!((fd_t - k_t).abs() < 1e-5 * k_t.abs());
+    }
+
+    #[test]
+    fn test_table_reproduces_quadratics() {
+        let table = table();
+        assert_eq!(table.log_r.len(), 6);
+        assert_eq!(table.log_t.len(), 7);
+        for &(r, t) in &[(-7.3, 3.9), (-4.2, 6.7), (-6.5, 5.0), (-3.0, 8.0)] {
+            let [f, f_r, f_t] = table.interpolate(r, t);
+            assert!((f - quadratic(r, t)).abs() < 1e-12);
+            assert!((f_r - (0.5 + 0.1 * t + 0.1 * r)).abs() < 1e-10);
+            assert!((f_t - (-2.0 + 0.1 * r)).abs() < 1e-10);
+        }
+        // Clamped outside the grid
+        let [f, _, f_t] = table.interpolate(-5.0, 9.0);
+        assert!((f - quadratic(-5.0, 8.0)).abs() < 1e-12 && f_t == 0.0);
+    }
+
+    #[test]
+    fn test_table_opacity_derivatives() {
+        let table = table();
+        let (rho, t) = (1e-3, 2e5);
+        let [_, k_rho, k_t] = table.opacity(rho, t);
+        let fd_rho = (table.opacity(rho * 1.0001, t)[0] - table.opacity(rho * 0.9999, t)[0])
+            / (0.0002 * rho);
+        let fd_t =
+            (table.opacity(rho, t * 1.0001)[0] - table.opacity(rho, t * 0.9999)[0]) / (0.0002 * t);
+        assert!((fd_rho - k_rho).abs() < 1e-6 * k_rho.abs());
+        assert!((fd_t - k_t).abs() < 1e-6 * k_t.abs());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!("3.75 1.0 2.0".parse::<OpacityTable>().is_err());
+        assert!("logR = -8 -7\n3.75 1 2\n4.0 1 2\n4.5 1 2"
+            .parse::<OpacityTable>()
+            .is_err());
+        let short = "logR = -8 -7 -6\n3.75 1 2\n4.0 1 2 3\n4.5 1 2 3";
+        assert!(short.parse::<OpacityTable>().unwrap().log_kappa[0][2].is_nan());
+        // Ragged or mismatched tables are refused rather than indexed.
+        let (log_r, log_t) = (vec![-8.0, -7.0, -6.0], vec![3.75, 4.0, 4.5]);
+        let ragged = vec![vec![1.0; 3], vec![1.0; 2], vec![1.0; 3]];
+        assert!(OpacityTable::new(log_r.clone(), log_t.clone(), ragged).is_err());
+        assert!(OpacityTable::new(log_r.clone(), log_t.clone(), vec![vec![1.0; 3]; 2]).is_err());
+        assert!(
+            OpacityTable::new(log_r.clone(), vec![4.0, 3.75, 4.5], vec![vec![1.0; 3]; 3]).is_err()
+        );
+        assert!(OpacityTable::new(log_r, log_t, vec![vec![1.0; 3]; 3]).is_ok());
+    }
+
+    #[test]
+    fn test_table_edge() {
+        // OPAL-like rows stopping short at high density: cells whose corners
+        // are all present interpolate as if the table were complete.
+        let log_r = [-8.0, -7.0, -6.5, -5.0, -4.0, -3.0];
+        let log_t = [3.75, 4.0, 4.5, 5.0, 6.0, 7.0, 8.0];
+        let mut text = String::from("logR =");
+        for r in &log_r {
+            text += &format!(" {:.2}", r);
+        }
+        text += "\n";
+        for (j, t) in log_t.iter().enumerate() {
+            text += &format!("{:.2}", t);
+            for r in &log_r[..log_r.len() - j.min(3)] {
+                text += &format!(" {:.17e}", quadratic(*r, *t));
+            }
+            text += "\n";
+        }
+        let table: OpacityTable = text.parse().unwrap();
+        assert!(table.log_kappa[6][3].is_nan() && table.log_kappa[6][2].is_finite());
+        for &(r, t) in &[(-6.8, 7.5), (-6.0, 4.2), (-4.5, 3.9), (-7.5, 7.9)] {
+            let [f, f_r, f_t] = table.interpolate(r, t);
+            assert!((f - quadratic(r, t)).abs() < 1e-10, "{} {}", r, t);
+            assert!((f_r - (0.5 + 0.1 * t + 0.1 * r)).abs() < 1e-9);
+            assert!((f_t - (-2.0 + 0.1 * r)).abs() < 1e-9);
+        }
+        // Only beyond the data is there nothing to interpolate.
+        assert!(table.interpolate(-3.5, 7.5)[0].is_nan());
+    }
+}
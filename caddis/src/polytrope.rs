@@ -0,0 +1,256 @@
+//! Polytropes, P = K rho^(1 + 1/n), from the Lane-Emden equation
+//!
+//! ```text
+//! 1/xi^2 d/dxi (xi^2 dtheta/dxi) = -theta^n
+//! ```
+//!
+//! with rho = rho_c theta^n, P = P_c theta^(n+1) and r = alpha xi. They give
+//! starting models for the Henyey method and benchmarks for it.
+
+use std::f64::consts::PI;
+
+use crate::units::{MOLAR_GAS_CONSTANT, MOLAR_MASS_CONSTANT, NEWTONIAN_CONSTANT_OF_GRAVITATION};
+
+type Scalar = f64;
+
+/// Molar gas constant divided by the molar mass constant (J kg^-1 K^-1)
+const GAS_CONSTANT: Scalar = MOLAR_GAS_CONSTANT / MOLAR_MASS_CONSTANT;
+
+/// Runge-Kutta step in xi
+const STEP: Scalar = 1e-3;
+/// Where the integration of polytropes without a surface, n >= 5, stops
+const MAX_XI: Scalar = 100.0;
+
+/// Lane-Emden solution theta(xi) of index `n`, tabulated from the centre to
+/// the first zero xi_1 of theta.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polytrope {
+    pub n: Scalar,
+    /// xi, theta and dtheta/dxi
+    table: Vec<[Scalar; 3]>,
+    /// First zero xi_1 of theta, none for n >= 5
+    surface: Option<Scalar>,
+}
+
+/// Analytic solutions theta(xi) for n = 0, 1 and 5.
+pub fn analytic(n: Scalar, xi: Scalar) -> Option<Scalar> {
+    if n == 0.0 {
+        Some(1.0 - xi * xi / 6.0)
+    } else if n == 1.0 {
+        Some(if xi == 0.0 { 1.0 } else { xi.sin() / xi })
+    } else if n == 5.0 {
+        Some((1.0 + xi * xi / 3.0).powf(-0.5))
+    } else {
+        None
+    }
+}
+
+impl Polytrope {
+    /// Integrates the Lane-Emden equation of index `n` by fourth order
+    /// Runge-Kutta steps, starting from the series
+    /// theta = 1 - xi^2/6 + n xi^4/120 off the centre.
+    pub fn new(n: Scalar) -> Self {
+        let f = |xi: Scalar, [theta, dtheta]: [Scalar; 2]| {
+            [dtheta, -theta.max(0.0).powf(n) - 2.0 * dtheta / xi]
+        };
+        let xi = STEP;
+        let mut y = [
+            1.0 - xi * xi / 6.0 + n * xi.powi(4) / 120.0,
+            -xi / 3.0 + n * xi.powi(3) / 30.0,
+        ];
+        let mut table = vec![[0.0, 1.0, 0.0], [xi, y[0], y[1]]];
+        let mut surface = None;
+        let mut xi = xi;
+        while xi < MAX_XI {
+            let h = STEP;
+            let add =
+                |y: [Scalar; 2], k: [Scalar; 2], c: Scalar| [y[0] + c * k[0], y[1] + c * k[1]];
+            let k1 = f(xi, y);
+            let k2 = f(xi + 0.5 * h, add(y, k1, 0.5 * h));
+            let k3 = f(xi + 0.5 * h, add(y, k2, 0.5 * h));
+            let k4 = f(xi + h, add(y, k3, h));
+            let next = [
+                y[0] + h / 6.0 * (k1[0] + 2.0 * k2[0] + 2.0 * k3[0] + k4[0]),
+                y[1] + h / 6.0 * (k1[1] + 2.0 * k2[1] + 2.0 * k3[1] + k4[1]),
+            ];
+            if next[0] <= 0.0 {
+                // Zero of the Taylor expansion about the last point inside
+                let second = f(xi, y)[1];
+                let mut d = -y[0] / y[1];
+                for _ in 0..3 {
+                    d -= (y[0] + y[1] * d + 0.5 * second * d * d) / (y[1] + second * d);
+                }
+                let xi_1 = xi + d;
+                let dtheta = y[1] + second * d;
+                table.push([xi_1, 0.0, dtheta]);
+                surface = Some(xi_1);
+                break;
+            }
+            xi += h;
+            y = next;
+            table.push([xi, y[0], y[1]]);
+        }
+        Polytrope { n, table, surface }
+    }
+
+    /// First zero xi_1 of theta, none for n >= 5.
+    pub fn surface(&self) -> Option<Scalar> {
+        self.surface
+    }
+
+    /// theta and dtheta/dxi at `xi`, interpolated by cubic Hermite
+    /// polynomials and extrapolated beyond the table.
+    pub fn theta(&self, xi: Scalar) -> [Scalar; 2] {
+        let i = match self.table.iter().position(|y| y[0] >= xi) {
+            Some(0) => 1,
+            Some(i) => i,
+            None => self.table.len() - 1,
+        };
+        let ([x0, y0, d0], [x1, y1, d1]) = (self.table[i - 1], self.table[i]);
+        let h = x1 - x0;
+        let t = (xi - x0) / h;
+        let theta = (2.0 * t.powi(3) - 3.0 * t * t + 1.0) * y0
+            + (t.powi(3) - 2.0 * t * t + t) * h * d0
+            + (-2.0 * t.powi(3) + 3.0 * t * t) * y1
+            + (t.powi(3) - t * t) * h * d1;
+        let dtheta = ((6.0 * t * t - 6.0 * t) * (y0 - y1)) / h
+            + (3.0 * t * t - 4.0 * t + 1.0) * d0
+            + (3.0 * t * t - 2.0 * t) * d1;
+        [theta, dtheta]
+    }
+
+    /// Dimensionless mass -xi^2 dtheta/dxi inside `xi`.
+    fn mass(&self, y: &[Scalar; 3]) -> Scalar {
+        -y[0] * y[0] * y[2]
+    }
+
+    /// Dimensionless total mass -xi_1^2 theta'(xi_1).
+    pub fn total_mass(&self) -> Option<Scalar> {
+        self.surface.map(|_| self.mass(self.table.last().unwrap()))
+    }
+
+    /// Ratio of the central to the mean density, xi_1/(3 |theta'(xi_1)|).
+    pub fn central_condensation(&self) -> Option<Scalar> {
+        self.surface
+            .map(|xi| xi / (3.0 * self.table.last().unwrap()[2].abs()))
+    }
+
+    /// Central density and pressure of a polytrope of `mass` and `radius`,
+    ///
+    /// ```text
+    /// rho_c = 3 M/(4 pi R^3) rho_c/rho_mean
+    /// P_c = 4 pi G alpha^2 rho_c^2/(n + 1),    alpha = R/xi_1
+    /// ```
+    pub fn central(&self, mass: Scalar, radius: Scalar) -> Option<[Scalar; 2]> {
+        let xi_1 = self.surface?;
+        let rho_c = 3.0 * mass / (4.0 * PI * radius.powi(3)) * self.central_condensation()?;
+        let alpha = radius / xi_1;
+        let p_c =
+            4.0 * PI * NEWTONIAN_CONSTANT_OF_GRAVITATION * (alpha * rho_c).powi(2) / (self.n + 1.0);
+        Some([rho_c, p_c])
+    }
+
+    /// r, P, rho and the ideal gas temperature for mean molecular weight
+    /// `mu` at mass coordinate `m` of a polytrope of `mass` and `radius`.
+    pub fn at_mass(
+        &self,
+        m: Scalar,
+        mass: Scalar,
+        radius: Scalar,
+        mu: Scalar,
+    ) -> Option<[Scalar; 4]> {
+        let [rho_c, p_c] = self.central(mass, radius)?;
+        let target = m / mass * self.total_mass()?;
+        // The mass grows monotonically outwards.
+        let i = self
+            .table
+            .iter()
+            .position(|y| self.mass(y) >= target)
+            .unwrap_or(self.table.len() - 1)
+            .max(1);
+        let (a, b) = (&self.table[i - 1], &self.table[i]);
+        let (q_a, q_b) = (self.mass(a), self.mass(b));
+        let xi = if m >= mass {
+            self.surface?
+        } else {
+            a[0] + (b[0] - a[0]) * (target - q_a) / (q_b - q_a)
+        };
+        let theta = self.theta(xi)[0].max(0.0);
+        let rho = rho_c * theta.powf(self.n);
+        let p = p_c * theta.powf(self.n + 1.0);
+        let temperature = if rho > 0.0 {
+            p * mu / (GAS_CONSTANT * rho)
+        } else {
+            0.0
+        };
+        Some([xi * radius / self.surface?, p, rho, temperature])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analytic() {
+        for &n in &[0.0, 1.0, 5.0] {
+            let polytrope = Polytrope::new(n);
+            let end = polytrope.surface().unwrap_or(5.0);
+            for i in 0..100 {
+                let xi = end * i as Scalar / 100.0;
+                let theta = analytic(n, xi).unwrap();
+                assert!(
+                    (polytrope.theta(xi)[0] - theta).abs() < 1e-8,
+                    "{} {}",
+                    n,
+                    xi
+                );
+            }
+        }
+        assert!((Polytrope::new(0.0).surface().unwrap() - 6f64.sqrt()).abs() < 1e-8);
+        assert!((Polytrope::new(1.0).surface().unwrap() - PI).abs() < 1e-8);
+        assert_eq!(Polytrope::new(5.0).surface(), None);
+    }
+
+    #[test]
+    fn test_constants() {
+        // Chandrasekhar's xi_1, -xi_1^2 theta'(xi_1) and rho_c/rho_mean
+        for &(n, xi_1, mass, condensation) in &[
+            (1.5, 3.653_75, 2.714_06, 5.990_71),
+            (3.0, 6.896_85, 2.018_24, 54.182_5),
+        ] {
+            let polytrope = Polytrope::new(n);
+            assert!((polytrope.surface().unwrap() / xi_1 - 1.0).abs() < 1e-5);
+            assert!((polytrope.total_mass().unwrap() / mass - 1.0).abs() < 1e-5);
+            assert!((polytrope.central_condensation().unwrap() / condensation - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_dimensional() {
+        let (mass, radius) = (2e30, 7e8);
+        let polytrope = Polytrope::new(3.0);
+        let [rho_c, p_c] = polytrope.central(mass, radius).unwrap();
+        assert_eq!(
+            polytrope.at_mass(0.0, mass, radius, 0.6).unwrap()[..3],
+            [0.0, p_c, rho_c]
+        );
+        let surface = polytrope.at_mass(mass, mass, radius, 0.6).unwrap();
+        assert!((surface[0] / radius - 1.0).abs() < 1e-12 && surface[1] == 0.0);
+        // The mass of the shells recovers the mass of the star.
+        let mut m = 0.0;
+        let mut inner = [0.0f64; 4];
+        for j in 1..=1000 {
+            let outer = polytrope
+                .at_mass(mass * j as Scalar / 1000.0, mass, radius, 0.6)
+                .unwrap();
+            m += 4.0 / 3.0
+                * PI
+                * (outer[0].powi(3) - inner[0].powi(3))
+                * 0.5
+                * (outer[2] + inner[2]);
+            inner = outer;
+        }
+        assert!((m / mass - 1.0).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,437 @@
+//! Tables of named columns, for stellar profiles and evolution histories.
+//!
+//! A `Profile` has one row per shell of a model, or per model of a history,
+//! and a header of named scalars. It is written and read as
+//!
+//! - CSV, with the header as `# name = value` comment lines before the row
+//!   of column names;
+//! - a self-describing little-endian binary file,
+//!
+//!   ```text
+//!   "CADDISPF"  version: u32
+//!   header entries: u32, then name and value of each
+//!   columns: u32  rows: u64
+//!   column names
+//!   values, column by column: f64
+//!   ```
+//!
+//!   with strings stored as a u32 byte length and UTF-8;
+//! - the layout of MESA's `profile.data` and `history.data`: a line of
+//!   header numbers, names and values, a blank line, and a line of column
+//!   numbers, names and rows. MESA columns keep MESA's units, mostly cgs,
+//!   solar and log10.
+
+use std::convert::TryInto;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+type Scalar = f64;
+
+const MAGIC: &[u8; 8] = b"CADDISPF";
+const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    /// Name and value of each header entry
+    pub header: Vec<(String, String)>,
+    pub names: Vec<String>,
+    /// Values of each column, all of the same length
+    pub columns: Vec<Vec<Scalar>>,
+}
+
+fn invalid<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// Parses a number, accepting Fortran exponents such as 1.5D+03.
+fn number(s: &str) -> io::Result<Scalar> {
+    s.replace(['D', 'd'], "E")
+        .parse()
+        .map_err(|_| invalid(format!("not a number: {}", s)))
+}
+
+/// Splits a line of MESA header values at whitespace outside quotes,
+/// unquoting strings.
+fn tokens(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let (mut quoted, mut started) = (false, false);
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                started = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if started {
+                    tokens.push(std::mem::take(&mut token));
+                    started = false;
+                }
+            }
+            c => {
+                token.push(c);
+                started = true;
+            }
+        }
+    }
+    if started {
+        tokens.push(token);
+    }
+    tokens
+}
+
+fn write_string<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+    w.write_all(&(s.len() as u32).to_le_bytes())?;
+    w.write_all(s.as_bytes())
+}
+
+fn read_bytes<R: Read, const N: usize>(r: &mut R) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(read_bytes(r)?))
+}
+
+/// Reads `len` bytes, allocating only as many as the reader holds, so that
+/// a corrupt length fails at the end of the data instead of exhausting
+/// memory.
+fn read_exactly<R: Read>(r: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    r.take(len).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+fn read_string<R: Read>(r: &mut R) -> io::Result<String> {
+    let len = read_u32(r)?;
+    String::from_utf8(read_exactly(r, len.into())?).map_err(invalid)
+}
+
+impl Profile {
+    /// Empty profile with columns `names`.
+    pub fn new(names: &[&str]) -> Self {
+        Profile {
+            header: Vec::new(),
+            names: names.iter().map(|name| name.to_string()).collect(),
+            columns: vec![Vec::new(); names.len()],
+        }
+    }
+
+    /// Adds the header entry `name`.
+    pub fn with_header<V: ToString>(mut self, name: &str, value: V) -> Self {
+        self.header.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Appends a row, one value per column.
+    pub fn push(&mut self, row: &[Scalar]) {
+        assert_eq!(row.len(), self.columns.len(), "row length");
+        for (column, x) in self.columns.iter_mut().zip(row) {
+            column.push(*x);
+        }
+    }
+
+    /// Number of rows.
+    pub fn len(&self) -> usize {
+        self.columns.first().map_or(0, Vec::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn column(&self, name: &str) -> Option<&[Scalar]> {
+        let i = self.names.iter().position(|n| n == name)?;
+        Some(&self.columns[i])
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.header
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn row(&self, i: usize) -> impl Iterator<Item = Scalar> + '_ {
+        self.columns.iter().map(move |column| column[i])
+    }
+
+    /// Checks that the columns are named and of one length.
+    fn validate(self) -> io::Result<Self> {
+        if self.names.len() != self.columns.len()
+            || self.columns.iter().any(|c| c.len() != self.len())
+        {
+            return Err(invalid("ragged columns"));
+        }
+        Ok(self)
+    }
+
+    /// Writes CSV; values use the shortest form that reads back exactly.
+    pub fn write_csv<W: Write>(&self, mut w: W) -> io::Result<()> {
+        for (name, value) in &self.header {
+            writeln!(w, "# {} = {}", name, value)?;
+        }
+        writeln!(w, "{}", self.names.join(","))?;
+        for i in 0..self.len() {
+            let row: Vec<String> = self.row(i).map(|x| format!("{:e}", x)).collect();
+            writeln!(w, "{}", row.join(","))?;
+        }
+        Ok(())
+    }
+
+    pub fn read_csv<R: Read>(r: R) -> io::Result<Self> {
+        let mut profile = Profile::new(&[]);
+        let mut names = false;
+        for line in BufReader::new(r).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(entry) = line.strip_prefix('#') {
+                let (name, value) = entry.split_once('=').ok_or_else(|| invalid(line))?;
+                profile
+                    .header
+                    .push((name.trim().into(), value.trim().into()));
+            } else if !names {
+                profile.names = line.split(',').map(|n| n.trim().to_string()).collect();
+                profile.columns = vec![Vec::new(); profile.names.len()];
+                names = true;
+            } else {
+                let row = line
+                    .split(',')
+                    .map(|x| number(x.trim()))
+                    .collect::<io::Result<Vec<_>>>()?;
+                if row.len() != profile.names.len() {
+                    return Err(invalid(format!("row of {} values", row.len())));
+                }
+                profile.push(&row);
+            }
+        }
+        profile.validate()
+    }
+
+    pub fn write_binary<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&(self.header.len() as u32).to_le_bytes())?;
+        for (name, value) in &self.header {
+            write_string(&mut w, name)?;
+            write_string(&mut w, value)?;
+        }
+        w.write_all(&(self.names.len() as u32).to_le_bytes())?;
+        w.write_all(&(self.len() as u64).to_le_bytes())?;
+        for name in &self.names {
+            write_string(&mut w, name)?;
+        }
+        for column in &self.columns {
+            for x in column {
+                w.write_all(&x.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_binary<R: Read>(mut r: R) -> io::Result<Self> {
+        if &read_bytes::<_, 8>(&mut r)? != MAGIC {
+            return Err(invalid("not a profile"));
+        }
+        let version = read_u32(&mut r)?;
+        if version != VERSION {
+            return Err(invalid(format!("profile version {}", version)));
+        }
+        let mut profile = Profile::new(&[]);
+        for _ in 0..read_u32(&mut r)? {
+            profile
+                .header
+                .push((read_string(&mut r)?, read_string(&mut r)?));
+        }
+        let n = read_u32(&mut r)? as usize;
+        let rows = u64::from_le_bytes(read_bytes(&mut r)?);
+        let size = rows
+            .checked_mul(8)
+            .ok_or_else(|| invalid(format!("{} rows", rows)))?;
+        for _ in 0..n {
+            profile.names.push(read_string(&mut r)?);
+        }
+        for _ in 0..n {
+            let column = read_exactly(&mut r, size)?
+                .chunks_exact(8)
+                .map(|x| f64::from_le_bytes(x.try_into().unwrap()))
+                .collect();
+            profile.columns.push(column);
+        }
+        Ok(profile)
+    }
+
+    /// Writes the layout of MESA's `profile.data`, quoting header values
+    /// that are not numbers.
+    pub fn write_mesa<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let width = 28;
+        let line = |w: &mut W, cells: &mut dyn Iterator<Item = String>| {
+            let cells: Vec<String> = cells.map(|c| format!("{:>1$}", c, width)).collect();
+            writeln!(w, "{}", cells.join(" "))
+        };
+        line(&mut w, &mut (1..=self.header.len()).map(|i| i.to_string()))?;
+        line(
+            &mut w,
+            &mut self.header.iter().map(|(name, _)| name.clone()),
+        )?;
+        line(
+            &mut w,
+            &mut self.header.iter().map(|(_, value)| match number(value) {
+                Ok(_) => value.clone(),
+                Err(_) => format!("\"{}\"", value),
+            }),
+        )?;
+        writeln!(w)?;
+        line(&mut w, &mut (1..=self.names.len()).map(|i| i.to_string()))?;
+        line(&mut w, &mut self.names.iter().cloned())?;
+        for i in 0..self.len() {
+            line(&mut w, &mut self.row(i).map(|x| format!("{:.16e}", x)))?;
+        }
+        Ok(())
+    }
+
+    /// Reads a MESA `profile.data` or `history.data` file. Lines are taken
+    /// by their position around the blank line before the column numbers,
+    /// as those of an empty header are blank themselves.
+    pub fn read_mesa<R: Read>(r: R) -> io::Result<Self> {
+        let mut lines = BufReader::new(r).lines().collect::<io::Result<Vec<_>>>()?;
+        let blank = |line: &String| line.trim().is_empty();
+        while lines.last().is_some_and(blank) {
+            lines.pop();
+        }
+        // Rows are never blank, so the separator is the last blank line.
+        let separator = match lines.iter().rposition(blank) {
+            Some(separator) if separator >= 3 && separator + 2 < lines.len() => separator,
+            _ => return Err(invalid("truncated MESA file")),
+        };
+        if !lines[..separator - 3].iter().all(blank) {
+            return Err(invalid("more than three header lines"));
+        }
+        let names = tokens(&lines[separator - 2]);
+        let values = tokens(&lines[separator - 1]);
+        if names.len() != values.len() {
+            return Err(invalid("header names and values differ in number"));
+        }
+        let mut profile = Profile::new(&[]);
+        profile.header = names.into_iter().zip(values).collect();
+        profile.names = tokens(&lines[separator + 2]);
+        profile.columns = vec![Vec::new(); profile.names.len()];
+        for line in &lines[separator + 3..] {
+            let row = line
+                .split_whitespace()
+                .map(number)
+                .collect::<io::Result<Vec<_>>>()?;
+            if row.len() != profile.names.len() {
+                return Err(invalid(format!("row of {} values", row.len())));
+            }
+            profile.push(&row);
+        }
+        profile.validate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Profile {
+        let mut profile = Profile::new(&["m", "r", "T"])
+            .with_header("mass", 1.988_47e30)
+            .with_header("version", "caddis 0.1");
+        profile.push(&[0.0, 0.0, 1.5e7]);
+        profile.push(&[1e29, 1.234_567_890_123_456_7e8, 1.0 / 3.0]);
+        profile.push(&[2e30, 6.957e8, f64::MIN_POSITIVE]);
+        profile
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let profile = example();
+        assert_eq!(profile.len(), 3);
+        assert_eq!(profile.column("r").unwrap()[1], 1.234_567_890_123_456_7e8);
+        assert_eq!(profile.header("version"), Some("caddis 0.1"));
+
+        let mut csv = Vec::new();
+        profile.write_csv(&mut csv).unwrap();
+        assert_eq!(Profile::read_csv(&csv[..]).unwrap(), profile);
+
+        let mut binary = Vec::new();
+        profile.write_binary(&mut binary).unwrap();
+        assert_eq!(Profile::read_binary(&binary[..]).unwrap(), profile);
+        assert!(Profile::read_binary(&binary[..binary.len() - 1]).is_err());
+
+        let mut mesa = Vec::new();
+        profile.write_mesa(&mut mesa).unwrap();
+        let read = Profile::read_mesa(&mesa[..]).unwrap();
+        assert_eq!(read.header, profile.header);
+        assert_eq!(read.names, profile.names);
+        for (a, b) in read
+            .columns
+            .iter()
+            .flatten()
+            .zip(profile.columns.iter().flatten())
+        {
+            assert!((a - b).abs() <= 1e-15 * b.abs());
+        }
+    }
+
+    #[test]
+    fn test_mesa() {
+        let data = r#"
+                   1                    2                    3
+        model_number             num_zones     version_number
+                 100                    3       "r23.05.1 x"
+
+                   1                    2                    3
+                zone                  mass               logT
+                   1   1.0000000000000000D+00   3.7617E+00
+                   2   5.0000000000000000D-01   6.9000E+00
+                   3   1.0000000000000000D-06   7.1947E+00
+"#;
+        let profile = Profile::read_mesa(data.as_bytes()).unwrap();
+        assert_eq!(profile.header("num_zones"), Some("3"));
+        assert_eq!(profile.header("version_number"), Some("r23.05.1 x"));
+        assert_eq!(profile.names, ["zone", "mass", "logT"]);
+        assert_eq!(profile.column("mass").unwrap(), [1.0, 0.5, 1e-6]);
+        assert_eq!(profile.column("logT").unwrap()[2], 7.1947);
+        assert!(Profile::read_mesa(&data.as_bytes()[..200]).is_err());
+    }
+
+    #[test]
+    fn test_empty_header() {
+        let mut profile = Profile::new(&["a", "b"]);
+        for i in 1..4 {
+            profile.push(&[i as f64, 2.0 * i as f64]);
+        }
+        let mut mesa = Vec::new();
+        profile.write_mesa(&mut mesa).unwrap();
+        assert_eq!(Profile::read_mesa(&mesa[..]).unwrap(), profile);
+    }
+
+    #[test]
+    fn test_corrupt_lengths() {
+        // Lengths far beyond the data fail without allocating for them.
+        let mut binary = Vec::new();
+        example().write_binary(&mut binary).unwrap();
+        // Magic, version and the number of header entries come first, then
+        // the length of the first header name.
+        let mut long_name = binary.clone();
+        long_name[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Profile::read_binary(&long_name[..]).is_err());
+        let mut empty = Vec::new();
+        Profile::new(&["a"]).write_binary(&mut empty).unwrap();
+        // Without a header the number of rows follows the number of columns.
+        for rows in [u64::MAX, u64::MAX / 8, 1 << 40] {
+            let mut long_column = empty.clone();
+            long_column[20..28].copy_from_slice(&rows.to_le_bytes());
+            assert!(Profile::read_binary(&long_column[..]).is_err());
+        }
+    }
+}
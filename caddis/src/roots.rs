@@ -0,0 +1,850 @@
+//! Roots of nonlinear equations.
+//!
+//! Scalar equations f(x) = 0 are solved inside a bracket [a, b] where f
+//! changes sign, by `bisection` or by `brent`, which combines bisection
+//! with secant and inverse quadratic interpolation steps and converges
+//! superlinearly on smooth functions while never doing worse than
+//! bisection.
+//!
+//! Systems F(x) = 0 are solved by Newton's method,
+//!
+//! ```text
+//! J(x_k) dx = -F(x_k),  x_k+1 = x_k + lambda dx
+//! ```
+//!
+//! damped by a backtracking line search that halves lambda, or less, until
+//! |F| decreases sufficiently. `Newton` solves for dx with the Jacobian
+//! supplied by the `System`: by finite differences, by hand, by forward
+//! (`Forward`) or reverse (`Reverse`) mode differentiation, or from the
+//! block-tridiagonal Henyey matrix. `NewtonKrylov` needs only residuals:
+//! it solves for dx inexactly by restarted GMRES, approximating the
+//! Jacobian-vector products by differences of F along the Krylov vectors.
+//!
+//! Failures are returned as a `RootError` holding the `Report` of the
+//! iterations so far.
+
+use std::fmt;
+
+use rgsl::linear_algebra::{LU_decomp, LU_solve};
+use rgsl::types::matrix::MatrixF64;
+use rgsl::types::permutation::Permutation;
+use rgsl::types::vector::VectorF64;
+
+use crate::dual::Dual;
+use crate::tape::{Tape, Var};
+
+type Scalar = f64;
+
+const MAX_BRACKET_ITERATIONS: usize = 200;
+/// Sufficient decrease of |F| relative to the step, after Dennis and
+/// Schnabel
+const ARMIJO: Scalar = 1e-4;
+/// Smallest damping of a Newton step before the line search gives up
+const MIN_DAMPING: Scalar = 1e-10;
+/// Relative step of finite differences
+const DIFFERENCE: Scalar = 1.5e-8;
+
+/// Work done by a solver and the largest residual |F_i| at each iterate,
+/// starting from the initial guess.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    pub iterations: usize,
+    /// Evaluations of f or F, including those of Jacobian-vector products
+    /// but not of Jacobians
+    pub evaluations: usize,
+    pub jacobians: usize,
+    pub linear_iterations: usize,
+    pub history: Vec<Scalar>,
+}
+
+impl Report {
+    /// Largest residual at the last iterate.
+    pub fn residual(&self) -> Scalar {
+        self.history.last().copied().unwrap_or(Scalar::INFINITY)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} iterations, {} evaluations, {} Jacobians, {} linear iterations, residual {:e}",
+            self.iterations,
+            self.evaluations,
+            self.jacobians,
+            self.linear_iterations,
+            self.residual()
+        )
+    }
+}
+
+/// Root found and how.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Root<T> {
+    pub x: T,
+    pub report: Report,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RootError {
+    /// f(a) and f(b) have the same sign.
+    NotBracketed {
+        a: Scalar,
+        fa: Scalar,
+        b: Scalar,
+        fb: Scalar,
+    },
+    /// The Newton correction could not be solved for.
+    Singular(Report),
+    /// No damping of the Newton step decreased the residual.
+    LineSearch(Report),
+    /// The residual was not finite.
+    NonFinite(Report),
+    /// The allowed number of iterations ran out.
+    NotConverged(Report),
+}
+
+impl RootError {
+    pub fn report(&self) -> Option<&Report> {
+        match self {
+            RootError::NotBracketed { .. } => None,
+            RootError::Singular(report)
+            | RootError::LineSearch(report)
+            | RootError::NonFinite(report)
+            | RootError::NotConverged(report) => Some(report),
+        }
+    }
+}
+
+impl fmt::Display for RootError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RootError::NotBracketed { a, fa, b, fb } => write!(
+                f,
+                "root not bracketed: f({:e}) = {:e}, f({:e}) = {:e}",
+                a, fa, b, fb
+            ),
+            RootError::Singular(report) => write!(f, "singular Jacobian after {}", report),
+            RootError::LineSearch(report) => write!(f, "line search failed after {}", report),
+            RootError::NonFinite(report) => write!(f, "residual not finite after {}", report),
+            RootError::NotConverged(report) => write!(f, "not converged after {}", report),
+        }
+    }
+}
+
+/// Root of `f` in [a, b] to within `tolerance` in x, by bisection.
+pub fn bisection<F>(
+    mut f: F,
+    a: Scalar,
+    b: Scalar,
+    tolerance: Scalar,
+) -> Result<Root<Scalar>, RootError>
+where
+    F: FnMut(Scalar) -> Scalar,
+{
+    let (mut a, mut b) = (a, b);
+    let (mut fa, fb) = (f(a), f(b));
+    let mut report = Report {
+        evaluations: 2,
+        history: vec![fa.abs().min(fb.abs())],
+        ..Report::default()
+    };
+    if fa == 0.0 || fb == 0.0 {
+        let x = if fa == 0.0 { a } else { b };
+        return Ok(Root { x, report });
+    }
+    if (fa < 0.0) == (fb < 0.0) || fa.is_nan() || fb.is_nan() {
+        return Err(RootError::NotBracketed { a, fa, b, fb });
+    }
+    while report.iterations < MAX_BRACKET_ITERATIONS {
+        report.iterations += 1;
+        let m = 0.5 * (a + b);
+        let fm = f(m);
+        report.evaluations += 1;
+        report.history.push(fm.abs());
+        if fm == 0.0 || (b - a).abs() <= 2.0 * tolerance {
+            return Ok(Root { x: m, report });
+        }
+        if (fm < 0.0) == (fa < 0.0) {
+            a = m;
+            fa = fm;
+        } else {
+            b = m;
+        }
+    }
+    Err(RootError::NotConverged(report))
+}
+
+/// Root of `f` in [a, b] to within `tolerance` in x, by Brent's method.
+pub fn brent<F>(
+    mut f: F,
+    a: Scalar,
+    b: Scalar,
+    tolerance: Scalar,
+) -> Result<Root<Scalar>, RootError>
+where
+    F: FnMut(Scalar) -> Scalar,
+{
+    let (mut a, mut b) = (a, b);
+    let (mut fa, mut fb) = (f(a), f(b));
+    let mut report = Report {
+        evaluations: 2,
+        history: vec![fb.abs()],
+        ..Report::default()
+    };
+    if fa * fb > 0.0 || fa.is_nan() || fb.is_nan() {
+        return Err(RootError::NotBracketed { a, fa, b, fb });
+    }
+    // b is the best estimate, c the other end of the bracket and a the
+    // previous b; d is the last step and e the one before.
+    let (mut c, mut fc) = (b, fb);
+    let (mut d, mut e) = (b - a, b - a);
+    while report.iterations < MAX_BRACKET_ITERATIONS {
+        report.iterations += 1;
+        if (fb > 0.0) == (fc > 0.0) {
+            c = a;
+            fc = fa;
+            d = b - a;
+            e = d;
+        }
+        if fc.abs() < fb.abs() {
+            a = b;
+            b = c;
+            c = a;
+            fa = fb;
+            fb = fc;
+            fc = fa;
+        }
+        let tol = 2.0 * Scalar::EPSILON * b.abs() + 0.5 * tolerance;
+        let m = 0.5 * (c - b);
+        if m.abs() <= tol || fb == 0.0 {
+            return Ok(Root { x: b, report });
+        }
+        if e.abs() >= tol && fa.abs() > fb.abs() {
+            let s = fb / fa;
+            let (mut p, mut q) = if a == c {
+                // Secant
+                (2.0 * m * s, 1.0 - s)
+            } else {
+                // Inverse quadratic interpolation
+                let (q, r) = (fa / fc, fb / fc);
+                (
+                    s * (2.0 * m * q * (q - r) - (b - a) * (r - 1.0)),
+                    (q - 1.0) * (r - 1.0) * (s - 1.0),
+                )
+            };
+            if p > 0.0 {
+                q = -q;
+            }
+            p = p.abs();
+            // Accept the interpolation only if it falls inside the bracket
+            // and converges faster than bisection.
+            if 2.0 * p < (3.0 * m * q - (tol * q).abs()).min((e * q).abs()) {
+                e = d;
+                d = p / q;
+            } else {
+                d = m;
+                e = d;
+            }
+        } else {
+            d = m;
+            e = d;
+        }
+        a = b;
+        fa = fb;
+        b += if d.abs() > tol { d } else { tol.copysign(m) };
+        fb = f(b);
+        report.evaluations += 1;
+        report.history.push(fb.abs());
+    }
+    Err(RootError::NotConverged(report))
+}
+
+/// Nonlinear system F(x) = 0 of as many equations as unknowns.
+pub trait System {
+    fn residual(&mut self, x: &[Scalar]) -> Vec<Scalar>;
+
+    /// Typical magnitude of each unknown, by which steps are measured.
+    fn scale(&self, x: &[Scalar]) -> Vec<Scalar> {
+        x.iter()
+            .map(|x| if *x != 0.0 { x.abs() } else { 1.0 })
+            .collect()
+    }
+
+    /// Rows dF_i/dx of the Jacobian at `x`, where F is `f`; by forward
+    /// differences unless overridden.
+    fn jacobian(&mut self, x: &[Scalar], f: &[Scalar]) -> Vec<Vec<Scalar>> {
+        let scale = self.scale(x);
+        let mut rows = vec![vec![0.0; x.len()]; f.len()];
+        let mut y = x.to_vec();
+        for j in 0..x.len() {
+            let h = DIFFERENCE * scale[j];
+            y[j] = x[j] + h;
+            let h = y[j] - x[j];
+            let g = self.residual(&y);
+            for (row, (g, f)) in rows.iter_mut().zip(g.iter().zip(f)) {
+                row[j] = (g - f) / h;
+            }
+            y[j] = x[j];
+        }
+        rows
+    }
+
+    /// Newton correction dx solving J dx = -f at `x`, or None if J is
+    /// singular; by dense LU decomposition unless overridden.
+    fn correction(&mut self, x: &[Scalar], f: &[Scalar]) -> Option<Vec<Scalar>> {
+        let j = self.jacobian(x, f);
+        let b: Vec<Scalar> = f.iter().map(|f| -f).collect();
+        solve_dense(&j, &b)
+    }
+}
+
+/// System of residuals `F` with a finite-difference Jacobian.
+pub struct Differenced<F>(pub F);
+
+impl<F> System for Differenced<F>
+where
+    F: FnMut(&[Scalar]) -> Vec<Scalar>,
+{
+    fn residual(&mut self, x: &[Scalar]) -> Vec<Scalar> {
+        (self.0)(x)
+    }
+}
+
+/// System of residuals `F` and Jacobian rows `J`.
+pub struct Analytic<F, J>(pub F, pub J);
+
+impl<F, J> System for Analytic<F, J>
+where
+    F: FnMut(&[Scalar]) -> Vec<Scalar>,
+    J: FnMut(&[Scalar]) -> Vec<Vec<Scalar>>,
+{
+    fn residual(&mut self, x: &[Scalar]) -> Vec<Scalar> {
+        (self.0)(x)
+    }
+
+    fn jacobian(&mut self, x: &[Scalar], _f: &[Scalar]) -> Vec<Vec<Scalar>> {
+        (self.1)(x)
+    }
+}
+
+/// System of N unknowns differentiated in forward mode.
+pub struct Forward<F, const N: usize>(pub F);
+
+impl<F, const N: usize> System for Forward<F, N>
+where
+    F: Fn([Dual<[Scalar; N]>; N]) -> [Dual<[Scalar; N]>; N],
+{
+    fn residual(&mut self, x: &[Scalar]) -> Vec<Scalar> {
+        let mut y = [0.0; N];
+        y.copy_from_slice(x);
+        (self.0)(Dual::variables(y))
+            .iter()
+            .map(|f| f.value)
+            .collect()
+    }
+
+    fn jacobian(&mut self, x: &[Scalar], _f: &[Scalar]) -> Vec<Vec<Scalar>> {
+        let mut y = [0.0; N];
+        y.copy_from_slice(x);
+        (self.0)(Dual::variables(y))
+            .iter()
+            .map(|f| f.gradient.to_vec())
+            .collect()
+    }
+}
+
+/// System differentiated in reverse mode, one backward pass per equation.
+pub struct Reverse<F>(pub F);
+
+impl<F> Reverse<F>
+where
+    F: for<'t> Fn(&[Var<'t>]) -> Vec<Var<'t>>,
+{
+    /// Reverse-mode system of `f`, which unlike the tuple constructor lets
+    /// a closure be generic in the lifetime of its tape.
+    pub fn new(f: F) -> Self {
+        Reverse(f)
+    }
+}
+
+impl<F> System for Reverse<F>
+where
+    F: for<'t> Fn(&[Var<'t>]) -> Vec<Var<'t>>,
+{
+    fn residual(&mut self, x: &[Scalar]) -> Vec<Scalar> {
+        let tape = Tape::new();
+        let x: Vec<Var> = x.iter().map(|x| tape.var(*x)).collect();
+        (self.0)(&x).iter().map(|f| f.value()).collect()
+    }
+
+    fn jacobian(&mut self, x: &[Scalar], _f: &[Scalar]) -> Vec<Vec<Scalar>> {
+        let tape = Tape::new();
+        let x: Vec<Var> = x.iter().map(|x| tape.var(*x)).collect();
+        (self.0)(&x)
+            .iter()
+            .map(|f| f.gradient().wrt_all(&x))
+            .collect()
+    }
+}
+
+/// Solves J x = b by LU decomposition with partial pivoting.
+pub fn solve_dense(j: &[Vec<Scalar>], b: &[Scalar]) -> Option<Vec<Scalar>> {
+    let n = b.len();
+    let mut m = MatrixF64::new(n, n).unwrap();
+    for (row, jr) in j.iter().enumerate() {
+        for (col, jrc) in jr.iter().enumerate() {
+            m.set(row, col, *jrc);
+        }
+    }
+    let mut p = Permutation::new(n).unwrap();
+    let mut signum = 0;
+    LU_decomp(&mut m, &mut p, &mut signum).ok()?;
+    let mut rhs = VectorF64::new(n).unwrap();
+    for (i, b) in b.iter().enumerate() {
+        rhs.set(i, *b);
+    }
+    let mut x = VectorF64::new(n).unwrap();
+    LU_solve(&m, &p, &rhs, &mut x).ok()?;
+    let x: Vec<Scalar> = (0..n).map(|i| x.get(i)).collect();
+    if x.iter().all(|x| x.is_finite()) {
+        Some(x)
+    } else {
+        None
+    }
+}
+
+/// Approximate solution of A x = b by GMRES.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Krylov {
+    pub x: Vec<Scalar>,
+    pub iterations: usize,
+    /// |b - A x|/|b|
+    pub residual: Scalar,
+}
+
+/// Solves A x = b from x = 0 by GMRES restarted every `restart`
+/// iterations, until |b - A x| <= `tolerance` |b| or `max_iterations`
+/// products by A.
+pub fn gmres<A>(
+    mut apply: A,
+    b: &[Scalar],
+    tolerance: Scalar,
+    restart: usize,
+    max_iterations: usize,
+) -> Krylov
+where
+    A: FnMut(&[Scalar]) -> Vec<Scalar>,
+{
+    let n = b.len();
+    let norm_b = norm(b);
+    let mut x = vec![0.0; n];
+    let mut iterations = 0;
+    if norm_b == 0.0 {
+        return Krylov {
+            x,
+            iterations,
+            residual: 0.0,
+        };
+    }
+    let mut r = b.to_vec();
+    loop {
+        let beta = norm(&r);
+        if beta <= tolerance * norm_b || iterations >= max_iterations || !beta.is_finite() {
+            return Krylov {
+                x,
+                iterations,
+                residual: beta / norm_b,
+            };
+        }
+        // Arnoldi basis v, Hessenberg columns h reduced to upper triangular
+        // by the Givens rotations (c, s), and g = |r| Q^T e_1.
+        let mut v = vec![r.iter().map(|r| r / beta).collect::<Vec<_>>()];
+        let mut h: Vec<Vec<Scalar>> = Vec::new();
+        let mut rotations: Vec<(Scalar, Scalar)> = Vec::new();
+        let mut g = vec![beta];
+        while h.len() < restart.max(1) && iterations < max_iterations {
+            let k = h.len();
+            let mut w = apply(&v[k]);
+            iterations += 1;
+            let mut column = vec![0.0; k + 2];
+            for i in 0..=k {
+                column[i] = dot(&w, &v[i]);
+                axpy(-column[i], &v[i], &mut w);
+            }
+            column[k + 1] = norm(&w);
+            for (i, (c, s)) in rotations.iter().enumerate() {
+                let (a, b) = (column[i], column[i + 1]);
+                column[i] = c * a + s * b;
+                column[i + 1] = -s * a + c * b;
+            }
+            let d = column[k].hypot(column[k + 1]);
+            let (c, s) = if d > 0.0 {
+                (column[k] / d, column[k + 1] / d)
+            } else {
+                (1.0, 0.0)
+            };
+            let breakdown = column[k + 1] == 0.0;
+            if !breakdown {
+                v.push(w.iter().map(|w| w / column[k + 1]).collect());
+            }
+            column[k] = d;
+            column[k + 1] = 0.0;
+            rotations.push((c, s));
+            g.push(-s * g[k]);
+            g[k] *= c;
+            h.push(column);
+            if breakdown || g[k + 1].abs() <= tolerance * norm_b {
+                break;
+            }
+        }
+        // Back substitution for the coefficients of the basis.
+        let m = h.len();
+        let mut y = vec![0.0; m];
+        for i in (0..m).rev() {
+            let sum: Scalar = (i + 1..m).map(|j| h[j][i] * y[j]).sum();
+            y[i] = if h[i][i] != 0.0 {
+                (g[i] - sum) / h[i][i]
+            } else {
+                0.0
+            };
+        }
+        for (y, v) in y.iter().zip(&v) {
+            axpy(*y, v, &mut x);
+        }
+        let ax = apply(&x);
+        r = b.iter().zip(&ax).map(|(b, ax)| b - ax).collect();
+    }
+}
+
+fn dot(a: &[Scalar], b: &[Scalar]) -> Scalar {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn norm(a: &[Scalar]) -> Scalar {
+    dot(a, a).sqrt()
+}
+
+fn max_norm(a: &[Scalar]) -> Scalar {
+    a.iter().fold(0.0, |m: Scalar, a| m.max(a.abs()))
+}
+
+/// y += a x
+fn axpy(a: Scalar, x: &[Scalar], y: &mut [Scalar]) {
+    for (y, x) in y.iter_mut().zip(x) {
+        *y += a * x;
+    }
+}
+
+/// Damped Newton iteration until the largest residual |F_i| falls below
+/// the tolerance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Newton {
+    tolerance: Scalar,
+    max_iterations: usize,
+    line_search: bool,
+}
+
+impl Newton {
+    pub fn new(tolerance: Scalar) -> Self {
+        Newton {
+            tolerance,
+            max_iterations: 50,
+            line_search: true,
+        }
+    }
+
+    pub fn with_max_iterations(mut self, iterations: usize) -> Self {
+        self.max_iterations = iterations;
+        self
+    }
+
+    /// Takes full Newton steps if `line_search` is false.
+    pub fn with_line_search(mut self, line_search: bool) -> Self {
+        self.line_search = line_search;
+        self
+    }
+
+    pub fn solve<S: System>(
+        &self,
+        system: &mut S,
+        x0: &[Scalar],
+    ) -> Result<Root<Vec<Scalar>>, RootError> {
+        self.iterate(system, x0, |system, x, f, report| {
+            report.jacobians += 1;
+            system.correction(x, f)
+        })
+    }
+
+    /// Newton iteration from `x0`, with corrections from `correction`.
+    fn iterate<S, C>(
+        &self,
+        system: &mut S,
+        x0: &[Scalar],
+        mut correction: C,
+    ) -> Result<Root<Vec<Scalar>>, RootError>
+    where
+        S: System,
+        C: FnMut(&mut S, &[Scalar], &[Scalar], &mut Report) -> Option<Vec<Scalar>>,
+    {
+        let mut x = x0.to_vec();
+        let mut f = system.residual(&x);
+        let mut report = Report {
+            evaluations: 1,
+            history: vec![max_norm(&f)],
+            ..Report::default()
+        };
+        loop {
+            let residual = report.residual();
+            if !residual.is_finite() {
+                return Err(RootError::NonFinite(report));
+            }
+            if residual <= self.tolerance {
+                return Ok(Root { x, report });
+            }
+            if report.iterations >= self.max_iterations {
+                return Err(RootError::NotConverged(report));
+            }
+            report.iterations += 1;
+            let dx = match correction(system, &x, &f, &mut report) {
+                Some(dx) => dx,
+                None => return Err(RootError::Singular(report)),
+            };
+
+            // Backtrack along dx, minimising the quadratic through |F|^2
+            // at lambda = 0, its slope there and at the rejected lambda.
+            let phi0 = dot(&f, &f);
+            let mut lambda = 1.0;
+            loop {
+                let y: Vec<Scalar> = x.iter().zip(&dx).map(|(x, dx)| x + lambda * dx).collect();
+                let g = system.residual(&y);
+                report.evaluations += 1;
+                let phi = dot(&g, &g);
+                let accept = phi.sqrt() <= (1.0 - ARMIJO * lambda) * phi0.sqrt();
+                if accept || !self.line_search {
+                    x = y;
+                    f = g;
+                    break;
+                }
+                lambda = if phi.is_finite() {
+                    let minimum = phi0 * lambda * lambda / (phi - phi0 + 2.0 * phi0 * lambda);
+                    minimum.max(0.1 * lambda).min(0.5 * lambda)
+                } else {
+                    0.1 * lambda
+                };
+                if lambda < MIN_DAMPING {
+                    return Err(RootError::LineSearch(report));
+                }
+            }
+            report.history.push(max_norm(&f));
+        }
+    }
+}
+
+/// Jacobian-free Newton-Krylov iteration: damped Newton with corrections
+/// solved by GMRES to a relative residual `forcing`, in units of
+/// `System::scale`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NewtonKrylov {
+    newton: Newton,
+    forcing: Scalar,
+    restart: usize,
+    max_linear_iterations: usize,
+}
+
+impl NewtonKrylov {
+    pub fn new(tolerance: Scalar) -> Self {
+        NewtonKrylov {
+            newton: Newton::new(tolerance),
+            forcing: 1e-4,
+            restart: 30,
+            max_linear_iterations: 1000,
+        }
+    }
+
+    pub fn with_max_iterations(mut self, iterations: usize) -> Self {
+        self.newton = self.newton.with_max_iterations(iterations);
+        self
+    }
+
+    pub fn with_line_search(mut self, line_search: bool) -> Self {
+        self.newton = self.newton.with_line_search(line_search);
+        self
+    }
+
+    pub fn with_forcing(mut self, forcing: Scalar) -> Self {
+        self.forcing = forcing;
+        self
+    }
+
+    pub fn with_restart(mut self, restart: usize) -> Self {
+        self.restart = restart;
+        self
+    }
+
+    pub fn with_max_linear_iterations(mut self, iterations: usize) -> Self {
+        self.max_linear_iterations = iterations;
+        self
+    }
+
+    /// Root of `system` from `x0`, using only its residuals and scale.
+    pub fn solve<S: System>(
+        &self,
+        system: &mut S,
+        x0: &[Scalar],
+    ) -> Result<Root<Vec<Scalar>>, RootError> {
+        self.newton.iterate(system, x0, |system, x, f, report| {
+            // J diag(s) z = -F, with J v ~ (F(x + h v) - F(x))/h
+            let s = system.scale(x);
+            let b: Vec<Scalar> = f.iter().map(|f| -f).collect();
+            let mut evaluations = 0;
+            let krylov = gmres(
+                |z| {
+                    evaluations += 1;
+                    let h = DIFFERENCE / norm(z).max(Scalar::MIN_POSITIVE);
+                    let y: Vec<Scalar> = (0..x.len()).map(|i| x[i] + h * s[i] * z[i]).collect();
+                    let g = system.residual(&y);
+                    g.iter().zip(f).map(|(g, f)| (g - f) / h).collect()
+                },
+                &b,
+                self.forcing,
+                self.restart,
+                self.max_linear_iterations,
+            );
+            report.evaluations += evaluations;
+            report.linear_iterations += krylov.iterations;
+            if !krylov.residual.is_finite() || krylov.residual >= 1.0 {
+                return None;
+            }
+            Some(krylov.x.iter().zip(&s).map(|(z, s)| z * s).collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::{Mul, Sub};
+
+    #[test]
+    fn test_brackets() {
+        // Radius of an aluminium capsule, a spherical shell 5 mm thick,
+        // holding a 2 t payload in 3 t.
+        let shell = |r: Scalar| 4.0 * std::f64::consts::PI * r * r * 5e-3 * 2700.0;
+        let f = |r: Scalar| shell(r) + 2000.0 - 3000.0;
+        let exact = (1000.0 / (4.0 * std::f64::consts::PI * 5e-3 * 2700.0)).sqrt();
+        let a = bisection(f, 0.1, 10.0, 1e-10).unwrap();
+        let b = brent(f, 0.1, 10.0, 1e-10).unwrap();
+        assert!((a.x - exact).abs() < 1e-9);
+        assert!((b.x - exact).abs() < 1e-9);
+        assert!(b.report.evaluations < a.report.evaluations / 3);
+
+        // Speed of an orbital ring at 400 km holding up 1.1 times its own
+        // mass in cables and stations, v^2/r = (1 + 1.1) GM/r^2.
+        let (gm, r) = (3.986_004_418e14, 6.771e6);
+        let v = brent(|v| v * v / r - 2.1 * gm / (r * r), 0.0, 2e4, 1e-9).unwrap();
+        assert!((v.x - (2.1 * gm / r).sqrt()).abs() < 1e-6);
+
+        match brent(|x| x * x + 1.0, -1.0, 1.0, 1e-9) {
+            Err(RootError::NotBracketed { fa, fb, .. }) => assert_eq!((fa, fb), (2.0, 2.0)),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    /// Rosenbrock's banana valley as a system, singular away from its root
+    /// along x = 0.
+    fn rosenbrock<T>(x: &[T]) -> [T; 2]
+    where
+        T: Copy + Sub<Output = T> + Mul<Output = T> + Mul<Scalar, Output = T>,
+        Scalar: Sub<T, Output = T>,
+    {
+        [1.0 - x[0], (x[1] - x[0] * x[0]) * 10.0]
+    }
+
+    #[test]
+    fn test_newton() {
+        let x0 = [-1.2, 1.0];
+        let forward = Newton::new(1e-12)
+            .solve(
+                &mut Forward(|x: [Dual<[Scalar; 2]>; 2]| rosenbrock(&x)),
+                &x0,
+            )
+            .unwrap();
+        let reverse = Newton::new(1e-12)
+            .solve(&mut Reverse::new(|x| rosenbrock(x).to_vec()), &x0)
+            .unwrap();
+        let differenced = Newton::new(1e-12)
+            .solve(&mut Differenced(|x: &[Scalar]| rosenbrock(x).to_vec()), &x0)
+            .unwrap();
+        for root in [&forward, &reverse, &differenced].iter() {
+            assert!((root.x[0] - 1.0).abs() < 1e-12 && (root.x[1] - 1.0).abs() < 1e-12);
+            assert!(root.report.residual() <= 1e-12);
+        }
+        assert_eq!(forward.report.history, reverse.report.history);
+
+        // A full step from a poor guess overshoots where the line search
+        // does not.
+        let f = |x: &[Scalar]| vec![x[0].atan()];
+        let damped = Newton::new(1e-12)
+            .solve(&mut Differenced(f), &[3.0])
+            .unwrap();
+        assert!(damped.x[0].abs() < 1e-12);
+        let full = Newton::new(1e-12)
+            .with_line_search(false)
+            .solve(&mut Differenced(f), &[3.0])
+            .unwrap_err();
+        assert!(full.report().unwrap().residual() > 1.0);
+
+        let singular = Newton::new(1e-12).solve(
+            &mut Differenced(|x: &[Scalar]| vec![x[0] * x[0] + 1.0, 0.0]),
+            &[0.0, 0.0],
+        );
+        assert!(matches!(singular, Err(RootError::Singular(_))));
+    }
+
+    /// Bratu's problem u'' + e^u = 0 on (0, 1) with u = 0 at both ends, by
+    /// central differences on `n` interior points.
+    fn bratu(u: &[Scalar]) -> Vec<Scalar> {
+        let n = u.len();
+        let h = 1.0 / (n + 1) as Scalar;
+        (0..n)
+            .map(|i| {
+                let left = if i > 0 { u[i - 1] } else { 0.0 };
+                let right = if i + 1 < n { u[i + 1] } else { 0.0 };
+                (left - 2.0 * u[i] + right) / (h * h) + u[i].exp()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_gmres() {
+        let a = [[4.0, 1.0, 0.0], [1.0, 3.0, -1.0], [0.0, -1.0, 2.0]];
+        let b = [1.0, 2.0, 3.0];
+        let apply = |x: &[Scalar]| a.iter().map(|row| dot(row, x)).collect();
+        let full = gmres(apply, &b, 1e-12, 3, 10);
+        let restarted = gmres(apply, &b, 1e-12, 1, 100);
+        let exact = solve_dense(&a.iter().map(|row| row.to_vec()).collect::<Vec<_>>(), &b).unwrap();
+        assert!(full.iterations <= 3);
+        assert!(restarted.iterations > full.iterations);
+        for (i, exact) in exact.iter().enumerate() {
+            assert!((full.x[i] - exact).abs() < 1e-10);
+            assert!((restarted.x[i] - exact).abs() < 1e-10);
+        }
+
+        let x0 = vec![0.0; 50];
+        let newton = Newton::new(1e-8)
+            .solve(&mut Differenced(bratu), &x0)
+            .unwrap();
+        let krylov = NewtonKrylov::new(1e-8)
+            .with_restart(50)
+            .solve(&mut Differenced(bratu), &x0)
+            .unwrap();
+        assert_eq!(krylov.report.jacobians, 0);
+        assert!(krylov.report.linear_iterations > 0);
+        for (a, b) in newton.x.iter().zip(&krylov.x) {
+            assert!((a - b).abs() < 1e-8);
+        }
+        // The lower branch peaks at 0.1405 in the middle.
+        assert!((newton.x[24] - 0.1405).abs() < 1e-3);
+    }
+}
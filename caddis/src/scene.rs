@@ -0,0 +1,528 @@
+//! Scene description and JSON export for the three.js viewer in `www/`.
+//!
+//! A scene is written as a single JSON document which `www/js/scene.js`
+//! turns into three.js meshes. Lengths are in metres and rotations are unit
+//! quaternions `[x, y, z, w]`, as in glTF 2.0.
+//!
+//! ```text
+//! {
+//!   "asset": { "generator": "caddis", "version": 1 },
+//!   "nodes": [
+//!     {
+//!       "name": "hull",
+//!       "material": "steel",
+//!       "translation": [x, y, z],
+//!       "rotation": [x, y, z, w],
+//!       "scale": [x, y, z],
+//!       "primitive": { "type": "sphere", "center": [x, y, z], "radius": r }
+//!                  | { "type": "cylinder", "center": [x, y, z], "axis": [x, y, z],
+//!                      "radius": r, "length": l }
+//!                  | { "type": "cuboid", "center": [x, y, z], "size": [x, y, z] }
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Primitives are given in node coordinates; the node transform is applied
+//! as scale, then rotation, then translation. Non-finite numbers are written
+//! as `null`.
+
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+use crate::geometry::{Cuboid, Cylinder, Sphere, Vector};
+
+type Scalar = f64;
+
+const SCENE_VERSION: usize = 1;
+
+pub enum Primitive {
+    Sphere(Sphere),
+    Cylinder(Cylinder),
+    Cuboid(Cuboid),
+}
+
+pub struct Transform {
+    pub translation: Vector,
+    pub rotation: [Scalar; 4],
+    pub scale: Vector,
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform {
+            translation: Vector::new(),
+            rotation: [0.0, 0.0, 0.0, 1.0],
+            scale: Vector::from_slice(&[1.0, 1.0, 1.0]),
+        }
+    }
+}
+
+impl Transform {
+    /// Maps a point from node coordinates to scene coordinates.
+    pub fn apply(&self, point: &Vector) -> Vector {
+        let mut p = point.clone();
+        for i in 0..3 {
+            p[i] *= self.scale[i];
+        }
+        rotate(&self.rotation, &p) + self.translation.clone()
+    }
+
+    /// Maps a point from scene coordinates to node coordinates.
+    pub fn invert(&self, point: &Vector) -> Vector {
+        let [x, y, z, w] = self.rotation;
+        let mut p = rotate(
+            &[-x, -y, -z, w],
+            &(point.clone() - self.translation.clone()),
+        );
+        for i in 0..3 {
+            p[i] /= self.scale[i];
+        }
+        p
+    }
+
+    /// Maps the gradient `g` of a function of node coordinates to the
+    /// gradient of the same function of scene coordinates.
+    pub fn apply_gradient(&self, g: &Vector) -> Vector {
+        let mut g = g.clone();
+        for i in 0..3 {
+            g[i] /= self.scale[i];
+        }
+        rotate(&self.rotation, &g)
+    }
+
+    /// Smallest scale factor, by which distances in node coordinates are at
+    /// least multiplied when mapped into the scene.
+    pub fn min_scale(&self) -> Scalar {
+        self.scale[0]
+            .abs()
+            .min(self.scale[1].abs())
+            .min(self.scale[2].abs())
+    }
+}
+
+/// Rotates `v` by the unit quaternion `q = [x, y, z, w]`.
+fn rotate(q: &[Scalar; 4], v: &Vector) -> Vector {
+    let [x, y, z, w] = *q;
+    let (vx, vy, vz) = (v[0], v[1], v[2]);
+    // t = 2 (q.xyz × v)
+    let tx = 2.0 * (y * vz - z * vy);
+    let ty = 2.0 * (z * vx - x * vz);
+    let tz = 2.0 * (x * vy - y * vx);
+    Vector::from_slice(&[
+        vx + w * tx + (y * tz - z * ty),
+        vy + w * ty + (z * tx - x * tz),
+        vz + w * tz + (x * ty - y * tx),
+    ])
+}
+
+pub struct Node {
+    pub name: String,
+    pub primitive: Primitive,
+    pub transform: Transform,
+    pub material: String,
+}
+
+impl Node {
+    pub fn new(name: &str, primitive: Primitive, material: &str) -> Self {
+        Node {
+            name: name.to_string(),
+            primitive,
+            transform: Transform::default(),
+            material: material.to_string(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Scene {
+    pub nodes: Vec<Node>,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Scene { nodes: Vec::new() }
+    }
+
+    pub fn add(&mut self, node: Node) -> &mut Self {
+        self.nodes.push(node);
+        self
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut s = String::new();
+        s.push_str("{\n");
+        let _ = writeln!(
+            s,
+            "  \"asset\": {{ \"generator\": \"caddis\", \"version\": {} }},",
+            SCENE_VERSION
+        );
+        s.push_str("  \"nodes\": [");
+        for (i, node) in self.nodes.iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+            s.push_str("\n    {");
+            let _ = write!(s, "\n      \"name\": {},", json_string(&node.name));
+            let _ = write!(s, "\n      \"material\": {},", json_string(&node.material));
+            let t = &node.transform;
+            let _ = write!(
+                s,
+                "\n      \"translation\": {},",
+                json_vector(&t.translation)
+            );
+            let _ = write!(s, "\n      \"rotation\": {},", json_array(&t.rotation));
+            let _ = write!(s, "\n      \"scale\": {},", json_vector(&t.scale));
+            let _ = write!(
+                s,
+                "\n      \"primitive\": {}",
+                json_primitive(&node.primitive)
+            );
+            s.push_str("\n    }");
+        }
+        if !self.nodes.is_empty() {
+            s.push_str("\n  ");
+        }
+        s.push_str("]\n}\n");
+        s
+    }
+
+    pub fn write_json<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(self.to_json().as_bytes())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        self.write_json(&mut file)
+    }
+}
+
+/// The capsule of `www/js/capsule.js`, which `caddis scene <path>` writes
+/// for `www/scene.json`: a steel hull, a cylinder of 5.02 m outer radius and
+/// 30 m length along y, closed by hemispherical ends.
+pub fn capsule() -> Scene {
+    let (radius, length) = (5.02, 30.0);
+    let y = Vector::from_slice(&[0.0, 1.0, 0.0]);
+    let mut scene = Scene::new();
+    let cylinder = Cylinder::new(&Vector::new(), &y, radius, length);
+    scene.add(Node::new("hull", Primitive::Cylinder(cylinder), "steel"));
+    for (name, end) in [("bow", 0.5), ("stern", -0.5)].iter() {
+        let center = Vector::from_slice(&[0.0, end * length, 0.0]);
+        let sphere = Sphere::from_radius(&center, radius);
+        scene.add(Node::new(name, Primitive::Sphere(sphere), "steel"));
+    }
+    scene
+}
+
+fn json_primitive(p: &Primitive) -> String {
+    match p {
+        Primitive::Sphere(sphere) => format!(
+            "{{ \"type\": \"sphere\", \"center\": {}, \"radius\": {} }}",
+            json_vector(&sphere.center()),
+            json_number(sphere.radius())
+        ),
+        Primitive::Cylinder(cylinder) => format!(
+            "{{ \"type\": \"cylinder\", \"center\": {}, \"axis\": {}, \"radius\": {}, \"length\": {} }}",
+            json_vector(&cylinder.center),
+            json_vector(&cylinder.axis),
+            json_number(cylinder.radius),
+            json_number(cylinder.length)
+        ),
+        Primitive::Cuboid(cuboid) => format!(
+            "{{ \"type\": \"cuboid\", \"center\": {}, \"size\": {} }}",
+            json_vector(&cuboid.center),
+            json_vector(&(cuboid.half_extents.clone() * 2.0))
+        ),
+    }
+}
+
+fn json_number(x: Scalar) -> String {
+    if x.is_finite() {
+        format!("{}", x)
+    } else {
+        "null".to_string()
+    }
+}
+
+fn json_array(xs: &[Scalar]) -> String {
+    let items: Vec<String> = xs.iter().map(|x| json_number(*x)).collect();
+    format!("[{}]", items.join(", "))
+}
+
+fn json_vector(v: &Vector) -> String {
+    json_array(&[v[0], v[1], v[2]])
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let mut scene = Scene::new();
+        let sphere = Sphere::from_radius(&Vector::from_slice(&[0.0, 1.0, 0.0]), 2.5);
+        scene.add(Node::new(
+            "nose \"cone\"",
+            Primitive::Sphere(sphere),
+            "steel",
+        ));
+        let json = scene.to_json();
+        assert!(json.contains("\"name\": \"nose \\\"cone\\\"\""));
+        assert!(json.contains("\"material\": \"steel\""));
+        assert!(json.contains("\"type\": \"sphere\", \"center\": [0, 1, 0], \"radius\": 2.5"));
+        assert!(json.contains("\"rotation\": [0, 0, 0, 1]"));
+    }
+
+    /// Parsed JSON value, enough of it to check the schema.
+    #[derive(Debug, PartialEq)]
+    enum Json {
+        Null,
+        Number(Scalar),
+        String(String),
+        Array(Vec<Json>),
+        Object(Vec<(String, Json)>),
+    }
+
+    impl Json {
+        fn get(&self, key: &str) -> &Json {
+            match self {
+                Json::Object(fields) => fields
+                    .iter()
+                    .find(|(k, _)| k == key)
+                    .map(|(_, v)| v)
+                    .unwrap_or_else(|| panic!("no field {}", key)),
+                _ => panic!("not an object"),
+            }
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            match self {
+                Json::Object(fields) => fields.iter().map(|(k, _)| k.as_str()).collect(),
+                _ => panic!("not an object"),
+            }
+        }
+
+        fn numbers(&self) -> Vec<Scalar> {
+            match self {
+                Json::Array(items) => items
+                    .iter()
+                    .map(|item| match item {
+                        Json::Number(x) => *x,
+                        _ => panic!("not a number"),
+                    })
+                    .collect(),
+                _ => panic!("not an array"),
+            }
+        }
+    }
+
+    fn parse(s: &str) -> Json {
+        let mut chars = s.chars().peekable();
+        let json = parse_value(&mut chars);
+        skip_whitespace(&mut chars);
+        assert_eq!(chars.next(), None, "trailing characters");
+        json
+    }
+
+    type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+    fn skip_whitespace(chars: &mut Chars) {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+    }
+
+    fn expect(chars: &mut Chars, c: char) {
+        skip_whitespace(chars);
+        assert_eq!(chars.next(), Some(c));
+    }
+
+    fn parse_value(chars: &mut Chars) -> Json {
+        skip_whitespace(chars);
+        match *chars.peek().expect("unexpected end") {
+            '{' => {
+                chars.next();
+                let mut fields = Vec::new();
+                skip_whitespace(chars);
+                if chars.peek() == Some(&'}') {
+                    chars.next();
+                    return Json::Object(fields);
+                }
+                loop {
+                    skip_whitespace(chars);
+                    let key = match parse_value(chars) {
+                        Json::String(key) => key,
+                        other => panic!("key {:?}", other),
+                    };
+                    expect(chars, ':');
+                    fields.push((key, parse_value(chars)));
+                    skip_whitespace(chars);
+                    match chars.next() {
+                        Some(',') => continue,
+                        Some('}') => return Json::Object(fields),
+                        other => panic!("unexpected {:?}", other),
+                    }
+                }
+            }
+            '[' => {
+                chars.next();
+                let mut items = Vec::new();
+                skip_whitespace(chars);
+                if chars.peek() == Some(&']') {
+                    chars.next();
+                    return Json::Array(items);
+                }
+                loop {
+                    items.push(parse_value(chars));
+                    skip_whitespace(chars);
+                    match chars.next() {
+                        Some(',') => continue,
+                        Some(']') => return Json::Array(items),
+                        other => panic!("unexpected {:?}", other),
+                    }
+                }
+            }
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next().expect("unterminated string") {
+                        '"' => return Json::String(s),
+                        '\\' => match chars.next() {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some('r') => s.push('\r'),
+                            Some('u') => {
+                                let hex: String = chars.by_ref().take(4).collect();
+                                let c = u32::from_str_radix(&hex, 16).unwrap();
+                                s.push(std::char::from_u32(c).unwrap());
+                            }
+                            Some(c) => s.push(c),
+                            None => panic!("unterminated string"),
+                        },
+                        c => s.push(c),
+                    }
+                }
+            }
+            'n' => {
+                let null: String = chars.by_ref().take(4).collect();
+                assert_eq!(null, "null");
+                Json::Null
+            }
+            _ => {
+                let mut number = String::new();
+                while chars
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_digit() || "+-.eE".contains(*c))
+                {
+                    number.push(chars.next().unwrap());
+                }
+                Json::Number(number.parse().expect("number"))
+            }
+        }
+    }
+
+    #[test]
+    fn test_schema() {
+        let mut scene = capsule();
+        let cuboid = Cuboid::new(&Vector::new(), &Vector::from_slice(&[1.0, 2.0, 3.0]));
+        scene.add(Node::new("tab\there", Primitive::Cuboid(cuboid), "glass"));
+        let json = parse(&scene.to_json());
+        assert_eq!(json.keys(), ["asset", "nodes"]);
+        assert_eq!(
+            json.get("asset").get("generator"),
+            &Json::String("caddis".into())
+        );
+        assert_eq!(json.get("asset").get("version"), &Json::Number(1.0));
+        let nodes = match json.get("nodes") {
+            Json::Array(nodes) => nodes,
+            _ => panic!("nodes is not an array"),
+        };
+        assert_eq!(nodes.len(), 4);
+        for node in nodes {
+            assert_eq!(
+                node.keys(),
+                [
+                    "name",
+                    "material",
+                    "translation",
+                    "rotation",
+                    "scale",
+                    "primitive"
+                ]
+            );
+            assert_eq!(node.get("translation").numbers(), [0.0, 0.0, 0.0]);
+            assert_eq!(node.get("rotation").numbers(), [0.0, 0.0, 0.0, 1.0]);
+            assert_eq!(node.get("scale").numbers(), [1.0, 1.0, 1.0]);
+        }
+
+        let hull = nodes[0].get("primitive");
+        assert_eq!(hull.keys(), ["type", "center", "axis", "radius", "length"]);
+        assert_eq!(hull.get("type"), &Json::String("cylinder".into()));
+        assert_eq!(hull.get("axis").numbers(), [0.0, 1.0, 0.0]);
+        assert_eq!(hull.get("radius"), &Json::Number(5.02));
+        assert_eq!(hull.get("length"), &Json::Number(30.0));
+        let bow = nodes[1].get("primitive");
+        assert_eq!(bow.keys(), ["type", "center", "radius"]);
+        assert_eq!(bow.get("center").numbers(), [0.0, 15.0, 0.0]);
+        let box_ = &nodes[3];
+        assert_eq!(box_.get("name"), &Json::String("tab\there".into()));
+        assert_eq!(box_.get("material"), &Json::String("glass".into()));
+        assert_eq!(box_.get("primitive").keys(), ["type", "center", "size"]);
+        assert_eq!(box_.get("primitive").get("size").numbers(), [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_capsule() {
+        // www/scene.json is what `caddis scene ../www/scene.json` writes.
+        assert_eq!(include_str!("../../www/scene.json"), capsule().to_json());
+    }
+
+    #[test]
+    fn test_transform_roundtrip() {
+        let h = std::f64::consts::FRAC_1_SQRT_2;
+        let t = Transform {
+            translation: Vector::from_slice(&[1.0, 2.0, 3.0]),
+            rotation: [0.0, 0.0, h, h],
+            scale: Vector::from_slice(&[2.0, 2.0, 2.0]),
+        };
+        let p = t.apply(&Vector::from_slice(&[1.0, 0.0, 0.0]));
+        assert!((p[0] - 1.0).abs() < 1e-12);
+        assert!((p[1] - 4.0).abs() < 1e-12);
+        assert!((p[2] - 3.0).abs() < 1e-12);
+        let q = t.invert(&p);
+        assert!((q[0] - 1.0).abs() < 1e-12);
+        assert!(q[1].abs() < 1e-12 && q[2].abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_non_finite() {
+        assert_eq!(
+            json_array(&[1.5, f64::NAN, f64::INFINITY]),
+            "[1.5, null, null]"
+        );
+    }
+}
@@ -0,0 +1,383 @@
+//! Signed distance functions for the `geometry` primitives and their
+//! constructive solid geometry combinations.
+//!
+//! Distances are negative inside a solid, zero on its surface and positive
+//! outside. The gradient of a signed distance function is the outward unit
+//! normal wherever it is differentiable.
+
+use crate::geometry::{Cuboid, Cylinder, FiniteDimVectorSpace, NormedSpace, Sphere, Vector};
+use crate::scene::{Node, Primitive, Scene};
+
+type Scalar = f64;
+
+const GRADIENT_STEP: Scalar = 1e-6;
+const TRACE_EPSILON: Scalar = 1e-9;
+const TRACE_MAX_STEPS: usize = 256;
+
+pub trait SignedDistance {
+    fn distance(&self, point: &Vector) -> Scalar;
+
+    /// Gradient of the distance at `point`, by central differences unless
+    /// the primitive knows better.
+    fn gradient(&self, point: &Vector) -> Vector {
+        let mut g = Vector::new();
+        for i in 0..Vector::dimension() {
+            let h = GRADIENT_STEP * point[i].abs().max(1.0);
+            let mut p = point.clone();
+            p[i] += h;
+            let f1 = self.distance(&p);
+            p[i] -= 2.0 * h;
+            let f0 = self.distance(&p);
+            g[i] = (f1 - f0) / (2.0 * h);
+        }
+        g
+    }
+
+    fn normal(&self, point: &Vector) -> Vector {
+        self.gradient(point).normalize()
+    }
+
+    fn contains(&self, point: &Vector) -> bool {
+        self.distance(point) <= 0.0
+    }
+}
+
+impl SignedDistance for Sphere {
+    fn distance(&self, point: &Vector) -> Scalar {
+        (point.clone() - self.center()).norm() - self.radius()
+    }
+
+    fn gradient(&self, point: &Vector) -> Vector {
+        let d = point.clone() - self.center();
+        match d.try_normalize(0.0) {
+            Some(n) => n,
+            None => Vector::canonical_basis_element(0),
+        }
+    }
+}
+
+impl Cylinder {
+    /// Signed distances at `point` to the curved wall and to the end caps,
+    /// and the unit vectors along which they grow.
+    fn slabs(&self, point: &Vector) -> ([Scalar; 2], [Vector; 2]) {
+        let d = point.clone() - self.center.clone();
+        let h = d.dot(&self.axis);
+        let radial = d - self.axis.clone() * h;
+        let rho = radial.norm();
+        // Any direction normal to the axis will do on the axis itself.
+        let radial = radial.try_normalize(0.0).unwrap_or_else(|| {
+            let e = (0..3)
+                .map(Vector::canonical_basis_element)
+                .min_by(|a, b| a.dot(&self.axis).abs().total_cmp(&b.dot(&self.axis).abs()))
+                .unwrap();
+            (e.clone() - self.axis.clone() * e.dot(&self.axis)).normalize()
+        });
+        let along = self.axis.clone() * if h < 0.0 { -1.0 } else { 1.0 };
+        (
+            [rho - self.radius, h.abs() - 0.5 * self.length],
+            [radial, along],
+        )
+    }
+}
+
+impl SignedDistance for Cylinder {
+    fn distance(&self, point: &Vector) -> Scalar {
+        exterior_interior(self.slabs(point).0).0
+    }
+
+    fn gradient(&self, point: &Vector) -> Vector {
+        let (q, directions) = self.slabs(point);
+        let (_, d) = exterior_interior(q);
+        directions[0].clone() * d[0] + directions[1].clone() * d[1]
+    }
+}
+
+impl Cuboid {
+    /// Signed distances to the three slabs of the box at `point`.
+    fn slabs(&self, point: &Vector) -> [Scalar; 3] {
+        let mut q = [0.0; 3];
+        for (i, qi) in q.iter_mut().enumerate() {
+            *qi = (point[i] - self.center[i]).abs() - self.half_extents[i];
+        }
+        q
+    }
+}
+
+impl SignedDistance for Cuboid {
+    fn distance(&self, point: &Vector) -> Scalar {
+        exterior_interior(self.slabs(point)).0
+    }
+
+    fn gradient(&self, point: &Vector) -> Vector {
+        let (_, d) = exterior_interior(self.slabs(point));
+        let mut g = Vector::new();
+        for i in 0..3 {
+            g[i] = if point[i] < self.center[i] {
+                -d[i]
+            } else {
+                d[i]
+            };
+        }
+        g
+    }
+}
+
+/// Distance to a box-like region given the signed distances `q` to each of
+/// its slabs, and its gradient with respect to them.
+fn exterior_interior<const N: usize>(q: [Scalar; N]) -> (Scalar, [Scalar; N]) {
+    let outside = q.iter().map(|x| x.max(0.0).powi(2)).sum::<Scalar>().sqrt();
+    let mut gradient = [0.0; N];
+    if outside > 0.0 {
+        for (g, x) in gradient.iter_mut().zip(q.iter()) {
+            *g = x.max(0.0) / outside;
+        }
+        return (outside, gradient);
+    }
+    // Inside, or on the surface, the nearest slab decides.
+    let (i, inside) = q
+        .iter()
+        .cloned()
+        .enumerate()
+        .fold(
+            (0, Scalar::NEG_INFINITY),
+            |a, b| if b.1 > a.1 { b } else { a },
+        );
+    gradient[i] = 1.0;
+    (inside, gradient)
+}
+
+impl SignedDistance for Primitive {
+    fn distance(&self, point: &Vector) -> Scalar {
+        match self {
+            Primitive::Sphere(s) => s.distance(point),
+            Primitive::Cylinder(c) => c.distance(point),
+            Primitive::Cuboid(b) => b.distance(point),
+        }
+    }
+
+    fn gradient(&self, point: &Vector) -> Vector {
+        match self {
+            Primitive::Sphere(s) => s.gradient(point),
+            Primitive::Cylinder(c) => c.gradient(point),
+            Primitive::Cuboid(b) => b.gradient(point),
+        }
+    }
+}
+
+/// Node distances are exact for uniform scaling and a lower bound otherwise,
+/// which is still safe for sphere tracing and clearance checks.
+impl SignedDistance for Node {
+    fn distance(&self, point: &Vector) -> Scalar {
+        self.primitive.distance(&self.transform.invert(point)) * self.transform.min_scale()
+    }
+
+    fn gradient(&self, point: &Vector) -> Vector {
+        let g = self.primitive.gradient(&self.transform.invert(point));
+        self.transform.apply_gradient(&g) * self.transform.min_scale()
+    }
+}
+
+impl SignedDistance for Scene {
+    fn distance(&self, point: &Vector) -> Scalar {
+        self.nodes
+            .iter()
+            .map(|n| n.distance(point))
+            .fold(Scalar::INFINITY, Scalar::min)
+    }
+}
+
+pub struct Union<A, B>(pub A, pub B);
+
+pub struct Intersection<A, B>(pub A, pub B);
+
+pub struct Difference<A, B>(pub A, pub B);
+
+impl<A: SignedDistance, B: SignedDistance> SignedDistance for Union<A, B> {
+    fn distance(&self, point: &Vector) -> Scalar {
+        self.0.distance(point).min(self.1.distance(point))
+    }
+
+    fn gradient(&self, point: &Vector) -> Vector {
+        if self.0.distance(point) <= self.1.distance(point) {
+            self.0.gradient(point)
+        } else {
+            self.1.gradient(point)
+        }
+    }
+}
+
+impl<A: SignedDistance, B: SignedDistance> SignedDistance for Intersection<A, B> {
+    fn distance(&self, point: &Vector) -> Scalar {
+        self.0.distance(point).max(self.1.distance(point))
+    }
+
+    fn gradient(&self, point: &Vector) -> Vector {
+        if self.0.distance(point) >= self.1.distance(point) {
+            self.0.gradient(point)
+        } else {
+            self.1.gradient(point)
+        }
+    }
+}
+
+impl<A: SignedDistance, B: SignedDistance> SignedDistance for Difference<A, B> {
+    fn distance(&self, point: &Vector) -> Scalar {
+        self.0.distance(point).max(-self.1.distance(point))
+    }
+
+    fn gradient(&self, point: &Vector) -> Vector {
+        if self.0.distance(point) >= -self.1.distance(point) {
+            self.0.gradient(point)
+        } else {
+            -self.1.gradient(point)
+        }
+    }
+}
+
+/// Marches along the ray `origin + t direction` and returns the first `t`
+/// at which the surface is hit, if any, before `t_max`.
+pub fn sphere_trace<S: SignedDistance>(
+    sdf: &S,
+    origin: &Vector,
+    direction: &Vector,
+    t_max: Scalar,
+) -> Option<Scalar> {
+    let direction = direction.normalize();
+    let mut t = 0.0;
+    for _ in 0..TRACE_MAX_STEPS {
+        let d = sdf.distance(&(origin.clone() + direction.clone() * t));
+        if d.abs() < TRACE_EPSILON * t.max(1.0) {
+            return Some(t);
+        }
+        t += d.abs();
+        if t > t_max {
+            return None;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(x: Scalar, y: Scalar, z: Scalar) -> Vector {
+        Vector::from_slice(&[x, y, z])
+    }
+
+    #[test]
+    fn test_primitives() {
+        let sphere = Sphere::from_radius(&v(1.0, 0.0, 0.0), 2.0);
+        assert!((sphere.distance(&v(4.0, 0.0, 0.0)) - 1.0).abs() < 1e-12);
+        assert!((sphere.distance(&v(1.0, 0.0, 0.0)) + 2.0).abs() < 1e-12);
+
+        let cylinder = Cylinder::new(&v(0.0, 0.0, 0.0), &v(0.0, 0.0, 2.0), 1.0, 4.0);
+        assert!((cylinder.distance(&v(3.0, 0.0, 0.0)) - 2.0).abs() < 1e-12);
+        assert!((cylinder.distance(&v(0.0, 0.0, 5.0)) - 3.0).abs() < 1e-12);
+        assert!((cylinder.distance(&v(0.0, 0.0, 0.0)) + 1.0).abs() < 1e-12);
+
+        let cuboid = Cuboid::new(&v(0.0, 0.0, 0.0), &v(2.0, 2.0, 2.0));
+        assert!((cuboid.distance(&v(4.0, 5.0, 0.0)) - 5.0).abs() < 1e-12);
+        assert!((cuboid.distance(&v(0.5, 0.0, 0.0)) + 0.5).abs() < 1e-12);
+    }
+
+    /// Central differences of the distance, as by the default gradient
+    fn numeric<S: SignedDistance>(sdf: &S, point: &Vector) -> Vector {
+        let mut g = Vector::new();
+        for i in 0..3 {
+            let (mut a, mut b) = (point.clone(), point.clone());
+            a[i] += 1e-6;
+            b[i] -= 1e-6;
+            g[i] = (sdf.distance(&a) - sdf.distance(&b)) / 2e-6;
+        }
+        g
+    }
+
+    fn assert_close(a: &Vector, b: &Vector, tolerance: Scalar) {
+        assert!(
+            (a.clone() - b.clone()).norm() < tolerance,
+            "{:?} {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn test_gradient() {
+        let cuboid = Cuboid::new(&v(0.0, 0.0, 0.0), &v(2.0, 2.0, 2.0));
+        assert_close(
+            &cuboid.gradient(&v(3.0, 0.2, -0.1)),
+            &v(1.0, 0.0, 0.0),
+            1e-12,
+        );
+        assert_close(
+            &cuboid.gradient(&v(0.2, -0.7, 0.1)),
+            &v(0.0, -1.0, 0.0),
+            1e-12,
+        );
+        let cylinder = Cylinder::new(&v(1.0, 0.0, 0.0), &v(1.0, 1.0, 0.0), 1.0, 4.0);
+        for p in [
+            v(3.0, 0.5, 0.5),
+            v(5.0, 4.0, 0.0),
+            v(1.2, 0.1, 0.3),
+            v(-0.3, -1.1, 0.2),
+            v(-3.0, -3.5, 1.5),
+        ] {
+            assert_close(&cuboid.gradient(&p), &numeric(&cuboid, &p), 1e-6);
+            assert_close(&cylinder.gradient(&p), &numeric(&cylinder, &p), 1e-6);
+        }
+        // On the axis every radial direction is normal to it.
+        let g = cylinder.gradient(&v(1.0, 0.0, 0.0));
+        assert!((g.norm() - 1.0).abs() < 1e-12 && g.dot(&cylinder.axis).abs() < 1e-12);
+
+        let mut node = Node::new("box", Primitive::Cuboid(cuboid), "steel");
+        node.transform.translation = v(1.0, -2.0, 0.5);
+        node.transform.rotation = [0.0, 0.0, (0.3 as Scalar).sin(), (0.3 as Scalar).cos()];
+        node.transform.scale = v(2.0, 2.0, 2.0);
+        for p in [v(4.0, 1.0, -1.0), v(1.3, -1.6, 0.9)] {
+            assert_close(&node.gradient(&p), &numeric(&node, &p), 1e-6);
+            assert!((node.gradient(&p).norm() - 1.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_csg() {
+        let a = Sphere::from_radius(&v(0.0, 0.0, 0.0), 2.0);
+        let b = Sphere::from_radius(&v(2.0, 0.0, 0.0), 1.0);
+        let shell = Difference(a, b);
+        assert!(!shell.contains(&v(1.5, 0.0, 0.0)));
+        assert!(shell.contains(&v(-1.5, 0.0, 0.0)));
+        let g = shell.gradient(&v(1.2, 0.0, 0.0));
+        assert!((g[0] - 1.0).abs() < 1e-12);
+
+        let a = Sphere::from_radius(&v(0.0, 0.0, 0.0), 2.0);
+        let b = Cuboid::new(&v(2.0, 0.0, 0.0), &v(2.0, 2.0, 2.0));
+        let union = Union(a, b);
+        assert!(union.contains(&v(-1.5, 0.0, 0.0)) && union.contains(&v(2.8, 0.9, 0.0)));
+        assert!(!union.contains(&v(0.0, 0.0, 2.5)));
+        assert!((union.distance(&v(4.0, 0.0, 0.0)) - 1.0).abs() < 1e-12);
+        assert_close(
+            &union.gradient(&v(-3.0, 0.0, 0.0)),
+            &v(-1.0, 0.0, 0.0),
+            1e-12,
+        );
+        assert_close(&union.gradient(&v(4.0, 0.0, 0.0)), &v(1.0, 0.0, 0.0), 1e-12);
+
+        let a = Sphere::from_radius(&v(0.0, 0.0, 0.0), 2.0);
+        let b = Cuboid::new(&v(2.0, 0.0, 0.0), &v(2.0, 2.0, 2.0));
+        let lens = Intersection(a, b);
+        assert!(lens.contains(&v(1.5, 0.0, 0.0)));
+        assert!(!lens.contains(&v(-1.5, 0.0, 0.0)) && !lens.contains(&v(2.8, 0.0, 0.0)));
+        assert!((lens.distance(&v(0.5, 0.0, 0.0)) - 0.5).abs() < 1e-12);
+        assert_close(&lens.gradient(&v(0.5, 0.0, 0.0)), &v(-1.0, 0.0, 0.0), 1e-12);
+        assert_close(&lens.gradient(&v(2.5, 0.0, 0.0)), &v(1.0, 0.0, 0.0), 1e-12);
+    }
+
+    #[test]
+    fn test_sphere_trace() {
+        let sphere = Sphere::from_radius(&v(0.0, 0.0, 0.0), 1.0);
+        let t = sphere_trace(&sphere, &v(-5.0, 0.0, 0.0), &v(1.0, 0.0, 0.0), 100.0);
+        assert!((t.unwrap() - 4.0).abs() < 1e-6);
+        assert!(sphere_trace(&sphere, &v(-5.0, 2.0, 0.0), &v(1.0, 0.0, 0.0), 100.0).is_none());
+    }
+}
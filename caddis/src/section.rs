@@ -0,0 +1,336 @@
+//! Plane cross-sections of beams and frame members and their geometric
+//! properties.
+//!
+//! A section is a set of closed polygonal contours in the plane. Outer
+//! boundaries run counter-clockwise and holes clockwise, so that holes
+//! subtract from every area integral.
+
+use std::f64::consts::PI;
+
+type Scalar = f64;
+
+pub type Point2 = [Scalar; 2];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polygon {
+    pub vertices: Vec<Point2>,
+}
+
+impl Polygon {
+    pub fn new(vertices: Vec<Point2>) -> Self {
+        Polygon { vertices }
+    }
+
+    /// Axis-aligned rectangle with corners `min` and `max`, counter-clockwise.
+    pub fn rectangle(min: Point2, max: Point2) -> Self {
+        Polygon::new(vec![
+            [min[0], min[1]],
+            [max[0], min[1]],
+            [max[0], max[1]],
+            [min[0], max[1]],
+        ])
+    }
+
+    /// Regular `n`-gon inscribed in the circle of given centre and radius.
+    pub fn regular(center: Point2, radius: Scalar, n: usize) -> Self {
+        Polygon::new(
+            (0..n)
+                .map(|i| {
+                    let t = 2.0 * PI * i as Scalar / n as Scalar;
+                    [center[0] + radius * t.cos(), center[1] + radius * t.sin()]
+                })
+                .collect(),
+        )
+    }
+
+    pub fn reversed(&self) -> Self {
+        let mut vertices = self.vertices.clone();
+        vertices.reverse();
+        Polygon::new(vertices)
+    }
+
+    /// Signed area, positive for counter-clockwise contours.
+    pub fn signed_area(&self) -> Scalar {
+        self.edges().map(|(p, q)| cross(p, q)).sum::<Scalar>() / 2.0
+    }
+
+    fn edges(&self) -> impl Iterator<Item = (&Point2, &Point2)> {
+        let n = self.vertices.len();
+        (0..n).map(move |i| (&self.vertices[i], &self.vertices[(i + 1) % n]))
+    }
+}
+
+fn cross(p: &Point2, q: &Point2) -> Scalar {
+    p[0] * q[1] - q[0] * p[1]
+}
+
+/// Area integrals of a section about the coordinate origin.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Moments {
+    area: Scalar,
+    first_x: Scalar,
+    first_y: Scalar,
+    xx: Scalar,
+    yy: Scalar,
+    xy: Scalar,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SectionProperties {
+    pub area: Scalar,
+    pub centroid: Point2,
+    /// Second moment of area about the centroidal x axis, ∫ y² dA
+    pub ix: Scalar,
+    /// Second moment of area about the centroidal y axis, ∫ x² dA
+    pub iy: Scalar,
+    /// Product of inertia about the centroid, ∫ x y dA
+    pub ixy: Scalar,
+    /// Major and minor principal second moments
+    pub i1: Scalar,
+    pub i2: Scalar,
+    /// Angle from the x axis to the major principal axis
+    pub theta: Scalar,
+    /// Elastic section moduli, I divided by the distance to the extreme fibre
+    pub sx: Scalar,
+    pub sy: Scalar,
+}
+
+impl SectionProperties {
+    /// Polar second moment about the centroid.
+    pub fn polar(&self) -> Scalar {
+        self.ix + self.iy
+    }
+
+    pub fn radius_of_gyration_x(&self) -> Scalar {
+        (self.ix / self.area).sqrt()
+    }
+
+    pub fn radius_of_gyration_y(&self) -> Scalar {
+        (self.iy / self.area).sqrt()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Section {
+    pub contours: Vec<Polygon>,
+}
+
+impl Section {
+    pub fn new(contours: Vec<Polygon>) -> Self {
+        Section { contours }
+    }
+
+    /// Solid section bounded by `outer`, whatever its orientation.
+    pub fn solid(outer: Polygon) -> Self {
+        Section::new(vec![orient(outer, true)])
+    }
+
+    /// Adds a hole, whatever its orientation.
+    pub fn with_hole(mut self, hole: Polygon) -> Self {
+        self.contours.push(orient(hole, false));
+        self
+    }
+
+    pub fn rectangle(width: Scalar, height: Scalar) -> Self {
+        let (w, h) = (width / 2.0, height / 2.0);
+        Section::solid(Polygon::rectangle([-w, -h], [w, h]))
+    }
+
+    /// Rectangular hollow section of uniform wall thickness.
+    pub fn rectangular_tube(width: Scalar, height: Scalar, thickness: Scalar) -> Self {
+        let (w, h) = (width / 2.0, height / 2.0);
+        let t = thickness;
+        Section::rectangle(width, height)
+            .with_hole(Polygon::rectangle([t - w, t - h], [w - t, h - t]))
+    }
+
+    /// Circle approximated by a regular polygon of `segments` sides.
+    pub fn circle(diameter: Scalar, segments: usize) -> Self {
+        Section::solid(Polygon::regular([0.0, 0.0], diameter / 2.0, segments))
+    }
+
+    /// Circular hollow section approximated by regular polygons.
+    pub fn circular_tube(diameter: Scalar, thickness: Scalar, segments: usize) -> Self {
+        Section::circle(diameter, segments).with_hole(Polygon::regular(
+            [0.0, 0.0],
+            diameter / 2.0 - thickness,
+            segments,
+        ))
+    }
+
+    /// Doubly symmetric I-beam centred on the origin with its web along the
+    /// y axis.
+    pub fn i_beam(
+        depth: Scalar,
+        flange_width: Scalar,
+        flange_thickness: Scalar,
+        web_thickness: Scalar,
+    ) -> Self {
+        let (h, b) = (depth / 2.0, flange_width / 2.0);
+        let (tf, tw) = (flange_thickness, web_thickness / 2.0);
+        Section::solid(Polygon::new(vec![
+            [-b, -h],
+            [b, -h],
+            [b, tf - h],
+            [tw, tf - h],
+            [tw, h - tf],
+            [b, h - tf],
+            [b, h],
+            [-b, h],
+            [-b, h - tf],
+            [-tw, h - tf],
+            [-tw, tf - h],
+            [-b, tf - h],
+        ]))
+    }
+
+    /// Channel with its web along the y axis on the left and flanges
+    /// pointing in +x, symmetric about the x axis.
+    pub fn channel(
+        depth: Scalar,
+        flange_width: Scalar,
+        flange_thickness: Scalar,
+        web_thickness: Scalar,
+    ) -> Self {
+        let h = depth / 2.0;
+        let (b, tf, tw) = (flange_width, flange_thickness, web_thickness);
+        Section::solid(Polygon::new(vec![
+            [0.0, -h],
+            [b, -h],
+            [b, tf - h],
+            [tw, tf - h],
+            [tw, h - tf],
+            [b, h - tf],
+            [b, h],
+            [0.0, h],
+        ]))
+    }
+
+    fn moments(&self) -> Moments {
+        let mut m = Moments::default();
+        for contour in &self.contours {
+            for (p, q) in contour.edges() {
+                let c = cross(p, q);
+                m.area += c / 2.0;
+                m.first_x += (p[0] + q[0]) * c / 6.0;
+                m.first_y += (p[1] + q[1]) * c / 6.0;
+                m.xx += (p[1] * p[1] + p[1] * q[1] + q[1] * q[1]) * c / 12.0;
+                m.yy += (p[0] * p[0] + p[0] * q[0] + q[0] * q[0]) * c / 12.0;
+                m.xy +=
+                    (p[0] * q[1] + 2.0 * p[0] * p[1] + 2.0 * q[0] * q[1] + q[0] * p[1]) * c / 24.0;
+            }
+        }
+        m
+    }
+
+    pub fn area(&self) -> Scalar {
+        self.moments().area
+    }
+
+    pub fn centroid(&self) -> Point2 {
+        let m = self.moments();
+        [m.first_x / m.area, m.first_y / m.area]
+    }
+
+    pub fn properties(&self) -> SectionProperties {
+        let m = self.moments();
+        let c = [m.first_x / m.area, m.first_y / m.area];
+        let ix = m.xx - m.area * c[1] * c[1];
+        let iy = m.yy - m.area * c[0] * c[0];
+        let ixy = m.xy - m.area * c[0] * c[1];
+        let mean = (ix + iy) / 2.0;
+        let radius = (((ix - iy) / 2.0).powi(2) + ixy * ixy).sqrt();
+        let (mut x_max, mut y_max): (Scalar, Scalar) = (0.0, 0.0);
+        for contour in &self.contours {
+            for p in &contour.vertices {
+                x_max = x_max.max((p[0] - c[0]).abs());
+                y_max = y_max.max((p[1] - c[1]).abs());
+            }
+        }
+        SectionProperties {
+            area: m.area,
+            centroid: c,
+            ix,
+            iy,
+            ixy,
+            i1: mean + radius,
+            i2: mean - radius,
+            theta: 0.5 * (-2.0 * ixy).atan2(ix - iy),
+            sx: ix / y_max,
+            sy: iy / x_max,
+        }
+    }
+}
+
+fn orient(polygon: Polygon, counter_clockwise: bool) -> Polygon {
+    if (polygon.signed_area() > 0.0) == counter_clockwise {
+        polygon
+    } else {
+        polygon.reversed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Scalar, b: Scalar) -> bool {
+        (a - b).abs() <= 1e-9 * a.abs().max(b.abs()).max(1.0)
+    }
+
+    #[test]
+    fn test_rectangle() {
+        let p = Section::rectangle(2.0, 6.0).properties();
+        assert!(close(p.area, 12.0));
+        assert!(close(p.ix, 2.0 * 6f64.powi(3) / 12.0));
+        assert!(close(p.iy, 6.0 * 2f64.powi(3) / 12.0));
+        assert!(close(p.sx, 2.0 * 36.0 / 6.0));
+        assert!(close(p.i1, p.ix) && close(p.i2, p.iy));
+    }
+
+    #[test]
+    fn test_i_beam() {
+        let (d, b, tf, tw) = (0.3, 0.15, 0.01, 0.006);
+        let p = Section::i_beam(d, b, tf, tw).properties();
+        let hw = d - 2.0 * tf;
+        let ix = (b * d.powi(3) - (b - tw) * hw.powi(3)) / 12.0;
+        assert!(close(p.area, 2.0 * b * tf + hw * tw));
+        assert!(close(p.ix, ix));
+        assert!(close(p.sx, ix / (d / 2.0)));
+        assert!(p.centroid[0].abs() < 1e-12 && p.centroid[1].abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_tube_and_rotation() {
+        let p = Section::rectangular_tube(0.1, 0.2, 0.01).properties();
+        assert!(close(p.area, 0.1 * 0.2 - 0.08 * 0.18));
+        assert!(close(
+            p.ix,
+            (0.1 * 0.2f64.powi(3) - 0.08 * 0.18f64.powi(3)) / 12.0
+        ));
+
+        // A rectangle rotated by 30 degrees keeps its principal moments.
+        let (s, c) = (PI / 6.0).sin_cos();
+        let r = Section::rectangle(2.0, 6.0);
+        let rotated = Section::solid(Polygon::new(
+            r.contours[0]
+                .vertices
+                .iter()
+                .map(|v| [c * v[0] - s * v[1], s * v[0] + c * v[1]])
+                .collect(),
+        ));
+        let p = rotated.properties();
+        assert!(close(p.i1, 36.0) && close(p.i2, 4.0));
+        assert!(close(p.theta, PI / 6.0));
+    }
+
+    #[test]
+    fn test_channel_centroid() {
+        let p = Section::channel(0.2, 0.075, 0.01, 0.01).properties();
+        let a_web = 0.2 * 0.01;
+        let a_flange = 0.065 * 0.01;
+        let x = (a_web * 0.005 + 2.0 * a_flange * (0.01 + 0.0325)) / (a_web + 2.0 * a_flange);
+        assert!(close(p.centroid[0], x));
+        assert!(p.centroid[1].abs() < 1e-12);
+    }
+}
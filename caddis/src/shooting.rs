@@ -0,0 +1,430 @@
+//! Two-sided shooting for radiative stars, a cross-check of the Henyey
+//! method.
+//!
+//! The structure equations of `henyey`,
+//!
+//! ```text
+//! dr/dm = 1/(4 pi r^2 rho)
+//! dP/dm = -G m/(4 pi r^4)
+//! dT/dm = -G m T nabla_rad/(4 pi r^4 P)
+//! dl/dm = epsilon
+//! ```
+//!
+//! are integrated outwards from the central expansions and inwards from a
+//! grey photosphere to a fitting point, and Newton's method on the central
+//! pressure and temperature, the radius and, with an energy source, the
+//! luminosity makes the two sides meet. Energy is carried by radiation
+//! throughout.
+
+use std::f64::consts::PI;
+use std::fmt;
+
+use rgsl::linear_algebra::{LU_decomp, LU_solve};
+use rgsl::types::matrix::MatrixF64;
+use rgsl::types::permutation::Permutation;
+use rgsl::types::vector::VectorF64;
+
+use crate::eos::{Composition, EquationOfState, IdealGas};
+use crate::nuclear::EnergyGeneration;
+use crate::ode::DormandPrince;
+use crate::opacity::{self, Opacity};
+use crate::units::{
+    MOLAR_GAS_CONSTANT, MOLAR_MASS_CONSTANT, NEWTONIAN_CONSTANT_OF_GRAVITATION,
+    RADIATION_DENSITY_CONSTANT, SPEED_OF_LIGHT, STEFAN_BOLTZMANN_CONSTANT,
+};
+
+type Scalar = f64;
+
+/// Opacity of the first guess of the photospheric pressure (m^2 kg^-1)
+const PHOTOSPHERE_OPACITY: Scalar = 0.034;
+/// Guillotine factor of the default bound-free opacity, as in `henyey`
+const GUILLOTINE: Scalar = 10.0;
+
+/// Fraction of the mass where the outward integration starts
+const CENTRE_MASS: Scalar = 1e-6;
+/// Relative error allowed per integration step
+const INTEGRATION_TOLERANCE: Scalar = 1e-11;
+/// Relative change of the free values for the finite-difference Jacobian
+const DIFFERENCE: Scalar = 1e-6;
+/// Largest change of a logarithmic free value in one Newton step
+const MAX_CORRECTION: Scalar = 0.5;
+const TOLERANCE: Scalar = 1e-8;
+const MAX_ITERATIONS: usize = 50;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShootingError {
+    /// An integration left the physical domain or took too many steps
+    Integration,
+    /// The linearised matching conditions could not be solved
+    Singular,
+    /// No convergence within the iteration limit; holds the last largest
+    /// mismatch
+    NotConverged(Scalar),
+}
+
+impl fmt::Display for ShootingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShootingError::Integration => write!(f, "integration failed"),
+            ShootingError::Singular => write!(f, "singular matching conditions"),
+            ShootingError::NotConverged(d) => {
+                write!(f, "no convergence, last relative mismatch {:e}", d)
+            }
+        }
+    }
+}
+
+/// Boundary values of a converged star.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Boundary {
+    pub central_pressure: Scalar,
+    pub central_temperature: Scalar,
+    pub radius: Scalar,
+    pub luminosity: Scalar,
+}
+
+pub struct Shooting {
+    /// Total mass
+    mass: Scalar,
+    /// Fraction of the mass at the fitting point
+    fitting: Scalar,
+    eos: Box<dyn EquationOfState>,
+    kappa: Box<dyn Opacity>,
+    /// Energy generation rate, uniform in mass if none
+    energy: Option<Box<dyn EnergyGeneration>>,
+    /// ln P_c, ln T_c, ln R and ln L
+    x: [Scalar; 4],
+}
+
+impl Shooting {
+    /// Star of mass `m` with the defaults of `Henyey`, starting from the
+    /// central pressure and temperature of a homogeneous sphere of radius
+    /// `r`.
+    pub fn new(m: Scalar, r: Scalar) -> Self {
+        let mut shooting = Shooting {
+            mass: m,
+            fitting: 0.5,
+            eos: Box::new(IdealGas::new(0.5)),
+            kappa: Box::new(opacity::analytic(&Composition::solar(), GUILLOTINE)),
+            energy: None,
+            x: [0.0; 4],
+        };
+        let p_c = 3.0 * NEWTONIAN_CONSTANT_OF_GRAVITATION * m * m / (8.0 * PI * r.powi(4));
+        let rho = 3.0 * m / (4.0 * PI * r.powi(3));
+        let mu = shooting.eos.mean_molecular_weight();
+        let t_c = p_c * mu * MOLAR_MASS_CONSTANT / (MOLAR_GAS_CONSTANT * rho);
+        shooting.x = [p_c.ln(), t_c.ln(), r.ln(), 0.0];
+        shooting
+    }
+
+    /// Sets the luminosity, released uniformly in mass. With an energy
+    /// source it is only the starting guess.
+    pub fn with_luminosity(mut self, l: Scalar) -> Self {
+        self.x[3] = l.ln();
+        self
+    }
+
+    /// Replaces the starting guesses of the central pressure and
+    /// temperature.
+    pub fn with_centre(mut self, pressure: Scalar, temperature: Scalar) -> Self {
+        self.x[0] = pressure.ln();
+        self.x[1] = temperature.ln();
+        self
+    }
+
+    pub fn with_equation_of_state<E: EquationOfState + 'static>(mut self, eos: E) -> Self {
+        self.eos = Box::new(eos);
+        self
+    }
+
+    pub fn with_opacity<O: Opacity + 'static>(mut self, kappa: O) -> Self {
+        self.kappa = Box::new(kappa);
+        self
+    }
+
+    /// Releases energy at the rate of `energy`, making the luminosity free.
+    pub fn with_energy_generation<E: EnergyGeneration + 'static>(mut self, energy: E) -> Self {
+        self.energy = Some(Box::new(energy));
+        self
+    }
+
+    /// Moves the fitting point to the mass fraction `fraction`, 1/2 by
+    /// default.
+    pub fn with_fitting_point(mut self, fraction: Scalar) -> Self {
+        assert!(
+            fraction > CENTRE_MASS && fraction < 1.0,
+            "fitting point must lie inside the star"
+        );
+        self.fitting = fraction;
+        self
+    }
+
+    /// Boundary values for the current free values.
+    pub fn boundary(&self) -> Boundary {
+        Boundary {
+            central_pressure: self.x[0].exp(),
+            central_temperature: self.x[1].exp(),
+            radius: self.x[2].exp(),
+            luminosity: self.x[3].exp(),
+        }
+    }
+
+    fn density(&self, p: Scalar, t: Scalar) -> Scalar {
+        self.eos.density(p, t)[0]
+    }
+
+    /// Energy generation rate at `p` and `t`.
+    fn epsilon(&self, p: Scalar, t: Scalar) -> Scalar {
+        match &self.energy {
+            Some(energy) => energy.energy(self.density(p, t), t)[0],
+            None => self.x[3].exp() / self.mass,
+        }
+    }
+
+    /// Derivatives of r, P, T and l with respect to m.
+    fn derivatives(&self, m: Scalar, [r, p, t, l]: [Scalar; 4]) -> [Scalar; 4] {
+        let g = NEWTONIAN_CONSTANT_OF_GRAVITATION;
+        let (a, c) = (RADIATION_DENSITY_CONSTANT, SPEED_OF_LIGHT);
+        let rho = self.density(p, t);
+        let kappa = self.kappa.opacity(rho, t)[0];
+        let nabla = 3.0 * kappa * l * p / (16.0 * PI * a * c * g * m * t.powi(4));
+        let dp = -g * m / (4.0 * PI * r.powi(4));
+        [
+            1.0 / (4.0 * PI * r * r * rho),
+            dp,
+            dp * t * nabla / p,
+            self.epsilon(p, t),
+        ]
+    }
+
+    /// Integrates from `m0`, where the variables are `y`, to `m1` with
+    /// adaptive Dormand-Prince steps, or None if the step size collapses.
+    /// Steps that would make P or T negative are retried shorter. The mass
+    /// is counted from `m0`, so the thin surface layers resolve.
+    fn integrate(&self, m0: Scalar, y: [Scalar; 4], m1: Scalar) -> Option<[Scalar; 4]> {
+        let f = |s: Scalar, y: &[Scalar; 4]| {
+            if y[1] <= 0.0 || y[2] <= 0.0 {
+                [Scalar::NAN; 4]
+            } else {
+                self.derivatives(m0 + s, *y)
+            }
+        };
+        let solution = DormandPrince::new(INTEGRATION_TOLERANCE).solve(f, 0.0, y, m1 - m0);
+        solution.ok().map(|solution| *solution.last())
+    }
+
+    /// r, P, T and l at the fraction `CENTRE_MASS` of the mass, from the
+    /// central expansions.
+    fn centre(&self) -> [Scalar; 5] {
+        let g = NEWTONIAN_CONSTANT_OF_GRAVITATION;
+        let (a, c) = (RADIATION_DENSITY_CONSTANT, SPEED_OF_LIGHT);
+        let (p_c, t_c) = (self.x[0].exp(), self.x[1].exp());
+        let m = CENTRE_MASS * self.mass;
+        let rho = self.density(p_c, t_c);
+        let kappa = self.kappa.opacity(rho, t_c)[0];
+        let epsilon = self.epsilon(p_c, t_c);
+        let m_23 = m.powf(2.0 / 3.0);
+        [
+            m,
+            (3.0 * m / (4.0 * PI * rho)).cbrt(),
+            p_c - 3.0 * g / (8.0 * PI) * (4.0 * PI * rho / 3.0).powf(4.0 / 3.0) * m_23,
+            (t_c.powi(4)
+                - (3.0 / (4.0 * PI)).powf(2.0 / 3.0)
+                    * kappa
+                    * epsilon
+                    * rho.powf(4.0 / 3.0)
+                    * m_23
+                    / (2.0 * a * c))
+                .powf(0.25),
+            epsilon * m,
+        ]
+    }
+
+    /// r, P, T and l at a grey photosphere, T = T_eff and
+    /// P = 2 G M/(3 R^2 kappa), solved for P by Newton's method.
+    fn surface(&self) -> Option<[Scalar; 4]> {
+        let g = NEWTONIAN_CONSTANT_OF_GRAVITATION;
+        let (r, l) = (self.x[2].exp(), self.x[3].exp());
+        let t = (l / (4.0 * PI * STEFAN_BOLTZMANN_CONSTANT * r * r)).powf(0.25);
+        let target = (2.0 * g * self.mass / (3.0 * r * r)).ln();
+        let mut ln_p = target - PHOTOSPHERE_OPACITY.ln();
+        for _ in 0..MAX_ITERATIONS {
+            let p = ln_p.exp();
+            let [rho, rho_p, _] = self.eos.density(p, t);
+            let [kappa, kappa_rho, _] = self.kappa.opacity(rho, t);
+            let f = ln_p + kappa.ln() - target;
+            let slope = 1.0 + p * kappa_rho * rho_p / kappa;
+            let step = (f / slope).clamp(-MAX_CORRECTION, MAX_CORRECTION);
+            ln_p -= step;
+            if step.abs() < 1e-12 {
+                return Some([r, ln_p.exp(), t, l]);
+            }
+        }
+        None
+    }
+
+    /// r, P, T and l at the fitting point from the centre and from the
+    /// surface.
+    fn sides(&self) -> Option<([Scalar; 4], [Scalar; 4])> {
+        let fitting = self.fitting * self.mass;
+        let [m, r, p, t, l] = self.centre();
+        let outward = self.integrate(m, [r, p, t, l], fitting)?;
+        let inward = self.integrate(self.mass, self.surface()?, fitting)?;
+        Some((outward, inward))
+    }
+
+    /// Relative mismatch of r, P, T and l at the fitting point; l only
+    /// with an energy source, as it matches by construction otherwise.
+    fn mismatch(&self) -> Option<Vec<Scalar>> {
+        let (outward, inward) = self.sides()?;
+        let n = self.free();
+        Some((0..n).map(|v| outward[v] / inward[v] - 1.0).collect())
+    }
+
+    /// Number of free values.
+    fn free(&self) -> usize {
+        if self.energy.is_some() {
+            4
+        } else {
+            3
+        }
+    }
+
+    /// Matches the two sides by Newton's method on the logarithms of the
+    /// free values, with a finite-difference Jacobian.
+    pub fn solve(&mut self) -> Result<Boundary, ShootingError> {
+        let n = self.free();
+        let mut mismatch = Scalar::INFINITY;
+        for _ in 0..MAX_ITERATIONS {
+            let d = self.mismatch().ok_or(ShootingError::Integration)?;
+            mismatch = d.iter().fold(0.0, |a: Scalar, d| a.max(d.abs()));
+            if mismatch < TOLERANCE {
+                return Ok(self.boundary());
+            }
+            let mut jacobian = MatrixF64::new(n, n).unwrap();
+            for j in 0..n {
+                let x = self.x[j];
+                self.x[j] = x + DIFFERENCE;
+                let shifted = self.mismatch();
+                self.x[j] = x;
+                let shifted = shifted.ok_or(ShootingError::Integration)?;
+                for i in 0..n {
+                    jacobian.set(i, j, (shifted[i] - d[i]) / DIFFERENCE);
+                }
+            }
+            let mut p = Permutation::new(n).unwrap();
+            let mut signum = 0;
+            let mut b = VectorF64::new(n).unwrap();
+            for (i, d) in d.iter().enumerate() {
+                b.set(i, -d);
+            }
+            let mut dx = VectorF64::new(n).unwrap();
+            if LU_decomp(&mut jacobian, &mut p, &mut signum).is_err()
+                || LU_solve(&jacobian, &p, &b, &mut dx).is_err()
+            {
+                return Err(ShootingError::Singular);
+            }
+            let largest = (0..n).fold(0.0, |a: Scalar, i| a.max(dx.get(i).abs()));
+            let damping = (MAX_CORRECTION / largest).min(1.0);
+            for i in 0..n {
+                self.x[i] += damping * dx.get(i);
+            }
+        }
+        Err(ShootingError::NotConverged(mismatch))
+    }
+
+    /// r, P, T and l at mass coordinate `m`, integrated from the nearer of
+    /// the centre and the surface across the fitting point.
+    pub fn profile(&self, m: Scalar) -> Option<[Scalar; 4]> {
+        if m <= self.fitting * self.mass {
+            let [m0, r, p, t, l] = self.centre();
+            if m <= m0 {
+                return Some([r, p, t, l]);
+            }
+            self.integrate(m0, [r, p, t, l], m)
+        } else {
+            self.integrate(self.mass, self.surface()?, m)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::henyey::{Henyey, Surface};
+
+    use crate::units::{SOLAR_LUMINOSITY, SOLAR_MASS, SOLAR_RADIUS};
+
+    #[test]
+    fn test_henyey() {
+        let models: Vec<_> = [200usize, 400, 800]
+            .iter()
+            .map(|&k| {
+                Henyey::new(k, SOLAR_MASS, SOLAR_RADIUS)
+                    .with_luminosity(SOLAR_LUMINOSITY)
+                    .with_surface(Surface::Grey)
+                    .solve()
+                    .unwrap()
+            })
+            .collect();
+        let finest = &models[2];
+        let mut shooting = Shooting::new(SOLAR_MASS, *finest.r.last().unwrap())
+            .with_luminosity(SOLAR_LUMINOSITY)
+            .with_centre(finest.P[0], finest.T[0]);
+        let boundary = shooting.solve().unwrap();
+        // The Henyey central values approach the shooting ones as the grid
+        // is refined.
+        let errors: Vec<_> = models
+            .iter()
+            .map(|model| {
+                [
+                    (model.P[0] / boundary.central_pressure - 1.0).abs(),
+                    (model.T[0] / boundary.central_temperature - 1.0).abs(),
+                ]
+            })
+            .collect();
+        for pair in errors.windows(2) {
+            assert!(pair[1][0] < 0.75 * pair[0][0] && pair[1][1] < 0.75 * pair[0][1]);
+        }
+        assert!(errors[2][0] < 0.02 && errors[2][1] < 0.005);
+        // The two sides meet with matching profiles.
+        let m = 0.5 * SOLAR_MASS;
+        let below = shooting.profile(m * (1.0 - 1e-12)).unwrap();
+        let above = shooting.profile(m * (1.0 + 1e-12)).unwrap();
+        for v in 0..4 {
+            assert!((below[v] / above[v] - 1.0).abs() < 1e-7);
+        }
+        assert_eq!(shooting.profile(SOLAR_MASS).unwrap()[0], boundary.radius);
+    }
+
+    /// Energy generation rate proportional to the temperature, mild
+    /// enough to keep the core radiative
+    struct Linear(Scalar);
+
+    impl EnergyGeneration for Linear {
+        fn energy(&self, _rho: Scalar, temperature: Scalar) -> [Scalar; 3] {
+            [self.0 * temperature, 0.0, self.0]
+        }
+    }
+
+    #[test]
+    fn test_energy_generation() {
+        let mut shooting = Shooting::new(SOLAR_MASS, 6.5e7)
+            .with_luminosity(SOLAR_LUMINOSITY)
+            .with_centre(2.8e20, 1.17e8);
+        let t_c = shooting.solve().unwrap().central_temperature;
+        // The central rate of the uniform source
+        let rate = SOLAR_LUMINOSITY / (SOLAR_MASS * t_c);
+        let mut shooting = shooting.with_energy_generation(Linear(rate));
+        let boundary = shooting.solve().unwrap();
+        // The luminosity is the energy released inside the star.
+        let n = 1000;
+        let mut l = shooting.centre()[4];
+        for j in 1..n {
+            let m = SOLAR_MASS * j as Scalar / n as Scalar;
+            let [_, p, t, _] = shooting.profile(m).unwrap();
+            l += SOLAR_MASS / n as Scalar * shooting.epsilon(p, t);
+        }
+        assert!((l / boundary.luminosity - 1.0).abs() < 2e-3);
+    }
+}
@@ -0,0 +1,827 @@
+//! Symbolic expressions, their derivatives and LaTeX.
+//!
+//! An `Expr` is a tree of constants, named constants such as pi or the gas
+//! constant, variables, sums, products, powers and elementary functions.
+//! Differences and quotients are sums and products with factors -1 and
+//! powers -1, so that
+//!
+//! ```text
+//! 1/(4 pi r^2 rho) = 4^-1 pi^-1 r^-2 rho^-1
+//! ```
+//!
+//! once simplified, and rendered as `\frac{1}{4 \pi r^{2} \rho}`. Evaluation
+//! binds variables to `QuantityValue`s, checks dimensions and propagates
+//! uncertainties to first order through the symbolic derivatives.
+//!
+//! `dr_dm` and `ideal_gas_density` are the equations of doc/stars.tex, and
+//! the tests check the document against their rendering. The Henyey solver
+//! builds its Jacobian blocks from forward-mode dual numbers, see `dual`;
+//! its tests check the blocks of dr/dm against the derivatives of these.
+
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use num::pow::Pow;
+use num::rational::Ratio;
+
+use crate::units::{Dimension, DimensionError, QuantityValue, MOLAR_GAS_CONSTANT};
+
+type Scalar = f64;
+
+/// Names of variables rendered as Greek letters.
+const GREEK: [&str; 32] = [
+    "alpha", "beta", "gamma", "delta", "epsilon", "zeta", "eta", "theta", "iota", "kappa",
+    "lambda", "mu", "nu", "xi", "pi", "rho", "sigma", "tau", "phi", "chi", "psi", "omega", "nabla",
+    "Gamma", "Delta", "Theta", "Lambda", "Xi", "Pi", "Sigma", "Phi", "Omega",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Exp,
+    Ln,
+    Sin,
+    Cos,
+    Tan,
+}
+
+impl Function {
+    fn apply(self, x: Scalar) -> Scalar {
+        match self {
+            Function::Exp => x.exp(),
+            Function::Ln => x.ln(),
+            Function::Sin => x.sin(),
+            Function::Cos => x.cos(),
+            Function::Tan => x.tan(),
+        }
+    }
+
+    fn latex(self) -> &'static str {
+        match self {
+            Function::Exp => "\\exp",
+            Function::Ln => "\\ln",
+            Function::Sin => "\\sin",
+            Function::Cos => "\\cos",
+            Function::Tan => "\\tan",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Constant(Scalar),
+    /// Constant rendered by its LaTeX symbol
+    Named(String, QuantityValue),
+    Variable(String),
+    Sum(Vec<Expr>),
+    Product(Vec<Expr>),
+    Power(Box<Expr>, Box<Expr>),
+    Apply(Function, Box<Expr>),
+}
+
+/// Why an expression could not be evaluated.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvaluationError {
+    Unbound(String),
+    Dimension(DimensionError),
+}
+
+impl fmt::Display for EvaluationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvaluationError::Unbound(name) => write!(f, "no value bound to {}", name),
+            EvaluationError::Dimension(e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<DimensionError> for EvaluationError {
+    fn from(e: DimensionError) -> Self {
+        EvaluationError::Dimension(e)
+    }
+}
+
+fn dimensionless() -> Dimension {
+    QuantityValue::default().dimension
+}
+
+/// `found` as dimensionless, or an error.
+fn check_dimensionless(found: Dimension) -> Result<(), DimensionError> {
+    if found != dimensionless() {
+        return Err(DimensionError {
+            expected: dimensionless(),
+            found,
+        });
+    }
+    Ok(())
+}
+
+impl Expr {
+    pub fn var(name: &str) -> Self {
+        Expr::Variable(name.to_string())
+    }
+
+    pub fn constant(c: Scalar) -> Self {
+        Expr::Constant(c)
+    }
+
+    /// Named constant of LaTeX symbol `symbol`.
+    pub fn named(symbol: &str, value: QuantityValue) -> Self {
+        Expr::Named(symbol.to_string(), value)
+    }
+
+    pub fn pi() -> Self {
+        Expr::named(
+            "\\pi",
+            QuantityValue {
+                number: std::f64::consts::PI,
+                ..QuantityValue::default()
+            },
+        )
+    }
+
+    pub fn pow(self, exponent: Expr) -> Self {
+        Expr::Power(Box::new(self), Box::new(exponent))
+    }
+
+    pub fn powi(self, n: i32) -> Self {
+        self.pow(Expr::Constant(n as Scalar))
+    }
+
+    pub fn sqrt(self) -> Self {
+        self.pow(Expr::Constant(0.5))
+    }
+
+    pub fn exp(self) -> Self {
+        Expr::Apply(Function::Exp, Box::new(self))
+    }
+
+    pub fn ln(self) -> Self {
+        Expr::Apply(Function::Ln, Box::new(self))
+    }
+
+    pub fn sin(self) -> Self {
+        Expr::Apply(Function::Sin, Box::new(self))
+    }
+
+    pub fn cos(self) -> Self {
+        Expr::Apply(Function::Cos, Box::new(self))
+    }
+
+    pub fn tan(self) -> Self {
+        Expr::Apply(Function::Tan, Box::new(self))
+    }
+
+    /// Whether the expression contains the variable `x`.
+    pub fn depends_on(&self, x: &str) -> bool {
+        match self {
+            Expr::Constant(_) | Expr::Named(..) => false,
+            Expr::Variable(name) => name == x,
+            Expr::Sum(terms) | Expr::Product(terms) => terms.iter().any(|t| t.depends_on(x)),
+            Expr::Power(b, e) => b.depends_on(x) || e.depends_on(x),
+            Expr::Apply(_, a) => a.depends_on(x),
+        }
+    }
+
+    /// The expression with `value` in place of the variable `x`.
+    pub fn substitute(&self, x: &str, value: &Expr) -> Expr {
+        let s = |e: &Expr| e.substitute(x, value);
+        match self {
+            Expr::Variable(name) if name == x => value.clone(),
+            Expr::Sum(terms) => Expr::Sum(terms.iter().map(s).collect()),
+            Expr::Product(factors) => Expr::Product(factors.iter().map(s).collect()),
+            Expr::Power(b, e) => s(b).pow(s(e)),
+            Expr::Apply(f, a) => Expr::Apply(*f, Box::new(s(a))),
+            e => e.clone(),
+        }
+    }
+
+    /// Simplified derivative in the variable `x`.
+    pub fn derivative(&self, x: &str) -> Expr {
+        self.differentiate(x).simplify()
+    }
+
+    fn differentiate(&self, x: &str) -> Expr {
+        match self {
+            Expr::Constant(_) | Expr::Named(..) => Expr::Constant(0.0),
+            Expr::Variable(name) => Expr::Constant(if name == x { 1.0 } else { 0.0 }),
+            Expr::Sum(terms) => Expr::Sum(terms.iter().map(|t| t.differentiate(x)).collect()),
+            Expr::Product(factors) => Expr::Sum(
+                (0..factors.len())
+                    .map(|i| {
+                        let mut factors = factors.clone();
+                        factors[i] = factors[i].differentiate(x);
+                        Expr::Product(factors)
+                    })
+                    .collect(),
+            ),
+            Expr::Power(b, e) if !e.depends_on(x) => Expr::Product(vec![
+                (**e).clone(),
+                (**b)
+                    .clone()
+                    .pow(Expr::Sum(vec![(**e).clone(), Expr::Constant(-1.0)])),
+                b.differentiate(x),
+            ]),
+            Expr::Power(b, e) => Expr::Product(vec![
+                self.clone(),
+                Expr::Sum(vec![
+                    Expr::Product(vec![e.differentiate(x), (**b).clone().ln()]),
+                    Expr::Product(vec![
+                        (**e).clone(),
+                        b.differentiate(x),
+                        (**b).clone().powi(-1),
+                    ]),
+                ]),
+            ]),
+            Expr::Apply(f, a) => {
+                let a = (**a).clone();
+                let outer = match f {
+                    Function::Exp => a.clone().exp(),
+                    Function::Ln => a.clone().powi(-1),
+                    Function::Sin => a.clone().cos(),
+                    Function::Cos => -a.clone().sin(),
+                    Function::Tan => a.clone().cos().powi(-2),
+                };
+                Expr::Product(vec![outer, a.differentiate(x)])
+            }
+        }
+    }
+
+    /// Equivalent expression with nested sums and products flattened,
+    /// constants folded, like terms and like factors collected and
+    /// powers of products distributed.
+    pub fn simplify(&self) -> Expr {
+        match self {
+            Expr::Sum(terms) => {
+                let mut constant = 0.0;
+                let mut collected: Vec<(Scalar, Expr)> = Vec::new();
+                for term in terms.iter().flat_map(|t| t.simplify().operands(true)) {
+                    match term {
+                        Expr::Constant(c) => constant += c,
+                        term => {
+                            let (c, rest) = term.split_coefficient();
+                            match collected.iter_mut().find(|(_, r)| *r == rest) {
+                                Some((d, _)) => *d += c,
+                                None => collected.push((c, rest)),
+                            }
+                        }
+                    }
+                }
+                let mut terms: Vec<Expr> = collected
+                    .into_iter()
+                    .filter(|(c, _)| *c != 0.0)
+                    .map(|(c, rest)| rest.scaled(c))
+                    .collect();
+                if constant != 0.0 {
+                    terms.push(Expr::Constant(constant));
+                }
+                match terms.len() {
+                    0 => Expr::Constant(0.0),
+                    1 => terms.pop().unwrap(),
+                    _ => Expr::Sum(terms),
+                }
+            }
+            Expr::Product(factors) => {
+                let mut coefficient = 1.0;
+                let mut collected: Vec<(Expr, Expr)> = Vec::new();
+                for factor in factors.iter().flat_map(|f| f.simplify().operands(false)) {
+                    let (base, exponent) = match factor {
+                        Expr::Constant(c) => {
+                            coefficient *= c;
+                            continue;
+                        }
+                        Expr::Power(b, e) => (*b, *e),
+                        f => (f, Expr::Constant(1.0)),
+                    };
+                    match collected.iter_mut().find(|(b, _)| *b == base) {
+                        Some((_, e)) => *e = Expr::Sum(vec![e.clone(), exponent]).simplify(),
+                        None => collected.push((base, exponent)),
+                    }
+                }
+                if coefficient == 0.0 {
+                    return Expr::Constant(0.0);
+                }
+                let mut factors = Vec::new();
+                for (b, e) in collected {
+                    match b.pow(e).simplify() {
+                        Expr::Constant(c) => coefficient *= c,
+                        f => factors.push(f),
+                    }
+                }
+                reduce_fraction(&mut coefficient, &mut factors);
+                if coefficient != 1.0 || factors.is_empty() {
+                    factors.insert(0, Expr::Constant(coefficient));
+                }
+                match factors.len() {
+                    1 => factors.pop().unwrap(),
+                    _ => Expr::Product(factors),
+                }
+            }
+            Expr::Power(b, e) => match (b.simplify(), e.simplify()) {
+                (_, Expr::Constant(0.0)) => Expr::Constant(1.0),
+                (b, Expr::Constant(1.0)) => b,
+                // Folded only where the result stays exact
+                (Expr::Constant(b), Expr::Constant(e))
+                    if b == 1.0 || (e > 0.0 && e.fract() == 0.0) =>
+                {
+                    Expr::Constant(b.powf(e))
+                }
+                (Expr::Power(b, f), e @ Expr::Constant(_)) => {
+                    b.pow(Expr::Product(vec![*f, e])).simplify()
+                }
+                (Expr::Product(factors), e @ Expr::Constant(_)) => {
+                    Expr::Product(factors.into_iter().map(|f| f.pow(e.clone())).collect())
+                        .simplify()
+                }
+                (b, e) => b.pow(e),
+            },
+            Expr::Apply(f, a) => match (f, a.simplify()) {
+                (Function::Exp, Expr::Apply(Function::Ln, a)) => *a,
+                (Function::Ln, Expr::Apply(Function::Exp, a)) => *a,
+                (Function::Exp, Expr::Constant(0.0)) => Expr::Constant(1.0),
+                (Function::Ln, Expr::Constant(1.0)) => Expr::Constant(0.0),
+                (f, a) => Expr::Apply(*f, Box::new(a)),
+            },
+            e => e.clone(),
+        }
+    }
+
+    /// Terms of a sum or factors of a product, for flattening.
+    fn operands(self, sum: bool) -> Vec<Expr> {
+        match self {
+            Expr::Sum(terms) if sum => terms,
+            Expr::Product(factors) if !sum => factors,
+            e => vec![e],
+        }
+    }
+
+    /// Numerical coefficient of a simplified term and the rest of it.
+    fn split_coefficient(self) -> (Scalar, Expr) {
+        match self {
+            Expr::Product(mut factors) => match factors[0] {
+                Expr::Constant(c) => {
+                    factors.remove(0);
+                    match factors.len() {
+                        1 => (c, factors.pop().unwrap()),
+                        _ => (c, Expr::Product(factors)),
+                    }
+                }
+                _ => (1.0, Expr::Product(factors)),
+            },
+            e => (1.0, e),
+        }
+    }
+
+    fn scaled(self, c: Scalar) -> Expr {
+        match self {
+            e if c == 1.0 => e,
+            Expr::Product(mut factors) => {
+                factors.insert(0, Expr::Constant(c));
+                Expr::Product(factors)
+            }
+            e => Expr::Product(vec![Expr::Constant(c), e]),
+        }
+    }
+
+    /// Value of the expression with the variables bound to `bindings`, its
+    /// uncertainty propagated from theirs to first order,
+    ///
+    /// ```text
+    /// sigma^2 = sum (df/dx sigma_x)^2
+    /// ```
+    pub fn evaluate(
+        &self,
+        bindings: &[(&str, QuantityValue)],
+    ) -> Result<QuantityValue, EvaluationError> {
+        let (number, dimension) = self.value(bindings)?;
+        let mut variance = 0.0;
+        for (x, q) in bindings {
+            if q.uncertainty != 0.0 && self.depends_on(x) {
+                let (d, _) = self.derivative(x).value(bindings)?;
+                variance += (d * q.uncertainty).powi(2);
+            }
+        }
+        Ok(QuantityValue {
+            number,
+            dimension,
+            uncertainty: variance.sqrt(),
+        })
+    }
+
+    fn value(
+        &self,
+        bindings: &[(&str, QuantityValue)],
+    ) -> Result<(Scalar, Dimension), EvaluationError> {
+        Ok(match self {
+            Expr::Constant(c) => (*c, dimensionless()),
+            Expr::Named(_, q) => (q.number, q.dimension),
+            Expr::Variable(name) => match bindings.iter().find(|(x, _)| x == name) {
+                Some((_, q)) => (q.number, q.dimension),
+                None => return Err(EvaluationError::Unbound(name.clone())),
+            },
+            Expr::Sum(terms) if terms.is_empty() => (0.0, dimensionless()),
+            Expr::Sum(terms) => {
+                let (mut sum, dimension) = terms[0].value(bindings)?;
+                for term in &terms[1..] {
+                    let (t, found) = term.value(bindings)?;
+                    if found != dimension {
+                        return Err(DimensionError {
+                            expected: dimension,
+                            found,
+                        }
+                        .into());
+                    }
+                    sum += t;
+                }
+                (sum, dimension)
+            }
+            Expr::Product(factors) => {
+                let mut product = (1.0, dimensionless());
+                for factor in factors {
+                    let (f, dimension) = factor.value(bindings)?;
+                    product.0 *= f;
+                    for (d, e) in product.1.iter_mut().zip(dimension.iter()) {
+                        *d += e;
+                    }
+                }
+                product
+            }
+            Expr::Power(b, e) => {
+                let (e, found) = e.value(bindings)?;
+                check_dimensionless(found)?;
+                let (b, mut dimension) = b.value(bindings)?;
+                if dimension != dimensionless() {
+                    let exponent = Ratio::approximate_float(e).ok_or(DimensionError {
+                        expected: dimensionless(),
+                        found: dimension,
+                    })?;
+                    for d in dimension.iter_mut() {
+                        *d *= exponent;
+                    }
+                }
+                (b.powf(e), dimension)
+            }
+            Expr::Apply(f, a) => {
+                let (a, found) = a.value(bindings)?;
+                check_dimensionless(found)?;
+                (f.apply(a), dimensionless())
+            }
+        })
+    }
+
+    /// LaTeX of the expression, for math mode.
+    pub fn to_latex(&self) -> String {
+        match self {
+            Expr::Constant(c) if *c < 0.0 => format!("-{}", latex_number(-c)),
+            Expr::Constant(c) => latex_number(*c),
+            Expr::Named(symbol, _) => symbol.clone(),
+            Expr::Variable(name) => latex_symbol(name),
+            Expr::Sum(terms) if terms.is_empty() => latex_number(0.0),
+            Expr::Sum(terms) => {
+                let mut s = terms[0].to_latex();
+                for term in &terms[1..] {
+                    let t = term.to_latex();
+                    match t.strip_prefix('-') {
+                        Some(t) => s.push_str(&format!(" - {}", t)),
+                        None => s.push_str(&format!(" + {}", t)),
+                    }
+                }
+                s
+            }
+            Expr::Product(factors) => latex_product(factors),
+            Expr::Power(..) => latex_product(std::slice::from_ref(self)),
+            Expr::Apply(f, a) => format!("{}\\left({}\\right)", f.latex(), a.to_latex()),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_latex())
+    }
+}
+
+/// dr/dm = 1/(4 pi r^2 rho), of the variables r and rho
+pub fn dr_dm() -> Expr {
+    1.0 / (4.0 * Expr::pi() * Expr::var("r").powi(2) * Expr::var("rho"))
+}
+
+/// rho = mu P/(R T) of an ideal gas, of the variables mu (molar mass), P and
+/// T
+pub fn ideal_gas_density() -> Expr {
+    let gas_constant = QuantityValue::kg() * QuantityValue::m().pow(2.0)
+        / (QuantityValue::s().pow(2.0) * QuantityValue::mol() * QuantityValue::K())
+        * MOLAR_GAS_CONSTANT;
+    Expr::var("mu") * Expr::var("P") / (Expr::named("R", gas_constant) * Expr::var("T"))
+}
+
+/// Cancels the common divisor of an integer coefficient and the integer
+/// `d` of a factor d^-1, as in 2/4 = 1/2.
+fn reduce_fraction(coefficient: &mut Scalar, factors: &mut Vec<Expr>) {
+    let integer = |x: Scalar| x.fract() == 0.0 && x.abs() < 1e15;
+    let position = factors.iter().position(|f| match f {
+        Expr::Power(b, e) => match (&**b, &**e) {
+            (Expr::Constant(d), Expr::Constant(e)) => *e == -1.0 && integer(*d) && *d > 0.0,
+            _ => false,
+        },
+        _ => false,
+    });
+    let (i, d) = match position.map(|i| (i, &factors[i])) {
+        Some((i, Expr::Power(b, _))) if integer(*coefficient) => match **b {
+            Expr::Constant(d) => (i, d),
+            _ => return,
+        },
+        _ => return,
+    };
+    let (mut a, mut b) = (coefficient.abs() as u64, d as u64);
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    let gcd = a as Scalar;
+    *coefficient /= gcd;
+    if d == gcd {
+        factors.remove(i);
+    } else {
+        factors[i] = Expr::Constant(d / gcd).powi(-1);
+    }
+}
+
+fn latex_number(x: Scalar) -> String {
+    if (x.fract() == 0.0 && x.abs() < 1e6) || (1e-3..1e4).contains(&x.abs()) {
+        format!("{}", x)
+    } else {
+        let s = format!("{:e}", x);
+        let (mantissa, exponent) = s.split_at(s.find('e').unwrap());
+        format!("{} \\times 10^{{{}}}", mantissa, &exponent[1..])
+    }
+}
+
+/// Variable names as LaTeX, Greek letters by name and subscripts after `_`.
+fn latex_symbol(name: &str) -> String {
+    let greek = |s: &str| {
+        if GREEK.contains(&s) {
+            format!("\\{}", s)
+        } else {
+            s.to_string()
+        }
+    };
+    match name.split_once('_') {
+        Some((base, subscript)) => format!("{}_{{{}}}", greek(base), greek(subscript)),
+        None => greek(name),
+    }
+}
+
+/// Base and exponent of a factor of a product, parenthesized as needed.
+fn latex_power(base: &Expr, exponent: &Expr) -> String {
+    if *exponent == Expr::Constant(0.5) {
+        return format!("\\sqrt{{{}}}", base.to_latex());
+    }
+    let b = match base {
+        Expr::Constant(c) if *c >= 0.0 => base.to_latex(),
+        Expr::Named(..) | Expr::Variable(_) => base.to_latex(),
+        _ => format!("\\left({}\\right)", base.to_latex()),
+    };
+    match exponent {
+        Expr::Constant(e) if *e == 1.0 => b,
+        e => format!("{}^{{{}}}", b, e.to_latex()),
+    }
+}
+
+/// Product as a fraction of the factors of positive and negative constant
+/// exponents.
+fn latex_product(factors: &[Expr]) -> String {
+    let mut sign = "";
+    let mut numerator = Vec::new();
+    let mut denominator = Vec::new();
+    for factor in factors {
+        match factor {
+            Expr::Constant(c) => {
+                if *c < 0.0 {
+                    sign = "-";
+                }
+                if c.abs() != 1.0 {
+                    numerator.push(latex_number(c.abs()));
+                }
+            }
+            Expr::Power(b, e) => match **e {
+                Expr::Constant(e) if e < 0.0 => {
+                    denominator.push(latex_power(b, &Expr::Constant(-e)))
+                }
+                _ => numerator.push(latex_power(b, e)),
+            },
+            Expr::Sum(_) => numerator.push(format!("\\left({}\\right)", factor.to_latex())),
+            f => numerator.push(f.to_latex()),
+        }
+    }
+    let numerator = if numerator.is_empty() {
+        "1".to_string()
+    } else {
+        numerator.join(" ")
+    };
+    if denominator.is_empty() {
+        format!("{}{}", sign, numerator)
+    } else {
+        format!(
+            "{}\\frac{{{}}}{{{}}}",
+            sign,
+            numerator,
+            denominator.join(" ")
+        )
+    }
+}
+
+impl Add for Expr {
+    type Output = Expr;
+
+    fn add(self, other: Expr) -> Expr {
+        Expr::Sum(vec![self, other])
+    }
+}
+
+impl Sub for Expr {
+    type Output = Expr;
+
+    fn sub(self, other: Expr) -> Expr {
+        Expr::Sum(vec![self, -other])
+    }
+}
+
+impl Mul for Expr {
+    type Output = Expr;
+
+    fn mul(self, other: Expr) -> Expr {
+        Expr::Product(vec![self, other])
+    }
+}
+
+impl Div for Expr {
+    type Output = Expr;
+
+    fn div(self, other: Expr) -> Expr {
+        Expr::Product(vec![self, other.powi(-1)])
+    }
+}
+
+impl Neg for Expr {
+    type Output = Expr;
+
+    fn neg(self) -> Expr {
+        Expr::Product(vec![Expr::Constant(-1.0), self])
+    }
+}
+
+impl Mul<Scalar> for Expr {
+    type Output = Expr;
+
+    fn mul(self, other: Scalar) -> Expr {
+        self * Expr::Constant(other)
+    }
+}
+
+impl Div<Scalar> for Expr {
+    type Output = Expr;
+
+    fn div(self, other: Scalar) -> Expr {
+        self / Expr::Constant(other)
+    }
+}
+
+impl Mul<Expr> for Scalar {
+    type Output = Expr;
+
+    fn mul(self, other: Expr) -> Expr {
+        Expr::Constant(self) * other
+    }
+}
+
+impl Div<Expr> for Scalar {
+    type Output = Expr;
+
+    fn div(self, other: Expr) -> Expr {
+        Expr::Constant(self) / other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latex() {
+        let doc = include_str!("../../doc/stars.tex");
+        let f = dr_dm().simplify();
+        let rho = ideal_gas_density().simplify();
+        for (lhs, e) in [
+            ("f(r,\\rho)", &f),
+            ("\\rho(P,T)", &rho),
+            ("\\frac{\\partial f}{\\partial r}", &f.derivative("r")),
+            ("\\frac{\\partial f}{\\partial \\rho}", &f.derivative("rho")),
+            ("\\frac{\\partial \\rho}{\\partial T}", &rho.derivative("T")),
+        ] {
+            let line = format!("{} &= {}", lhs, e.to_latex());
+            assert!(doc.contains(&line), "{}", line);
+        }
+        assert_eq!(f.to_latex(), "\\frac{1}{4 \\pi r^{2} \\rho}");
+        let g = (Expr::var("T_c").powi(4) - 3.0 * Expr::var("x"))
+            .sqrt()
+            .exp()
+            * 6.674e-11;
+        assert_eq!(
+            g.simplify().to_latex(),
+            "6.674 \\times 10^{-11} \\exp\\left(\\sqrt{T_{c}^{4} - 3 x}\\right)"
+        );
+    }
+
+    #[test]
+    fn test_derivative() {
+        let x = Expr::var("x");
+        assert_eq!(
+            (x.clone() * x.clone() * 3.0 * x.clone()).derivative("x"),
+            9.0 * x.clone().powi(2)
+        );
+        assert_eq!(
+            (x.clone().ln() + x.clone().sin() - x.clone())
+                .derivative("x")
+                .simplify(),
+            Expr::Sum(vec![
+                x.clone().powi(-1),
+                x.clone().cos(),
+                Expr::Constant(-1.0)
+            ])
+        );
+        // x^x, through the logarithm
+        let bindings = [(
+            "x",
+            QuantityValue {
+                number: 1.7,
+                ..QuantityValue::default()
+            },
+        )];
+        let dx = x
+            .clone()
+            .pow(x.clone())
+            .derivative("x")
+            .evaluate(&bindings)
+            .unwrap();
+        assert!((dx.number - 1.7f64.powf(1.7) * (1.7f64.ln() + 1.0)).abs() < 1e-14);
+        assert_eq!(x.clone().exp().ln().simplify(), x);
+        assert_eq!((x.clone() - x.clone()).simplify(), Expr::Constant(0.0));
+        assert!(dr_dm()
+            .substitute("rho", &ideal_gas_density())
+            .derivative("mu")
+            .depends_on("P"));
+    }
+
+    #[test]
+    fn test_empty() {
+        let sum = Expr::Sum(vec![]);
+        let product = Expr::Product(vec![]);
+        for (e, value) in [(&sum, 0.0), (&product, 1.0)].iter() {
+            assert_eq!(e.evaluate(&[]).unwrap().number, *value);
+            assert_eq!(e.to_latex(), value.to_string());
+            assert_eq!(e.simplify(), Expr::Constant(*value));
+        }
+        assert_eq!(product.derivative("x").evaluate(&[]).unwrap().number, 0.0);
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let p = QuantityValue {
+            number: 2e14,
+            uncertainty: 2e11,
+            ..QuantityValue::Pa()
+        };
+        let t = QuantityValue::K() * 1.5e7;
+        let mu = QuantityValue::kg() / QuantityValue::mol() * 0.6e-3;
+        let rho = ideal_gas_density()
+            .evaluate(&[("P", p), ("T", t), ("mu", mu)])
+            .unwrap();
+        let expected = 0.6e-3 * 2e14 / (MOLAR_GAS_CONSTANT * 1.5e7);
+        assert!((rho.number / expected - 1.0).abs() < 1e-15);
+        assert_eq!(
+            rho.dimension,
+            (QuantityValue::kg() / QuantityValue::m().pow(3.0)).dimension
+        );
+        assert!((rho.uncertainty / (1e-3 * expected) - 1.0).abs() < 1e-12);
+
+        let r = QuantityValue::m() * 7e8;
+        let f = dr_dm().substitute("rho", &ideal_gas_density());
+        let value = f
+            .evaluate(&[("r", r), ("P", p), ("T", t), ("mu", mu)])
+            .unwrap();
+        assert_eq!(
+            value.dimension,
+            (QuantityValue::m() / QuantityValue::kg()).dimension
+        );
+        assert_eq!(
+            f.evaluate(&[("r", r), ("P", p), ("T", t)]),
+            Err(EvaluationError::Unbound("mu".to_string()))
+        );
+        let error = (Expr::var("r") + Expr::var("T"))
+            .evaluate(&[("r", r), ("T", t)])
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "expected a quantity in m, found one in K"
+        );
+    }
+}
@@ -0,0 +1,639 @@
+//! Reverse-mode automatic differentiation on a tape.
+//!
+//! Every operation on a `Var` appends a node to its `Tape` holding the
+//! partial derivatives of the result in its (at most two) operands. A single
+//! backward pass from an output then accumulates the adjoints
+//!
+//! ```text
+//! x_bar = sum over results y of x of y_bar dy/dx
+//! ```
+//!
+//! giving its derivatives in every input at the cost of a few evaluations,
+//! however many inputs there are. Forward mode (`dual`) is the better choice
+//! for few inputs and many outputs.
+//!
+//! `Quantity` carries the dimension of a `units::QuantityValue` alongside
+//! its `Var`, so that sensitivities come out in their units.
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use num::rational::Ratio;
+use num::CheckedMul;
+
+use crate::units::{Dimension, DimensionError, QuantityValue};
+
+type Scalar = f64;
+
+/// Operands of a node and the partial derivatives in them.
+type Node = [(usize, Scalar); 2];
+
+/// Record of the operations on its variables.
+#[derive(Debug, Default)]
+pub struct Tape {
+    nodes: RefCell<Vec<Node>>,
+}
+
+impl Tape {
+    pub fn new() -> Self {
+        Tape::default()
+    }
+
+    /// New input of value `value`.
+    pub fn var(&self, value: Scalar) -> Var<'_> {
+        let index = self.len();
+        self.push(value, [(index, 0.0), (index, 0.0)])
+    }
+
+    /// New input of the value and dimension of `q`.
+    pub fn quantity(&self, q: QuantityValue) -> Quantity<'_> {
+        Quantity {
+            var: self.var(q.number),
+            dimension: q.dimension,
+        }
+    }
+
+    /// Number of nodes recorded.
+    pub fn len(&self) -> usize {
+        self.nodes.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn push(&self, value: Scalar, node: Node) -> Var<'_> {
+        let mut nodes = self.nodes.borrow_mut();
+        nodes.push(node);
+        Var {
+            tape: self,
+            index: nodes.len() - 1,
+            value,
+        }
+    }
+}
+
+/// Variable recorded on a tape.
+#[derive(Clone, Copy)]
+pub struct Var<'t> {
+    tape: &'t Tape,
+    index: usize,
+    value: Scalar,
+}
+
+impl<'t> fmt::Debug for Var<'t> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Var")
+            .field("index", &self.index)
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+impl<'t> Var<'t> {
+    pub fn value(&self) -> Scalar {
+        self.value
+    }
+
+    /// Derivatives of the variable in every variable recorded before it, by
+    /// one backward pass.
+    pub fn gradient(&self) -> Gradient<'t> {
+        let nodes = self.tape.nodes.borrow();
+        let mut adjoints = vec![0.0; self.index + 1];
+        adjoints[self.index] = 1.0;
+        for i in (0..=self.index).rev() {
+            let adjoint = adjoints[i];
+            if adjoint == 0.0 {
+                continue;
+            }
+            for &(j, partial) in nodes[i].iter() {
+                adjoints[j] += partial * adjoint;
+            }
+        }
+        Gradient {
+            tape: self.tape,
+            adjoints,
+        }
+    }
+
+    fn unary(self, value: Scalar, partial: Scalar) -> Self {
+        self.tape
+            .push(value, [(self.index, partial), (self.index, 0.0)])
+    }
+
+    fn binary(self, other: Self, value: Scalar, partials: [Scalar; 2]) -> Self {
+        assert!(
+            std::ptr::eq(self.tape, other.tape),
+            "variables of different tapes"
+        );
+        self.tape.push(
+            value,
+            [(self.index, partials[0]), (other.index, partials[1])],
+        )
+    }
+
+    pub fn recip(self) -> Self {
+        self.unary(self.value.recip(), -self.value.powi(-2))
+    }
+
+    pub fn powi(self, n: i32) -> Self {
+        self.unary(self.value.powi(n), n as Scalar * self.value.powi(n - 1))
+    }
+
+    pub fn powf(self, n: Scalar) -> Self {
+        self.unary(self.value.powf(n), n * self.value.powf(n - 1.0))
+    }
+
+    /// x^y, differentiated in both.
+    pub fn pow(self, other: Self) -> Self {
+        let f = self.value.powf(other.value);
+        self.binary(
+            other,
+            f,
+            [
+                other.value * self.value.powf(other.value - 1.0),
+                f * self.value.ln(),
+            ],
+        )
+    }
+
+    pub fn sqrt(self) -> Self {
+        let f = self.value.sqrt();
+        self.unary(f, 0.5 / f)
+    }
+
+    pub fn cbrt(self) -> Self {
+        let f = self.value.cbrt();
+        self.unary(f, f / (3.0 * self.value))
+    }
+
+    pub fn exp(self) -> Self {
+        let f = self.value.exp();
+        self.unary(f, f)
+    }
+
+    pub fn ln(self) -> Self {
+        self.unary(self.value.ln(), self.value.recip())
+    }
+
+    pub fn log10(self) -> Self {
+        self.unary(
+            self.value.log10(),
+            (self.value * std::f64::consts::LN_10).recip(),
+        )
+    }
+
+    pub fn abs(self) -> Self {
+        self.unary(self.value.abs(), self.value.signum())
+    }
+
+    pub fn sin(self) -> Self {
+        self.unary(self.value.sin(), self.value.cos())
+    }
+
+    pub fn cos(self) -> Self {
+        self.unary(self.value.cos(), -self.value.sin())
+    }
+
+    pub fn tan(self) -> Self {
+        let f = self.value.tan();
+        self.unary(f, 1.0 + f * f)
+    }
+
+    pub fn atan(self) -> Self {
+        self.unary(self.value.atan(), (1.0 + self.value * self.value).recip())
+    }
+
+    pub fn tanh(self) -> Self {
+        let f = self.value.tanh();
+        self.unary(f, 1.0 - f * f)
+    }
+
+    /// The larger of the two, with its derivatives.
+    pub fn max(self, other: Self) -> Self {
+        let other_larger = other.value > self.value;
+        self.binary(
+            other,
+            self.value.max(other.value),
+            if other_larger { [0.0, 1.0] } else { [1.0, 0.0] },
+        )
+    }
+
+    pub fn min(self, other: Self) -> Self {
+        let other_smaller = other.value < self.value;
+        self.binary(
+            other,
+            self.value.min(other.value),
+            if other_smaller {
+                [0.0, 1.0]
+            } else {
+                [1.0, 0.0]
+            },
+        )
+    }
+}
+
+impl<'t> Add for Var<'t> {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        self.binary(other, self.value + other.value, [1.0, 1.0])
+    }
+}
+
+impl<'t> Sub for Var<'t> {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self.binary(other, self.value - other.value, [1.0, -1.0])
+    }
+}
+
+impl<'t> Mul for Var<'t> {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        self.binary(other, self.value * other.value, [other.value, self.value])
+    }
+}
+
+impl<'t> Div for Var<'t> {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        let f = self.value / other.value;
+        self.binary(other, f, [1.0 / other.value, -f / other.value])
+    }
+}
+
+impl<'t> Neg for Var<'t> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        self.unary(-self.value, -1.0)
+    }
+}
+
+impl<'t> Add<Scalar> for Var<'t> {
+    type Output = Self;
+
+    fn add(self, other: Scalar) -> Self {
+        self.unary(self.value + other, 1.0)
+    }
+}
+
+impl<'t> Sub<Scalar> for Var<'t> {
+    type Output = Self;
+
+    fn sub(self, other: Scalar) -> Self {
+        self.unary(self.value - other, 1.0)
+    }
+}
+
+impl<'t> Mul<Scalar> for Var<'t> {
+    type Output = Self;
+
+    fn mul(self, other: Scalar) -> Self {
+        self.unary(self.value * other, other)
+    }
+}
+
+impl<'t> Div<Scalar> for Var<'t> {
+    type Output = Self;
+
+    fn div(self, other: Scalar) -> Self {
+        self.unary(self.value / other, 1.0 / other)
+    }
+}
+
+impl<'t> Add<Var<'t>> for Scalar {
+    type Output = Var<'t>;
+
+    fn add(self, other: Var<'t>) -> Var<'t> {
+        other + self
+    }
+}
+
+impl<'t> Sub<Var<'t>> for Scalar {
+    type Output = Var<'t>;
+
+    fn sub(self, other: Var<'t>) -> Var<'t> {
+        other.unary(self - other.value, -1.0)
+    }
+}
+
+impl<'t> Mul<Var<'t>> for Scalar {
+    type Output = Var<'t>;
+
+    fn mul(self, other: Var<'t>) -> Var<'t> {
+        other * self
+    }
+}
+
+impl<'t> Div<Var<'t>> for Scalar {
+    type Output = Var<'t>;
+
+    fn div(self, other: Var<'t>) -> Var<'t> {
+        let f = self / other.value;
+        other.unary(f, -f / other.value)
+    }
+}
+
+/// Adjoints of one output, indexed by node.
+#[derive(Clone)]
+pub struct Gradient<'t> {
+    tape: &'t Tape,
+    adjoints: Vec<Scalar>,
+}
+
+impl<'t> fmt::Debug for Gradient<'t> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Gradient")
+            .field("adjoints", &self.adjoints)
+            .finish()
+    }
+}
+
+impl<'t> PartialEq for Gradient<'t> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.tape, other.tape) && self.adjoints == other.adjoints
+    }
+}
+
+impl<'t> Gradient<'t> {
+    /// Derivative of the output in `x`, zero if the output does not
+    /// depend on it. Panics if `x` is on another tape.
+    pub fn wrt(&self, x: &Var) -> Scalar {
+        assert!(
+            std::ptr::eq(self.tape, x.tape),
+            "variables of different tapes"
+        );
+        self.adjoints.get(x.index).copied().unwrap_or(0.0)
+    }
+
+    pub fn wrt_all(&self, x: &[Var]) -> Vec<Scalar> {
+        x.iter().map(|x| self.wrt(x)).collect()
+    }
+}
+
+/// Variable with the dimension of a `QuantityValue`.
+#[derive(Debug, Clone, Copy)]
+pub struct Quantity<'t> {
+    pub var: Var<'t>,
+    pub dimension: Dimension,
+}
+
+impl<'t> Quantity<'t> {
+    /// Current value, without uncertainty.
+    pub fn value(&self) -> QuantityValue {
+        QuantityValue {
+            number: self.var.value,
+            dimension: self.dimension,
+            uncertainty: 0.0,
+        }
+    }
+
+    /// Derivatives of the quantity in every quantity recorded before it.
+    pub fn sensitivities(&self) -> Sensitivities<'t> {
+        Sensitivities {
+            gradient: self.var.gradient(),
+            dimension: self.dimension,
+        }
+    }
+
+    /// Sum, or an error if the dimensions differ.
+    pub fn checked_add(self, other: Self) -> Result<Self, DimensionError> {
+        self.check(&other)?;
+        Ok(self.with(self.var + other.var))
+    }
+
+    pub fn checked_sub(self, other: Self) -> Result<Self, DimensionError> {
+        self.check(&other)?;
+        Ok(self.with(self.var - other.var))
+    }
+
+    /// Panics if an exponent of the dimension overflows `i8`.
+    pub fn powi(self, n: i32) -> Self {
+        let mut q = self.with(self.var.powi(n));
+        let n = Ratio::from_integer(i8::try_from(n).expect("exponent out of range"));
+        for e in q.dimension.iter_mut() {
+            *e = e.checked_mul(&n).expect("exponent out of range");
+        }
+        q
+    }
+
+    pub fn sqrt(self) -> Self {
+        let mut q = self.with(self.var.sqrt());
+        for e in q.dimension.iter_mut() {
+            *e /= Ratio::from_integer(2);
+        }
+        q
+    }
+
+    fn check(&self, other: &Self) -> Result<(), DimensionError> {
+        if self.dimension != other.dimension {
+            return Err(DimensionError {
+                expected: self.dimension,
+                found: other.dimension,
+            });
+        }
+        Ok(())
+    }
+
+    fn with(self, var: Var<'t>) -> Self {
+        Quantity { var, ..self }
+    }
+}
+
+/// Panics if the dimensions differ; see `checked_add`.
+impl<'t> Add for Quantity<'t> {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        self.checked_add(other).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+/// Panics if the dimensions differ; see `checked_sub`.
+impl<'t> Sub for Quantity<'t> {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self.checked_sub(other).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+impl<'t> Mul for Quantity<'t> {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        let mut q = self.with(self.var * other.var);
+        for (e, f) in q.dimension.iter_mut().zip(other.dimension.iter()) {
+            *e += f;
+        }
+        q
+    }
+}
+
+impl<'t> Div for Quantity<'t> {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        let mut q = self.with(self.var / other.var);
+        for (e, f) in q.dimension.iter_mut().zip(other.dimension.iter()) {
+            *e -= f;
+        }
+        q
+    }
+}
+
+impl<'t> Mul<QuantityValue> for Quantity<'t> {
+    type Output = Self;
+
+    /// Product with a constant quantity.
+    fn mul(self, other: QuantityValue) -> Self {
+        let mut q = self.with(self.var * other.number);
+        for (e, f) in q.dimension.iter_mut().zip(other.dimension.iter()) {
+            *e += f;
+        }
+        q
+    }
+}
+
+impl<'t> Mul<Scalar> for Quantity<'t> {
+    type Output = Self;
+
+    fn mul(self, other: Scalar) -> Self {
+        self.with(self.var * other)
+    }
+}
+
+/// Derivatives of one output quantity, in the units of output per input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sensitivities<'t> {
+    pub gradient: Gradient<'t>,
+    pub dimension: Dimension,
+}
+
+impl<'t> Sensitivities<'t> {
+    /// Derivative of the output in `x`.
+    pub fn wrt(&self, x: &Quantity) -> QuantityValue {
+        let mut dimension = self.dimension;
+        for (e, f) in dimension.iter_mut().zip(x.dimension.iter()) {
+            *e -= f;
+        }
+        QuantityValue {
+            number: self.gradient.wrt(&x.var),
+            dimension,
+            uncertainty: 0.0,
+        }
+    }
+
+    /// Uncertainty of the output from independent uncertainties of the
+    /// `inputs`, to first order,
+    ///
+    /// ```text
+    /// sigma_y^2 = sum (dy/dx_i sigma_i)^2
+    /// ```
+    pub fn uncertainty(&self, inputs: &[(Quantity, Scalar)]) -> Scalar {
+        inputs
+            .iter()
+            .map(|(x, sigma)| (self.gradient.wrt(&x.var) * sigma).powi(2))
+            .sum::<Scalar>()
+            .sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dual::Dual;
+    use num::pow::Pow;
+
+    #[test]
+    fn test_gradient() {
+        let tape = Tape::new();
+        let x = [1.3, 0.7, 2.1];
+        let v: Vec<Var> = x.iter().map(|&x| tape.var(x)).collect();
+        let f = (v[0] * v[1].sin() / (v[0] + v[2]).sqrt()).exp() - 2.0 / v[2]
+            + v[1].pow(v[0]) * v[2].ln().atan()
+            + (v[0] - v[1]).max(v[2].cbrt()).powi(3);
+        // The same function in forward mode
+        let d = Dual::variables(x);
+        let g = (d[0] * d[1].sin() / (d[0] + d[2]).sqrt()).exp() - 2.0 / d[2]
+            + d[1].powf(d[0]) * d[2].ln().atan()
+            + (d[0] - d[1]).max(d[2].cbrt()).powi(3);
+        assert_eq!(f.value(), g.value);
+        let gradient = f.gradient();
+        for (a, b) in gradient.wrt_all(&v).iter().zip(g.gradient.iter()) {
+            assert!((a - b).abs() < 1e-14 * b.abs());
+        }
+        // Variables recorded later do not affect f
+        assert_eq!(gradient.wrt(&tape.var(1.0)), 0.0);
+    }
+
+    #[test]
+    fn test_mass_budget() {
+        // Spherical shell of radius r, thickness t and density rho holding a
+        // payload of mass m_p
+        let tape = Tape::new();
+        let r = tape.quantity(QuantityValue::m() * 1.5);
+        let t = tape.quantity(QuantityValue::m() * 0.01);
+        let rho = tape.quantity(QuantityValue::kg() / QuantityValue::m().pow(3.0) * 2700.0);
+        let payload = tape.quantity(QuantityValue::kg() * 200.0);
+        let area = r.powi(2) * (4.0 * std::f64::consts::PI);
+        let mass = area * t * rho + payload;
+        let [r_0, t_0, rho_0] = [1.5, 0.01, 2700.0];
+        let area_0 = 4.0 * std::f64::consts::PI * r_0 * r_0;
+        assert_eq!(
+            mass.value().value_in(QuantityValue::kg()),
+            Ok(area_0 * t_0 * rho_0 + 200.0)
+        );
+
+        let sensitivities = mass.sensitivities();
+        let dm_dr = sensitivities.wrt(&r);
+        assert_eq!(
+            dm_dr.dimension,
+            (QuantityValue::kg() / QuantityValue::m()).dimension
+        );
+        assert!((dm_dr.number / (2.0 * area_0 * t_0 * rho_0 / r_0) - 1.0).abs() < 1e-15);
+        assert_eq!(
+            sensitivities
+                .wrt(&rho)
+                .value_in(QuantityValue::m().pow(3.0)),
+            Ok(area_0 * t_0)
+        );
+        let one = QuantityValue {
+            number: 1.0,
+            ..QuantityValue::default()
+        };
+        assert_eq!(sensitivities.wrt(&payload).value_in(one), Ok(1.0));
+        let sigma = sensitivities.uncertainty(&[(t, 1e-3), (payload, 5.0)]);
+        assert!((sigma - (area_0 * rho_0 * 1e-3).hypot(5.0)).abs() < 1e-9);
+
+        assert!(t.checked_add(r).is_ok());
+        let error = mass.checked_add(area).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "expected a quantity in kg, found one in m^(2)"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "variables of different tapes")]
+    fn test_other_tape() {
+        let [a, b] = [Tape::new(), Tape::new()];
+        let x = a.var(2.0);
+        let y = b.var(3.0);
+        (x * x).gradient().wrt(&y);
+    }
+
+    #[test]
+    #[should_panic(expected = "exponent out of range")]
+    fn test_powi_overflow() {
+        let tape = Tape::new();
+        tape.quantity(QuantityValue::m()).powi(256);
+    }
+}
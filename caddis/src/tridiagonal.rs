@@ -0,0 +1,319 @@
+//! Block-tridiagonal linear systems.
+//!
+//! The matrix is partitioned into square diagonal blocks of possibly
+//! different sizes, with nonzero blocks only on and next to the diagonal,
+//!
+//! ```text
+//! | B_0 C_0                 |
+//! | A_1 B_1 C_1             |
+//! |     A_2 B_2 C_2         |
+//! |          ...            |
+//! |             A_N-1 B_N-1 |
+//! ```
+//!
+//! and solved by block Thomas elimination, the scheme of Henyey et al.,
+//! in time and memory linear in the number of blocks.
+
+use rgsl::linear_algebra::{LU_decomp, LU_solve};
+use rgsl::types::matrix::MatrixF64;
+use rgsl::types::permutation::Permutation;
+use rgsl::types::vector::VectorF64;
+
+type Scalar = f64;
+
+/// Block-tridiagonal matrix, each block stored dense and row-major.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockTridiagonal {
+    /// Size of each diagonal block
+    sizes: Vec<usize>,
+    /// Row and column of the first element of each diagonal block
+    offsets: Vec<usize>,
+    /// Block of each row and column
+    blocks: Vec<usize>,
+    /// Blocks A_j below the diagonal, empty for j = 0
+    lower: Vec<Vec<Scalar>>,
+    /// Diagonal blocks B_j
+    diagonal: Vec<Vec<Scalar>>,
+    /// Blocks C_j above the diagonal, empty for the last block
+    upper: Vec<Vec<Scalar>>,
+}
+
+impl BlockTridiagonal {
+    /// Zero matrix with diagonal blocks of `sizes`.
+    pub fn new(sizes: Vec<usize>) -> Self {
+        let n = sizes.len();
+        let mut offsets = Vec::with_capacity(n);
+        let mut blocks = Vec::new();
+        for (j, size) in sizes.iter().enumerate() {
+            offsets.push(blocks.len());
+            blocks.resize(blocks.len() + size, j);
+        }
+        let block = |j: usize, k: Option<usize>| match k {
+            Some(k) if k < n => vec![0.0; sizes[j] * sizes[k]],
+            _ => Vec::new(),
+        };
+        BlockTridiagonal {
+            lower: (0..n).map(|j| block(j, j.checked_sub(1))).collect(),
+            diagonal: (0..n).map(|j| block(j, Some(j))).collect(),
+            upper: (0..n).map(|j| block(j, Some(j + 1))).collect(),
+            sizes,
+            offsets,
+            blocks,
+        }
+    }
+
+    /// Number of rows and columns.
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn set_zero(&mut self) {
+        for block in self
+            .lower
+            .iter_mut()
+            .chain(self.diagonal.iter_mut())
+            .chain(self.upper.iter_mut())
+        {
+            for x in block.iter_mut() {
+                *x = 0.0;
+            }
+        }
+    }
+
+    /// Block holding element (`row`, `col`) and the index into it, if the
+    /// element is inside the band of blocks.
+    fn locate(&self, row: usize, col: usize) -> Option<(&Vec<Scalar>, usize)> {
+        let (j, k) = (self.blocks[row], self.blocks[col]);
+        let block = match k as isize - j as isize {
+            -1 => &self.lower[j],
+            0 => &self.diagonal[j],
+            1 => &self.upper[j],
+            _ => return None,
+        };
+        Some((
+            block,
+            (row - self.offsets[j]) * self.sizes[k] + col - self.offsets[k],
+        ))
+    }
+
+    /// Element (`row`, `col`), zero outside the band of blocks.
+    pub fn get(&self, row: usize, col: usize) -> Scalar {
+        self.locate(row, col).map_or(0.0, |(block, i)| block[i])
+    }
+
+    /// Sets element (`row`, `col`), which must lie inside the band of
+    /// blocks unless `value` is zero.
+    pub fn set(&mut self, row: usize, col: usize, value: Scalar) {
+        let (j, k) = (self.blocks[row], self.blocks[col]);
+        let i = (row - self.offsets[j]) * self.sizes[k] + col - self.offsets[k];
+        match k as isize - j as isize {
+            -1 => self.lower[j][i] = value,
+            0 => self.diagonal[j][i] = value,
+            1 => self.upper[j][i] = value,
+            _ => assert!(value == 0.0, "({}, {}) outside the block band", row, col),
+        }
+    }
+
+    /// Dense copy of the matrix.
+    pub fn to_dense(&self) -> MatrixF64 {
+        let n = self.len();
+        let mut dense = MatrixF64::new(n, n).unwrap();
+        for row in 0..n {
+            let j = self.blocks[row];
+            let first = self.offsets[j.saturating_sub(1)];
+            let last = self.offsets.get(j + 2).copied().unwrap_or(n);
+            for col in first..last {
+                dense.set(row, col, self.get(row, col));
+            }
+        }
+        dense
+    }
+
+    /// Solves M x = b by block Thomas elimination,
+    ///
+    /// ```text
+    /// C'_j = (B_j - A_j C'_j-1)^-1 C_j
+    /// d'_j = (B_j - A_j C'_j-1)^-1 (b_j - A_j d'_j-1)
+    /// x_j = d'_j - C'_j x_j+1
+    /// ```
+    ///
+    /// with partial pivoting inside each diagonal block, or None if one is
+    /// singular, if there are no blocks or if `b` is not of length `len`.
+    pub fn solve(&self, b: &[Scalar]) -> Option<Vec<Scalar>> {
+        let n = self.sizes.len();
+        if n == 0 || b.len() != self.len() {
+            return None;
+        }
+        let mut upper: Vec<Vec<Scalar>> = Vec::with_capacity(n);
+        let mut x: Vec<Scalar> = b.to_vec();
+        for j in 0..n {
+            let (size, offset) = (self.sizes[j], self.offsets[j]);
+            let mut pivot = self.diagonal[j].clone();
+            if j > 0 {
+                let (previous, p) = (self.sizes[j - 1], self.offsets[j - 1]);
+                let a = &self.lower[j];
+                for r in 0..size {
+                    for k in 0..previous {
+                        let a = a[r * previous + k];
+                        if a == 0.0 {
+                            continue;
+                        }
+                        for c in 0..size {
+                            pivot[r * size + c] -= a * upper[j - 1][k * size + c];
+                        }
+                        x[offset + r] -= a * x[p + k];
+                    }
+                }
+            }
+            let permutation = factorize(&mut pivot, size)?;
+            substitute(&pivot, &permutation, &mut x[offset..offset + size], 1);
+            let mut c = self.upper[j].clone();
+            if j + 1 < n {
+                substitute(&pivot, &permutation, &mut c, self.sizes[j + 1]);
+            }
+            upper.push(c);
+        }
+        for j in (0..n - 1).rev() {
+            let (size, next) = (self.sizes[j], self.sizes[j + 1]);
+            let (offset, o) = (self.offsets[j], self.offsets[j + 1]);
+            for r in 0..size {
+                let mut sum = 0.0;
+                for k in 0..next {
+                    sum += upper[j][r * next + k] * x[o + k];
+                }
+                x[offset + r] -= sum;
+            }
+        }
+        Some(x)
+    }
+
+    /// Solves M x = b by dense LU decomposition, for verification, with the
+    /// same failures as `solve`.
+    pub fn solve_dense(&self, b: &[Scalar]) -> Option<Vec<Scalar>> {
+        let n = self.len();
+        if n == 0 || b.len() != n {
+            return None;
+        }
+        let mut lu = self.to_dense();
+        let mut p = Permutation::new(n).unwrap();
+        let mut signum = 0;
+        let mut rhs = VectorF64::new(n).unwrap();
+        for (i, b) in b.iter().enumerate() {
+            rhs.set(i, *b);
+        }
+        let mut x = VectorF64::new(n).unwrap();
+        if LU_decomp(&mut lu, &mut p, &mut signum).is_err()
+            || LU_solve(&lu, &p, &rhs, &mut x).is_err()
+        {
+            return None;
+        }
+        Some((0..n).map(|i| x.get(i)).collect())
+    }
+}
+
+/// LU factorization in place of the `n` x `n` row-major matrix `a` with
+/// partial pivoting, returning the row chosen at each step, or None if `a`
+/// is singular.
+fn factorize(a: &mut [Scalar], n: usize) -> Option<Vec<usize>> {
+    let mut permutation = Vec::with_capacity(n);
+    for k in 0..n {
+        let p = (k..n)
+            .max_by(|&i, &j| a[i * n + k].abs().total_cmp(&a[j * n + k].abs()))
+            .unwrap();
+        if a[p * n + k] == 0.0 || !a[p * n + k].is_finite() {
+            return None;
+        }
+        if p != k {
+            for c in 0..n {
+                a.swap(k * n + c, p * n + c);
+            }
+        }
+        permutation.push(p);
+        for i in k + 1..n {
+            let factor = a[i * n + k] / a[k * n + k];
+            a[i * n + k] = factor;
+            for c in k + 1..n {
+                a[i * n + c] -= factor * a[k * n + c];
+            }
+        }
+    }
+    Some(permutation)
+}
+
+/// Overwrites the `n` x `m` row-major `b` with a^-1 b, given the
+/// factorization of `factorize`.
+fn substitute(a: &[Scalar], permutation: &[usize], b: &mut [Scalar], m: usize) {
+    let n = permutation.len();
+    for (k, &p) in permutation.iter().enumerate() {
+        if p != k {
+            for c in 0..m {
+                b.swap(k * m + c, p * m + c);
+            }
+        }
+    }
+    for i in 0..n {
+        for k in 0..i {
+            for c in 0..m {
+                b[i * m + c] -= a[i * n + k] * b[k * m + c];
+            }
+        }
+    }
+    for i in (0..n).rev() {
+        for k in i + 1..n {
+            for c in 0..m {
+                b[i * m + c] -= a[i * n + k] * b[k * m + c];
+            }
+        }
+        for c in 0..m {
+            b[i * m + c] /= a[i * n + i];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solve() {
+        let sizes = vec![2, 4, 4, 3, 4];
+        let mut m = BlockTridiagonal::new(sizes);
+        let n = m.len();
+        assert_eq!(n, 17);
+        // Pseudo-random band, diagonally weighted
+        let mut seed = 12345u64;
+        let mut random = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (seed >> 11) as Scalar / (1u64 << 53) as Scalar - 0.5
+        };
+        for row in 0..n {
+            for col in 0..n {
+                if m.locate(row, col).is_some() {
+                    let diagonal = if row == col { 3.0 } else { 0.0 };
+                    m.set(row, col, random() + diagonal);
+                }
+            }
+        }
+        assert_eq!(m.get(0, 16), 0.0);
+        let b: Vec<Scalar> = (0..n).map(|i| i as Scalar - 4.0).collect();
+        let x = m.solve(&b).unwrap();
+        let dense = m.solve_dense(&b).unwrap();
+        for i in 0..n {
+            assert!((x[i] - dense[i]).abs() < 1e-12 * (1.0 + dense[i].abs()));
+        }
+        // Residual of the block solution
+        for (row, b) in b.iter().enumerate() {
+            let mx: Scalar = (0..n).map(|col| m.get(row, col) * x[col]).sum();
+            assert!((mx - b).abs() < 1e-12);
+        }
+        assert!(BlockTridiagonal::new(vec![2, 2]).solve(&[1.0; 4]).is_none());
+        // Mismatched right-hand sides and empty matrices have no solution.
+        assert!(m.solve(&b[1..]).is_none() && m.solve_dense(&b[1..]).is_none());
+        let empty = BlockTridiagonal::new(Vec::new());
+        assert!(empty.is_empty() && empty.solve(&[]).is_none() && empty.solve_dense(&[]).is_none());
+    }
+}
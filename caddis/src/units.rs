@@ -9,7 +9,62 @@ const NUM_DIM: usize = 7usize;
 const _DIMENSION_SYMBOLS: [&str; NUM_DIM] = ["L", "M", "T", "I", "Θ", "N", "J"];
 const UNIT_SYMBOLS: [&str; NUM_DIM] = ["m", "kg", "s", "A", "K", "mol", "cd"];
 
-type DimensionalExponent = Ratio<i8>;
+/// CODATA 2018 Newtonian constant of gravitation (m^3 kg^-1 s^-2)
+pub const NEWTONIAN_CONSTANT_OF_GRAVITATION: f64 = 6.674_30e-11;
+/// CODATA 2018 molar gas constant (J mol^-1 K^-1)
+pub const MOLAR_GAS_CONSTANT: f64 = 8.314_462_618;
+/// Molar mass constant (kg mol^-1)
+pub const MOLAR_MASS_CONSTANT: f64 = 1e-3;
+/// Radiation density constant a = 4 sigma/c (J m^-3 K^-4)
+pub const RADIATION_DENSITY_CONSTANT: f64 = 7.565_723e-16;
+/// Speed of light in vacuum (m s^-1)
+pub const SPEED_OF_LIGHT: f64 = 299_792_458.0;
+/// CODATA 2018 Stefan-Boltzmann constant (W m^-2 K^-4)
+pub const STEFAN_BOLTZMANN_CONSTANT: f64 = 5.670_374_419e-8;
+/// Nominal solar mass, IAU 2015 G M_sun over CODATA 2018 G (kg)
+pub const SOLAR_MASS: f64 = 1.988_47e30;
+/// IAU 2015 nominal solar radius (m)
+pub const SOLAR_RADIUS: f64 = 6.957e8;
+/// IAU 2015 nominal solar luminosity (W)
+pub const SOLAR_LUMINOSITY: f64 = 3.828e26;
+
+pub type DimensionalExponent = Ratio<i8>;
+pub type Dimension = [DimensionalExponent; NUM_DIM];
+
+/// A quantity given where one of another dimension was expected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DimensionError {
+    pub expected: Dimension,
+    pub found: Dimension,
+}
+
+/// Product of the SI base units raised to `dimension`, such as "kg m^(-3)".
+fn unit_symbol(dimension: &Dimension) -> String {
+    let mut s = Vec::new();
+    for (symbol, exponent) in UNIT_SYMBOLS.iter().zip(dimension.iter()) {
+        if exponent.is_one() {
+            s.push(symbol.to_string());
+        } else if !exponent.is_zero() {
+            s.push(format!("{}^({})", symbol, exponent));
+        }
+    }
+    if s.is_empty() {
+        "1".to_string()
+    } else {
+        s.join(" ")
+    }
+}
+
+impl fmt::Display for DimensionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "expected a quantity in {}, found one in {}",
+            unit_symbol(&self.expected),
+            unit_symbol(&self.found)
+        )
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct QuantityValue {
@@ -135,8 +190,8 @@ macro_rules! base_unit(
 impl QuantityValue {
     base_unit!(m, kg, s, A, K, mol, cd; 0, 1, 2, 3, 4, 5, 6);
 
-    #[allow(dead_code, non_snake_case)]
-    fn W() -> QuantityValue {
+    #[allow(non_snake_case)]
+    pub fn W() -> QuantityValue {
         let mut q = QuantityValue::default();
         q.number = f64::one();
         q.dimension[0] = DimensionalExponent::one() * 2;
@@ -145,6 +200,38 @@ impl QuantityValue {
         q
     }
 
+    #[allow(non_snake_case)]
+    pub fn Pa() -> QuantityValue {
+        QuantityValue::kg() / (QuantityValue::m() * QuantityValue::s().pow(2.0))
+    }
+
+    /// `SOLAR_MASS` in kilograms
+    pub fn solar_mass() -> QuantityValue {
+        QuantityValue::kg() * SOLAR_MASS
+    }
+
+    /// `SOLAR_RADIUS` in metres
+    pub fn solar_radius() -> QuantityValue {
+        QuantityValue::m() * SOLAR_RADIUS
+    }
+
+    /// `SOLAR_LUMINOSITY` in watts
+    pub fn solar_luminosity() -> QuantityValue {
+        QuantityValue::W() * SOLAR_LUMINOSITY
+    }
+
+    /// Number of `unit` in the quantity, or an error if their dimensions
+    /// differ.
+    pub fn value_in(&self, unit: QuantityValue) -> Result<f64, DimensionError> {
+        if self.dimension != unit.dimension {
+            return Err(DimensionError {
+                expected: unit.dimension,
+                found: self.dimension,
+            });
+        }
+        Ok(self.number / unit.number)
+    }
+
     #[allow(dead_code)]
     fn recip(&self) -> Self {
         let q = self.clone();
@@ -182,4 +269,19 @@ mod tests {
         f.dimension[1] = DimensionalExponent::one() * -1;
         assert_eq!(r.pow(-2.0) * rho.recip(), f);
     }
+
+    #[test]
+    fn test_value_in() {
+        let mass = QuantityValue::solar_mass() * 2.0;
+        assert_eq!(mass.value_in(QuantityValue::kg()), Ok(2.0 * 1.988_47e30));
+        assert_eq!(mass.value_in(QuantityValue::solar_mass()), Ok(2.0));
+        let error = mass.value_in(QuantityValue::W()).unwrap_err();
+        assert_eq!(error.found, QuantityValue::kg().dimension);
+        assert_eq!(
+            error.to_string(),
+            "expected a quantity in m^(2) kg s^(-3), found one in kg"
+        );
+        let pressure = QuantityValue::kg() * 1e5 / QuantityValue::m() / QuantityValue::s().pow(2.0);
+        assert_eq!(pressure.value_in(QuantityValue::Pa()), Ok(1e5));
+    }
 }
@@ -0,0 +1,88 @@
+/* Loads a scene written by caddis::scene::Scene::to_json and renders it with
+ * three.js. See caddis/src/scene.rs for the schema.
+ */
+
+var materialColors = {
+    steel: 0x8a8d8f,
+    aluminium: 0xc0c4c8,
+    glass: 0x88ccee,
+    default: 0xb58900
+};
+
+function loadScene(url, onLoad) {
+    let request = new XMLHttpRequest();
+    request.overrideMimeType("application/json");
+    request.open("GET", url, true);
+    request.onload = function () {
+        if (request.status === 200 || request.status === 0) {
+            onLoad(buildScene(JSON.parse(request.responseText)));
+        } else {
+            console.log("Could not load scene", url, request.status);
+        }
+    };
+    request.send();
+}
+
+function buildScene(json) {
+    let scene = new THREE.Scene();
+    for (let i = 0; i < json.nodes.length; i++) {
+        scene.add(buildNode(json.nodes[i]));
+    }
+    return scene;
+}
+
+function buildNode(node) {
+    let group = new THREE.Group();
+    group.name = node.name;
+    group.position.fromArray(node.translation);
+    group.quaternion.fromArray(node.rotation);
+    group.scale.fromArray(node.scale);
+    let mesh = new THREE.Mesh(buildGeometry(node.primitive), buildMaterial(node.material));
+    mesh.position.fromArray(node.primitive.center);
+    if (node.primitive.type === "cylinder") {
+        let axis = new THREE.Vector3().fromArray(node.primitive.axis).normalize();
+        mesh.quaternion.setFromUnitVectors(new THREE.Vector3(0, 1, 0), axis);
+    }
+    group.add(mesh);
+    return group;
+}
+
+function buildGeometry(primitive) {
+    switch (primitive.type) {
+        case "sphere":
+            return new THREE.SphereBufferGeometry(primitive.radius, 32, 16);
+        case "cylinder":
+            return new THREE.CylinderBufferGeometry(
+                primitive.radius, primitive.radius, primitive.length, 32);
+        case "cuboid":
+            return new THREE.BoxBufferGeometry(
+                primitive.size[0], primitive.size[1], primitive.size[2]);
+        default:
+            console.log("Unknown primitive", primitive.type);
+            return new THREE.Geometry();
+    }
+}
+
+function buildMaterial(tag) {
+    let color = materialColors.hasOwnProperty(tag) ? materialColors[tag] : materialColors.default;
+    return new THREE.MeshLambertMaterial({color: color});
+}
+
+function renderScene(scene, container) {
+    let width = container.clientWidth;
+    let height = container.clientHeight;
+    let camera = new THREE.PerspectiveCamera(45, width / height, 0.1, 10000);
+    let box = new THREE.Box3().setFromObject(scene);
+    let center = box.getCenter();
+    let size = box.getSize().length() || 1;
+    camera.position.set(center.x + size, center.y + size, center.z + size);
+    camera.lookAt(center);
+    scene.add(new THREE.AmbientLight(0x404040));
+    let light = new THREE.DirectionalLight(0xffffff, 0.8);
+    light.position.set(1, 2, 3);
+    scene.add(light);
+    let renderer = new THREE.WebGLRenderer({antialias: true});
+    renderer.setSize(width, height);
+    container.append(renderer.domElement);
+    renderer.render(scene, camera);
+}